@@ -0,0 +1,574 @@
+mod command_palette;
+
+pub use command_palette::CommandPalette;
+
+use command_palette::command_palette_system;
+use ActionScope::*;
+
+use crate::{Config, CurrentTool, EditorState};
+
+use bevy::{
+    app::prelude::*,
+    ecs::{prelude::*, system::SystemParam},
+    input::{prelude::*, InputSystem},
+};
+
+/// Everything the editor can do on request. Key bindings, the command palette and scripts all
+/// request actions from the `ActionQueue`, and every action that can run is sent as an event to
+/// its handler: the tool switcher, `undo_system`, `save_map_to_db`, the command palette, or the
+/// input map of the tool in its scope. Input-only actions are read with `ActionInput` instead.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    SwitchToDragFace,
+    SwitchToTerraform,
+    OpenHistoryBrowser,
+    OpenVersionBrowser,
+    OpenCommandPalette,
+    Undo,
+    Redo,
+    NextUndoBranch,
+    PruneHistory,
+    Save,
+    TerraformAdd,
+    TerraformRemove,
+    GrowBrush,
+    ShrinkBrush,
+    VoxelType1,
+    VoxelType2,
+    VoxelType3,
+    VoxelType4,
+    SelectCorner,
+    DragFace,
+    CancelDrag,
+    SelectPrevious,
+    SelectNext,
+    Confirm,
+    Cancel,
+    DeleteChar,
+    Label,
+    Commit,
+    Branch,
+    MarkVersion,
+    DiffVersion,
+    MergeVersion,
+    MarkRegionStart,
+    MarkRegionEnd,
+    ResolveOurs,
+    ResolveTheirs,
+    ResolveAllOurs,
+    ResolveAllTheirs,
+}
+
+/// When an action does anything. Tool actions only work while their tool is active, so they can
+/// share chords with other tools.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ActionScope {
+    Global,
+    Terraform,
+    /// The history or the version browser.
+    Browser,
+    HistoryBrowser,
+    VersionBrowser,
+    /// Only makes sense as a held key or button, or while typing, so it can't be run on its own.
+    InputOnly,
+}
+
+impl ActionScope {
+    pub fn is_available(self, current_tool: &CurrentTool) -> bool {
+        match self {
+            ActionScope::Global => true,
+            ActionScope::Terraform => matches!(current_tool, CurrentTool::Terraform),
+            ActionScope::Browser => matches!(
+                current_tool,
+                CurrentTool::HistoryBrowser(_) | CurrentTool::VersionBrowser(_)
+            ),
+            ActionScope::HistoryBrowser => matches!(current_tool, CurrentTool::HistoryBrowser(_)),
+            ActionScope::VersionBrowser => matches!(current_tool, CurrentTool::VersionBrowser(_)),
+            ActionScope::InputOnly => false,
+        }
+    }
+}
+
+pub struct ActionInfo {
+    pub action: Action,
+    /// Used in the `bindings` table of the config, and to request the action by name.
+    pub name: &'static str,
+    pub description: &'static str,
+    pub scope: ActionScope,
+    pub default_chords: &'static [&'static str],
+}
+
+const fn info(
+    action: Action,
+    name: &'static str,
+    scope: ActionScope,
+    default_chords: &'static [&'static str],
+    description: &'static str,
+) -> ActionInfo {
+    ActionInfo {
+        action,
+        name,
+        description,
+        scope,
+        default_chords,
+    }
+}
+
+/// The registry of every action. There's no clipboard yet, so there's no copy or paste.
+const ACTIONS: &[ActionInfo] = &[
+    info(
+        Action::SwitchToDragFace,
+        "switch_to_drag_face",
+        Global,
+        &["D"],
+        "Switch to the face dragging tool",
+    ),
+    info(
+        Action::SwitchToTerraform,
+        "switch_to_terraform",
+        Global,
+        &["T"],
+        "Switch to the terraforming tool",
+    ),
+    info(
+        Action::OpenHistoryBrowser,
+        "open_history_browser",
+        Global,
+        &["H"],
+        "Browse and label the edit history",
+    ),
+    info(
+        Action::OpenVersionBrowser,
+        "open_version_browser",
+        Global,
+        &["V"],
+        "Browse, commit, branch and merge world versions",
+    ),
+    info(
+        Action::OpenCommandPalette,
+        "open_command_palette",
+        Global,
+        &["Ctrl+Shift+P", "F1"],
+        "Search for an action and run it",
+    ),
+    info(Action::Undo, "undo", Global, &["U"], "Undo the last edit"),
+    info(
+        Action::Redo,
+        "redo",
+        Global,
+        &["R"],
+        "Redo the last undone edit",
+    ),
+    info(
+        Action::NextUndoBranch,
+        "next_undo_branch",
+        Global,
+        &["B"],
+        "Switch to the next branch of the undo history",
+    ),
+    info(
+        Action::PruneHistory,
+        "prune_history",
+        Global,
+        &["P"],
+        "Prune the undo history down to undo_history_limit edits",
+    ),
+    info(
+        Action::Save,
+        "save",
+        Global,
+        &["S"],
+        "Save the map and the undo history",
+    ),
+    info(
+        Action::TerraformAdd,
+        "terraform_add",
+        InputOnly,
+        &["Z"],
+        "Add terrain under the cursor while held",
+    ),
+    info(
+        Action::TerraformRemove,
+        "terraform_remove",
+        InputOnly,
+        &["X"],
+        "Remove terrain under the cursor while held",
+    ),
+    info(
+        Action::GrowBrush,
+        "grow_brush",
+        Terraform,
+        &["Up"],
+        "Increase the terraforming radius",
+    ),
+    info(
+        Action::ShrinkBrush,
+        "shrink_brush",
+        Terraform,
+        &["Down"],
+        "Decrease the terraforming radius",
+    ),
+    info(
+        Action::VoxelType1,
+        "voxel_type_1",
+        Terraform,
+        &["1"],
+        "Terraform with voxel type 1",
+    ),
+    info(
+        Action::VoxelType2,
+        "voxel_type_2",
+        Terraform,
+        &["2"],
+        "Terraform with voxel type 2",
+    ),
+    info(
+        Action::VoxelType3,
+        "voxel_type_3",
+        Terraform,
+        &["3"],
+        "Terraform with voxel type 3",
+    ),
+    info(
+        Action::VoxelType4,
+        "voxel_type_4",
+        Terraform,
+        &["4"],
+        "Terraform with voxel type 4",
+    ),
+    info(
+        Action::SelectCorner,
+        "select_corner",
+        InputOnly,
+        &["MouseLeft"],
+        "Select a corner of the face to drag",
+    ),
+    info(
+        Action::DragFace,
+        "drag_face",
+        InputOnly,
+        &["MouseLeft"],
+        "Drag the selected face while held",
+    ),
+    info(
+        Action::CancelDrag,
+        "cancel_drag",
+        InputOnly,
+        &["Escape", "MouseRight"],
+        "Cancel the drag and revert it",
+    ),
+    info(
+        Action::SelectPrevious,
+        "select_previous",
+        Browser,
+        &["Up"],
+        "Select the previous entry",
+    ),
+    info(
+        Action::SelectNext,
+        "select_next",
+        Browser,
+        &["Down"],
+        "Select the next entry",
+    ),
+    info(
+        Action::Confirm,
+        "confirm",
+        Browser,
+        &["Return"],
+        "Jump to or check out the selected entry",
+    ),
+    info(
+        Action::Cancel,
+        "cancel",
+        Browser,
+        &["Escape"],
+        "Stop typing, or clear the version diff",
+    ),
+    info(
+        Action::DeleteChar,
+        "delete_char",
+        InputOnly,
+        &["Back"],
+        "Delete the last typed character",
+    ),
+    info(
+        Action::Label,
+        "label",
+        HistoryBrowser,
+        &["L"],
+        "Label the current state of the edit history",
+    ),
+    info(
+        Action::Commit,
+        "commit",
+        VersionBrowser,
+        &["C"],
+        "Commit the current version",
+    ),
+    info(
+        Action::Branch,
+        "branch",
+        VersionBrowser,
+        &["N"],
+        "Start a new branch from the current version",
+    ),
+    info(
+        Action::MarkVersion,
+        "mark_version",
+        VersionBrowser,
+        &["M"],
+        "Mark the selected version to diff against",
+    ),
+    info(
+        Action::DiffVersion,
+        "diff_version",
+        VersionBrowser,
+        &["F"],
+        "Highlight the chunks that differ from the marked version",
+    ),
+    info(
+        Action::MergeVersion,
+        "merge_version",
+        VersionBrowser,
+        &["G"],
+        "Merge the selected version, or finish the merge",
+    ),
+    info(
+        Action::MarkRegionStart,
+        "mark_region_start",
+        VersionBrowser,
+        &["LBracket"],
+        "Mark the first corner of a region to resolve",
+    ),
+    info(
+        Action::MarkRegionEnd,
+        "mark_region_end",
+        VersionBrowser,
+        &["RBracket"],
+        "Mark the second corner of a region to resolve",
+    ),
+    info(
+        Action::ResolveOurs,
+        "resolve_ours",
+        VersionBrowser,
+        &["1"],
+        "Resolve the conflict under the cursor with the current version",
+    ),
+    info(
+        Action::ResolveTheirs,
+        "resolve_theirs",
+        VersionBrowser,
+        &["2"],
+        "Resolve the conflict under the cursor with the selected version",
+    ),
+    info(
+        Action::ResolveAllOurs,
+        "resolve_all_ours",
+        VersionBrowser,
+        &["Shift+1"],
+        "Resolve every conflict with the current version",
+    ),
+    info(
+        Action::ResolveAllTheirs,
+        "resolve_all_theirs",
+        VersionBrowser,
+        &["Shift+2"],
+        "Resolve every conflict with the selected version",
+    ),
+];
+
+impl Action {
+    pub fn all() -> impl Iterator<Item = Action> {
+        ACTIONS.iter().map(|info| info.action)
+    }
+
+    pub fn info(self) -> &'static ActionInfo {
+        ACTIONS.iter().find(|info| info.action == self).unwrap()
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    pub fn description(self) -> &'static str {
+        self.info().description
+    }
+
+    pub fn scope(self) -> ActionScope {
+        self.info().scope
+    }
+
+    pub fn default_chords(self) -> &'static [&'static str] {
+        self.info().default_chords
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|info| info.name == name)
+            .map(|info| info.action)
+    }
+}
+
+/// Actions to run on the next dispatch, whether their chords were pressed or the command palette or
+/// a script requested them.
+#[derive(Default)]
+pub struct ActionQueue {
+    pending: Vec<Action>,
+}
+
+impl ActionQueue {
+    pub fn request(&mut self, action: Action) {
+        self.pending.push(action);
+    }
+
+    /// For scripts, which only know the names.
+    pub fn request_by_name(&mut self, name: &str) -> Result<(), String> {
+        let action = Action::from_name(name).ok_or_else(|| format!("unknown action {:?}", name))?;
+        self.request(action);
+
+        Ok(())
+    }
+}
+
+/// Whether `action` can run with `current_tool`. While the user is typing, only confirming and
+/// cancelling the input can.
+fn can_run(action: Action, current_tool: &CurrentTool) -> bool {
+    action.scope().is_available(current_tool)
+        && (!current_tool.is_typing() || matches!(action, Action::Confirm | Action::Cancel))
+}
+
+/// Requests the actions whose chords were just pressed, unless the command palette has the
+/// keyboard, and sends every requested action that can run to its handler.
+fn dispatch_actions(
+    mut queue: ResMut<ActionQueue>,
+    mut actions: EventWriter<Action>,
+    config: Res<Config>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    palette: Res<CommandPalette>,
+    current_tool: Res<CurrentTool>,
+) {
+    if !palette.has_keyboard() {
+        for action in Action::all() {
+            // Chords are shared between tools, so only the ones that can run count.
+            if can_run(action, &current_tool) && config.bindings.just_pressed(action, &keys, &mouse)
+            {
+                queue.request(action);
+            }
+        }
+    }
+
+    for action in std::mem::take(&mut queue.pending) {
+        if can_run(action, &current_tool) {
+            log::info!("Running {}", action.name());
+            actions.send(action);
+        } else {
+            log::warn!("{} can't run with the current tool", action.name());
+        }
+    }
+}
+
+/// Checks the keys and buttons of the input-only actions, which are held down or only make sense
+/// while typing, so they're never dispatched. They're ignored while the command palette has the
+/// keyboard.
+#[derive(SystemParam)]
+pub struct ActionInput<'a> {
+    config: Res<'a, Config>,
+    keys: Res<'a, Input<KeyCode>>,
+    mouse: Res<'a, Input<MouseButton>>,
+    palette: Res<'a, CommandPalette>,
+}
+
+impl<'a> ActionInput<'a> {
+    pub fn just_pressed(&self, action: Action) -> bool {
+        !self.palette.has_keyboard()
+            && self
+                .config
+                .bindings
+                .just_pressed(action, &self.keys, &self.mouse)
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        !self.palette.has_keyboard()
+            && self
+                .config
+                .bindings
+                .pressed(action, &self.keys, &self.mouse)
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        self.config
+            .bindings
+            .just_released(action, &self.keys, &self.mouse)
+    }
+
+    pub fn mouse_buttons(&self, action: Action) -> Vec<MouseButton> {
+        if self.palette.has_keyboard() {
+            return Vec::new();
+        }
+
+        self.config.bindings.mouse_buttons(action, &self.keys)
+    }
+}
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ActionQueue>()
+            .init_resource::<CommandPalette>()
+            .add_event::<Action>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                dispatch_actions.system().after(InputSystem),
+            )
+            .add_system_set(
+                SystemSet::on_update(EditorState::Editing)
+                    .with_system(command_palette_system.system()),
+            );
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::KeyBindings;
+
+    #[test]
+    fn every_action_has_a_unique_name_and_valid_defaults() {
+        let bindings = KeyBindings::default();
+        for action in Action::all() {
+            assert_eq!(Action::from_name(action.name()), Some(action));
+            assert!(!action.description().is_empty());
+            assert!(!bindings.chords(action).is_empty());
+        }
+
+        let mut queue = ActionQueue::default();
+        queue.request_by_name("undo").unwrap();
+        assert!(queue.request_by_name("fly").is_err());
+        assert_eq!(queue.pending, vec![Action::Undo]);
+    }
+
+    #[test]
+    fn tool_actions_are_only_available_with_their_tool() {
+        let terraform = CurrentTool::Terraform;
+        assert!(Action::Undo.scope().is_available(&terraform));
+        assert!(Action::GrowBrush.scope().is_available(&terraform));
+        assert!(!Action::Commit.scope().is_available(&terraform));
+        assert!(!Action::TerraformAdd.scope().is_available(&terraform));
+
+        assert!(can_run(Action::GrowBrush, &terraform));
+        assert!(!can_run(Action::SelectPrevious, &terraform));
+        assert!(!can_run(Action::DeleteChar, &terraform));
+    }
+}
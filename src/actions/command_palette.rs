@@ -0,0 +1,308 @@
+use super::{Action, ActionInfo, ActionQueue};
+
+use crate::{Config, CurrentTool};
+
+use bevy::{
+    ecs::prelude::*,
+    input::prelude::*,
+    window::{ReceivedCharacter, Windows},
+};
+
+/// Searches the actions that are available with the current tool, and runs the selected one. The
+/// query, the matches and the selected action are shown in the window title, and the matches are
+/// also logged with their descriptions.
+#[derive(Default)]
+pub struct CommandPalette {
+    open: bool,
+    /// Set on the frame the palette closes, so the key that closed it doesn't reach the tools.
+    just_closed: bool,
+    query: String,
+    available: Vec<Action>,
+    matches: Vec<Action>,
+    /// Index into `matches`.
+    selected: usize,
+}
+
+/// How many matches are logged.
+const MAX_LISTED_MATCHES: usize = 10;
+/// How many matches fit in the window title.
+const MAX_SHOWN_MATCHES: usize = 6;
+
+impl CommandPalette {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// True if key bindings should be ignored by everything else.
+    pub fn has_keyboard(&self) -> bool {
+        self.open || self.just_closed
+    }
+
+    pub fn selected_action(&self) -> Option<Action> {
+        self.matches.get(self.selected).copied()
+    }
+
+    /// The matches shown in the window title, which always include the selected one.
+    fn shown_matches(&self) -> &[Action] {
+        let start = (self.selected + 1).saturating_sub(MAX_SHOWN_MATCHES);
+        let end = (start + MAX_SHOWN_MATCHES).min(self.matches.len());
+
+        &self.matches[start..end]
+    }
+
+    fn open(&mut self, current_tool: &CurrentTool) {
+        self.open = true;
+        self.query.clear();
+        self.available = Action::all()
+            .filter(|action| action.scope().is_available(current_tool))
+            .collect();
+        self.update_matches();
+    }
+
+    fn close(&mut self) {
+        self.open = false;
+        self.just_closed = true;
+    }
+
+    fn move_selection(&mut self, delta: i32) {
+        let last = self.matches.len().max(1) as i32 - 1;
+        self.selected = (self.selected as i32 + delta).max(0).min(last) as usize;
+    }
+
+    fn update_matches(&mut self) {
+        let mut scored: Vec<(u32, Action)> = self
+            .available
+            .iter()
+            .filter_map(|&action| match_score(&self.query, action.info()).map(|s| (s, action)))
+            .collect();
+        // Stable, so equally good matches stay in registry order.
+        scored.sort_by_key(|(score, _)| *score);
+        self.matches = scored.into_iter().map(|(_, action)| action).collect();
+        self.selected = 0;
+    }
+}
+
+/// Lower is better, and `None` means the action doesn't match at all. Names match before
+/// descriptions, and a query can also skip letters, like "nub" for "next_undo_branch".
+fn match_score(query: &str, info: &ActionInfo) -> Option<u32> {
+    let query = query.trim().to_lowercase().replace(' ', "_");
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let description = info.description.to_lowercase();
+    if info.name.starts_with(&query) {
+        Some(0)
+    } else if info.name.contains(&query) {
+        Some(1)
+    } else if description.contains(&query.replace('_', " ")) {
+        Some(2)
+    } else if is_subsequence(&query, info.name) {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+pub fn command_palette_system(
+    mut palette: ResMut<CommandPalette>,
+    mut queue: ResMut<ActionQueue>,
+    mut actions: EventReader<Action>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut windows: ResMut<Windows>,
+    config: Res<Config>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    current_tool: Res<CurrentTool>,
+) {
+    // Always drain the characters, so the chord that opens the palette isn't typed into the query.
+    let typed: Vec<char> = characters.iter().map(|c| c.char).collect();
+
+    palette.just_closed = false;
+
+    let open_requested = actions
+        .iter()
+        .any(|action| *action == Action::OpenCommandPalette);
+    // The palette reads its own keys directly, since nothing is dispatched while it's open.
+    let just_pressed = |action| config.bindings.just_pressed(action, &keys, &mouse);
+
+    if !palette.is_open() {
+        if open_requested {
+            palette.open(&current_tool);
+            log_matches(&palette, &config);
+            show_in_title(&palette, &config, &mut windows);
+        }
+        return;
+    }
+
+    if just_pressed(Action::Cancel) {
+        palette.close();
+    } else if just_pressed(Action::Confirm) {
+        if let Some(action) = palette.selected_action() {
+            queue.request(action);
+        }
+        palette.close();
+    } else if just_pressed(Action::SelectPrevious) {
+        palette.move_selection(-1);
+    } else if just_pressed(Action::SelectNext) {
+        palette.move_selection(1);
+    } else if just_pressed(Action::DeleteChar) {
+        if palette.query.pop().is_some() {
+            palette.update_matches();
+            log_matches(&palette, &config);
+        }
+    } else {
+        let typed: String = typed.into_iter().filter(|c| !c.is_control()).collect();
+        if !typed.is_empty() {
+            palette.query.push_str(&typed);
+            palette.update_matches();
+            log_matches(&palette, &config);
+        }
+    }
+
+    show_in_title(&palette, &config, &mut windows);
+}
+
+fn describe(action: Action, config: &Config) -> String {
+    format!(
+        "{} ({}): {}",
+        action.name(),
+        config.bindings.chord_names(action),
+        action.description()
+    )
+}
+
+fn log_matches(palette: &CommandPalette, config: &Config) {
+    log::info!(
+        "Command palette: {:?} matches {} actions",
+        palette.query,
+        palette.matches.len()
+    );
+    for action in palette.matches.iter().take(MAX_LISTED_MATCHES) {
+        log::info!("  {}", describe(*action, config));
+    }
+}
+
+fn show_in_title(palette: &CommandPalette, config: &Config, windows: &mut Windows) {
+    let window = if let Some(window) = windows.get_primary_mut() {
+        window
+    } else {
+        return;
+    };
+
+    if !palette.is_open() {
+        window.set_title(config.window.title.clone());
+        return;
+    }
+
+    let selected_action = palette.selected_action();
+    let selected = selected_action
+        .map(|action| describe(action, config))
+        .unwrap_or_else(|| "no matching actions".to_string());
+    let shown: Vec<String> = palette
+        .shown_matches()
+        .iter()
+        .map(|&action| {
+            if Some(action) == selected_action {
+                format!("[{}]", action.name())
+            } else {
+                action.name().to_string()
+            }
+        })
+        .collect();
+    let more = palette.matches.len() - shown.len();
+    let more = if more > 0 {
+        format!(" +{}", more)
+    } else {
+        String::new()
+    };
+    window.set_title(format!(
+        "{} - > {}_ | {}{} | {}",
+        config.window.title,
+        palette.query,
+        shown.join(" "),
+        more,
+        selected
+    ));
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_match_before_descriptions_and_abbreviations() {
+        let score = |query, action: Action| match_score(query, action.info());
+
+        assert_eq!(score("", Action::Save), Some(0));
+        assert_eq!(score("und", Action::Undo), Some(0));
+        assert_eq!(score("Undo", Action::NextUndoBranch), Some(1));
+        assert_eq!(score("undo branch", Action::NextUndoBranch), Some(1));
+        assert_eq!(score("radius", Action::GrowBrush), Some(2));
+        assert_eq!(score("nub", Action::NextUndoBranch), Some(3));
+        assert_eq!(score("paste", Action::Undo), None);
+    }
+
+    #[test]
+    fn matches_are_sorted_and_limited_to_the_current_tool() {
+        let mut palette = CommandPalette::default();
+        palette.open(&CurrentTool::Terraform);
+        assert!(palette.matches.contains(&Action::GrowBrush));
+        assert!(!palette.matches.contains(&Action::Commit));
+        assert!(!palette.matches.contains(&Action::TerraformAdd));
+
+        palette.query = "undo".to_string();
+        palette.update_matches();
+        assert_eq!(
+            palette.matches,
+            vec![
+                Action::Undo,
+                Action::NextUndoBranch,
+                Action::Redo,
+                Action::PruneHistory
+            ]
+        );
+
+        palette.move_selection(5);
+        assert_eq!(palette.selected_action(), Some(Action::PruneHistory));
+        assert_eq!(palette.shown_matches(), &palette.matches[..]);
+        palette.move_selection(-5);
+        assert_eq!(palette.selected_action(), Some(Action::Undo));
+
+        palette.close();
+        assert!(!palette.is_open());
+        assert!(palette.has_keyboard());
+    }
+
+    #[test]
+    fn the_selected_match_is_always_shown() {
+        let mut palette = CommandPalette::default();
+        palette.open(&CurrentTool::Terraform);
+        assert!(palette.matches.len() > MAX_SHOWN_MATCHES);
+        assert_eq!(
+            palette.shown_matches(),
+            &palette.matches[..MAX_SHOWN_MATCHES]
+        );
+
+        palette.move_selection(MAX_SHOWN_MATCHES as i32);
+        let shown = palette.shown_matches();
+        assert_eq!(shown.len(), MAX_SHOWN_MATCHES);
+        assert_eq!(shown.last().copied(), palette.selected_action());
+
+        palette.query = "nothing matches this".to_string();
+        palette.update_matches();
+        assert!(palette.shown_matches().is_empty());
+    }
+}
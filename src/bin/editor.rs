@@ -1,17 +1,41 @@
-use feldspar_editor::{Config, EditorPlugin};
+use feldspar_editor::{Config, EditorArgs, EditorPlugin, EDITOR_USAGE};
 
 use bevy::app::prelude::*;
 
-fn main() -> Result<(), ron::Error> {
-    env_logger::Builder::from_default_env()
+fn main() {
+    let args = match EditorArgs::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, EDITOR_USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", EDITOR_USAGE);
+        return;
+    }
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(level) = args.log_level {
+        logger.filter_level(level);
+    }
+    logger
         // Filter out some noisy crates
         .filter(Some("gfx_backend_metal"), log::LevelFilter::Error)
         .filter(Some("naga"), log::LevelFilter::Error)
         .init();
 
-    let config = Config::read_file("config.ron")?;
-
-    App::build().add_plugin(EditorPlugin::new(config)).run();
+    let mut config = match Config::read_file(&args.config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            // There's no sensible world to open without a config.
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    args.apply(&mut config);
 
-    Ok(())
+    App::build()
+        .add_plugin(EditorPlugin::new(config).watch_config(args))
+        .run();
 }
@@ -0,0 +1,284 @@
+//! Inspects and maintains a world database without opening the editor, e.g. to validate worlds in
+//! CI. The editor must not have the database open at the same time.
+
+use feldspar_editor::{
+    benchmark_storage, collect_garbage, compact_world_db, export_world_archive,
+    import_world_archive, ChunkCodec, Config, EditorResult, RetentionPolicy, StorageConfig,
+    WorldDb,
+};
+
+use std::path::Path;
+
+const USAGE: &str = "\
+Usage: world_db <DATABASE_PATH> <COMMAND>
+
+Commands:
+    versions          List the version graph
+    stats [VERSION]   Count the chunks and their compressed size per LOD (default: current version)
+    check             Decompress every chunk of every version, and exit with 1 if any are broken
+    compact           Rewrite the database to reclaim space left behind by old versions
+    gc <KEEP_LAST> [--dry-run]
+                      Remove every version except the current one, the newest of each branch,
+                      and the KEEP_LAST most recent ones
+    export <ARCHIVE> [--full-history]
+                      Write the current version, or every version, to a single archive file
+    import <ARCHIVE>  Create a new database at DATABASE_PATH from an archive
+    bench [LEVEL...] [--zstd FACTOR...]
+                      Compress the current version with each LZ4 level (default: 1 4 8 10 12 16),
+                      with and without sled's zstd compression at each factor (default: 5), and
+                      report the size, compression ratio and throughput
+
+The storage settings, and the chunk shape and LODs for export and import, are read from config.ron
+in the working directory.";
+
+const DEFAULT_BENCH_LEVELS: [u32; 6] = [1, 4, 8, 10, 12, 16];
+const DEFAULT_BENCH_ZSTD_FACTORS: [i32; 1] = [5];
+
+fn main() {
+    env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Warn)
+        .init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (path, command, command_args) = match args.as_slice() {
+        [path, command, command_args @ ..] => (path.as_str(), command.as_str(), command_args),
+        _ => exit_with_usage(),
+    };
+
+    let result = match (command, command_args) {
+        ("versions", []) => list_versions(path),
+        ("stats", []) => print_stats(path, None),
+        ("stats", [version]) => match version.parse() {
+            Ok(version) => print_stats(path, Some(version)),
+            Err(_) => exit_with_usage(),
+        },
+        ("check", []) => check(path),
+        ("compact", []) => compact(path),
+        ("gc", [keep_last]) => gc(path, keep_last, false),
+        ("gc", [keep_last, dry_run]) if dry_run == "--dry-run" => gc(path, keep_last, true),
+        ("export", [archive]) => export(path, archive, false),
+        ("export", [archive, full]) if full == "--full-history" => export(path, archive, true),
+        ("import", [archive]) => import(path, archive),
+        ("bench", bench_args) => bench(path, bench_args),
+        _ => exit_with_usage(),
+    };
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+// Every command returns whether it succeeded, for the exit code.
+
+fn list_versions(path: &str) -> EditorResult<bool> {
+    let (versions, current) = WorldDb::open(path, &storage_config()?)?.versions()?;
+    for (version, info) in versions.iter() {
+        let marker = if *version == current { "*" } else { " " };
+        let parent = match (info.parent, info.merged) {
+            (Some(p), Some(m)) => format!("{}+{}", p, m),
+            (Some(p), None) => p.to_string(),
+            _ => "-".to_string(),
+        };
+        println!(
+            "{} {:>6}  parent {:>6}  {:<16} {:>12}  {}",
+            marker,
+            version,
+            parent,
+            info.branch,
+            info.timestamp,
+            info.message.as_deref().unwrap_or("(working)")
+        );
+    }
+
+    Ok(true)
+}
+
+fn print_stats(path: &str, version: Option<u64>) -> EditorResult<bool> {
+    let world = WorldDb::open_read_only(path, &storage_config()?)?;
+    let version = match version {
+        Some(version) => version,
+        None => world.versions()?.1,
+    };
+
+    println!("Version {}", version);
+    println!("{:>4} {:>10} {:>14}", "LOD", "chunks", "bytes");
+    let mut total_chunks = 0;
+    let mut total_bytes = 0;
+    for (lod, stats) in world.chunk_stats(version)?.iter() {
+        println!(
+            "{:>4} {:>10} {:>14}",
+            lod, stats.num_chunks, stats.compressed_bytes
+        );
+        total_chunks += stats.num_chunks;
+        total_bytes += stats.compressed_bytes;
+    }
+    println!("{:>4} {:>10} {:>14}", "all", total_chunks, total_bytes);
+    println!("Database size on disk: {} bytes", world.size_on_disk()?);
+
+    Ok(true)
+}
+
+fn check(path: &str) -> EditorResult<bool> {
+    let report = WorldDb::open_read_only(path, &storage_config()?)?.check_integrity()?;
+    for problem in report.problems.iter() {
+        println!("{}", problem);
+    }
+    println!(
+        "Checked {} chunks in {} versions, found {} problems",
+        report.num_chunks,
+        report.num_versions,
+        report.problems.len()
+    );
+
+    Ok(report.is_ok())
+}
+
+fn compact(path: &str) -> EditorResult<bool> {
+    let (old_size, new_size) = compact_world_db(path, &storage_config()?)?;
+    println!("Compacted {} from {} to {} bytes", path, old_size, new_size);
+
+    Ok(true)
+}
+
+fn gc(path: &str, keep_last: &str, dry_run: bool) -> EditorResult<bool> {
+    let policy = RetentionPolicy {
+        keep_last: keep_last.parse().unwrap_or_else(|_| exit_with_usage()),
+    };
+    let report = collect_garbage(path, &storage_config()?, &policy, dry_run)?;
+    if report.removed_versions.is_empty() {
+        println!("Every version is retained, there's nothing to collect");
+    } else if dry_run {
+        println!("Would remove versions {:?}", report.removed_versions);
+    } else {
+        println!(
+            "Removed {} versions, reclaimed {} bytes ({} to {} bytes)",
+            report.removed_versions.len(),
+            report.reclaimed_bytes(),
+            report.old_size,
+            report.new_size
+        );
+        println!("The remaining versions were renumbered, list them with `versions`");
+    }
+
+    Ok(true)
+}
+
+fn export(path: &str, archive: &str, full_history: bool) -> EditorResult<bool> {
+    let config = Config::read_file("config.ron")?;
+    let header = export_world_archive(
+        path,
+        &config.storage,
+        archive,
+        config.feldspar.map.chunk_shape(),
+        full_history,
+    )?;
+    println!("Exported {} versions to {}", header.num_versions, archive);
+
+    Ok(true)
+}
+
+fn import(path: &str, archive: &str) -> EditorResult<bool> {
+    let config = Config::read_file("config.ron")?;
+    let map_config = config.feldspar.map;
+    let header = import_world_archive(
+        archive,
+        path,
+        &config.storage,
+        map_config.chunk_shape(),
+        map_config.num_lods as u8,
+    )?;
+    println!("Imported {} versions into {}", header.num_versions, path);
+
+    Ok(true)
+}
+
+fn bench(path: &str, args: &[String]) -> EditorResult<bool> {
+    let (levels, factors) = match args.iter().position(|arg| arg == "--zstd") {
+        Some(i) => (parse_all(&args[..i]), parse_all(&args[i + 1..])),
+        None => (parse_all(args), DEFAULT_BENCH_ZSTD_FACTORS.to_vec()),
+    };
+    let levels = if levels.is_empty() {
+        DEFAULT_BENCH_LEVELS.to_vec()
+    } else {
+        levels
+    };
+    let storage = storage_config()?;
+    let mut options = Vec::new();
+    for &level in levels.iter() {
+        let sled_factors = std::iter::once(None).chain(factors.iter().map(|&f| Some(f)));
+        for sled_compression_factor in sled_factors {
+            options.push(StorageConfig {
+                chunk_codec: ChunkCodec::Lz4 { level },
+                sled_compression_factor,
+            });
+        }
+    }
+
+    let results = benchmark_storage(&WorldDb::open(path, &storage)?, &options)?;
+    if let Some(first) = results.iter().find_map(|result| result.as_ref().ok()) {
+        println!(
+            "{} chunks, {} bytes uncompressed",
+            first.num_chunks, first.raw_bytes
+        );
+    }
+    println!(
+        "{:<16} {:>5} {:>14} {:>8} {:>14} {:>14} {:>14}",
+        "chunk codec", "zstd", "chunk bytes", "ratio", "on disk", "encode MiB/s", "decode MiB/s"
+    );
+    for (option, result) in options.iter().zip(results.into_iter()) {
+        let codec = format!("{:?}", option.chunk_codec);
+        let zstd = option
+            .sled_compression_factor
+            .map_or_else(|| "off".to_string(), |factor| factor.to_string());
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                println!("{:<16} {:>5} failed: {}", codec, zstd, e);
+                continue;
+            }
+        };
+        let marker = if *option == storage {
+            " (configured)"
+        } else {
+            ""
+        };
+        println!(
+            "{:<16} {:>5} {:>14} {:>8.2} {:>14} {:>14.1} {:>14.1}{}",
+            codec,
+            zstd,
+            result.compressed_bytes,
+            result.compression_ratio(),
+            result.size_on_disk,
+            result.encode_throughput(),
+            result.decode_throughput(),
+            marker
+        );
+    }
+
+    Ok(true)
+}
+
+fn parse_all<T: std::str::FromStr>(args: &[String]) -> Vec<T> {
+    args.iter()
+        .map(|arg| arg.parse().unwrap_or_else(|_| exit_with_usage()))
+        .collect()
+}
+
+/// The storage settings from config.ron, or the defaults if there isn't one.
+fn storage_config() -> EditorResult<StorageConfig> {
+    if Path::new("config.ron").exists() {
+        Ok(Config::read_file("config.ron")?.storage)
+    } else {
+        Ok(StorageConfig::default())
+    }
+}
@@ -0,0 +1,317 @@
+mod keys;
+
+use crate::Action;
+
+use bevy::input::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// A key or mouse button, with the modifiers that have to be held with it. Written like
+/// `Ctrl+Shift+Z` or `MouseRight`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Chord {
+    pub modifiers: Modifiers,
+    pub trigger: Trigger,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    /// The Windows or Command key.
+    pub logo: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Trigger {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl Modifiers {
+    fn held(keys: &Input<KeyCode>) -> Self {
+        let either = |l, r| keys.pressed(l) || keys.pressed(r);
+        Self {
+            ctrl: either(KeyCode::LControl, KeyCode::RControl),
+            shift: either(KeyCode::LShift, KeyCode::RShift),
+            alt: either(KeyCode::LAlt, KeyCode::RAlt),
+            logo: either(KeyCode::LWin, KeyCode::RWin),
+        }
+    }
+}
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let trigger = parts.pop().unwrap();
+        let trigger = keys::parse_trigger(trigger)
+            .ok_or_else(|| format!("unknown key {:?} in {:?}", trigger, s))?;
+
+        let mut modifiers = Modifiers::default();
+        for modifier in parts.into_iter() {
+            let held = match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => &mut modifiers.ctrl,
+                "shift" => &mut modifiers.shift,
+                "alt" | "option" => &mut modifiers.alt,
+                "super" | "cmd" | "win" | "logo" => &mut modifiers.logo,
+                _ => return Err(format!("unknown modifier {:?} in {:?}", modifier, s)),
+            };
+            *held = true;
+        }
+
+        Ok(Chord { modifiers, trigger })
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.modifiers.ctrl, "Ctrl+"),
+            (self.modifiers.shift, "Shift+"),
+            (self.modifiers.alt, "Alt+"),
+            (self.modifiers.logo, "Super+"),
+        ]
+        .iter()
+        {
+            if *held {
+                write!(f, "{}", name)?;
+            }
+        }
+
+        write!(f, "{}", keys::trigger_name(self.trigger))
+    }
+}
+
+/// The chords bound to each action. The `bindings` table in the config only needs the actions that
+/// are rebound, e.g. `{ "undo": ["Ctrl+Z"], "redo": ["Ctrl+Y", "Ctrl+Shift+Z"] }`, and an empty
+/// list unbinds an action.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "HashMap<String, Vec<String>>")]
+pub struct KeyBindings {
+    chords: HashMap<Action, Vec<Chord>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let chords = Action::all()
+            .map(|action| {
+                let chords = action
+                    .default_chords()
+                    .iter()
+                    .map(|chord| chord.parse().expect("Bad default chord"))
+                    .collect();
+                (action, chords)
+            })
+            .collect();
+
+        Self { chords }
+    }
+}
+
+impl TryFrom<HashMap<String, Vec<String>>> for KeyBindings {
+    type Error = String;
+
+    fn try_from(table: HashMap<String, Vec<String>>) -> Result<Self, Self::Error> {
+        let mut bindings = Self::default();
+        for (name, chords) in table.into_iter() {
+            let action =
+                Action::from_name(&name).ok_or_else(|| format!("unknown action {:?}", name))?;
+            let chords = chords
+                .iter()
+                .map(|chord| chord.parse())
+                .collect::<Result<_, _>>()?;
+            bindings.chords.insert(action, chords);
+        }
+
+        Ok(bindings)
+    }
+}
+
+impl KeyBindings {
+    pub fn chords(&self, action: Action) -> &[Chord] {
+        self.chords.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// The chords bound to `action` for help text, like "Ctrl+Z or U".
+    pub fn chord_names(&self, action: Action) -> String {
+        let names: Vec<String> = self
+            .chords(action)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        if names.is_empty() {
+            "unbound".to_string()
+        } else {
+            names.join(" or ")
+        }
+    }
+
+    /// A line of help text like "Up/Down: select, Return: jump", with what each group of actions
+    /// does.
+    pub fn help(&self, entries: &[(&[Action], &str)]) -> String {
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(actions, what)| {
+                let chords: Vec<String> = actions
+                    .iter()
+                    .map(|action| self.chord_names(*action))
+                    .collect();
+                format!("{}: {}", chords.join("/"), what)
+            })
+            .collect();
+
+        entries.join(", ")
+    }
+
+    /// True if one of the chords for `action` was just pressed, with exactly its modifiers held.
+    pub fn just_pressed(
+        &self,
+        action: Action,
+        keys: &Input<KeyCode>,
+        mouse: &Input<MouseButton>,
+    ) -> bool {
+        let held = Modifiers::held(keys);
+        self.chords(action).iter().any(|chord| {
+            chord.modifiers == held
+                && match chord.trigger {
+                    Trigger::Key(key) => keys.just_pressed(key),
+                    Trigger::Mouse(button) => mouse.just_pressed(button),
+                }
+        })
+    }
+
+    /// True while one of the chords for `action` is held, with exactly its modifiers.
+    pub fn pressed(
+        &self,
+        action: Action,
+        keys: &Input<KeyCode>,
+        mouse: &Input<MouseButton>,
+    ) -> bool {
+        let held = Modifiers::held(keys);
+        self.chords(action).iter().any(|chord| {
+            chord.modifiers == held
+                && match chord.trigger {
+                    Trigger::Key(key) => keys.pressed(key),
+                    Trigger::Mouse(button) => mouse.pressed(button),
+                }
+        })
+    }
+
+    /// True if the key or button of one of the chords for `action` was just released. The
+    /// modifiers don't matter, since they might have been let go first.
+    pub fn just_released(
+        &self,
+        action: Action,
+        keys: &Input<KeyCode>,
+        mouse: &Input<MouseButton>,
+    ) -> bool {
+        self.chords(action).iter().any(|chord| match chord.trigger {
+            Trigger::Key(key) => keys.just_released(key),
+            Trigger::Mouse(button) => mouse.just_released(button),
+        })
+    }
+
+    /// The mouse buttons bound to `action` whose modifiers are held, for tools that need to know
+    /// which voxel a button was pressed on.
+    pub fn mouse_buttons(&self, action: Action, keys: &Input<KeyCode>) -> Vec<MouseButton> {
+        let held = Modifiers::held(keys);
+        self.chords(action)
+            .iter()
+            .filter_map(|chord| match chord.trigger {
+                Trigger::Mouse(button) if chord.modifiers == held => Some(button),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chords_round_trip() {
+        for s in [
+            "Z",
+            "Ctrl+Z",
+            "Ctrl+Shift+Z",
+            "Alt+MouseLeft",
+            "Shift+1",
+            "F5",
+            "Up",
+        ]
+        .iter()
+        {
+            let chord: Chord = s.parse().unwrap();
+            assert_eq!(chord.to_string(), *s);
+        }
+
+        let chord: Chord = "ctrl + shift + z".parse().unwrap();
+        assert_eq!(chord.to_string(), "Ctrl+Shift+Z");
+        assert!("Hyper+Z".parse::<Chord>().is_err());
+        assert!("Ctrl+Nope".parse::<Chord>().is_err());
+        assert!("".parse::<Chord>().is_err());
+    }
+
+    #[test]
+    fn config_table_overrides_defaults() {
+        let bindings: KeyBindings =
+            ron::de::from_str(r#"{ "undo": ["Ctrl+Z"], "save": [] }"#).unwrap();
+        assert_eq!(
+            bindings.chords(Action::Undo),
+            &["Ctrl+Z".parse().unwrap()][..]
+        );
+        assert!(bindings.chords(Action::Save).is_empty());
+        assert_eq!(bindings.chords(Action::Redo), &["R".parse().unwrap()][..]);
+
+        assert!(ron::de::from_str::<KeyBindings>(r#"{ "fly": ["F"] }"#).is_err());
+        assert!(ron::de::from_str::<KeyBindings>(r#"{ "undo": ["Ctrl+"] }"#).is_err());
+    }
+
+    #[test]
+    fn help_shows_the_configured_chords() {
+        let bindings: KeyBindings =
+            ron::de::from_str(r#"{ "undo": ["Ctrl+Z", "U"], "save": [] }"#).unwrap();
+        assert_eq!(bindings.chord_names(Action::Undo), "Ctrl+Z or U");
+        assert_eq!(bindings.chord_names(Action::Save), "unbound");
+        assert_eq!(
+            bindings.help(&[
+                (&[Action::SelectPrevious, Action::SelectNext], "select"),
+                (&[Action::Save], "save"),
+            ]),
+            "Up/Down: select, unbound: save"
+        );
+    }
+
+    #[test]
+    fn modifiers_have_to_match_exactly() {
+        let bindings: KeyBindings = ron::de::from_str(r#"{ "undo": ["Ctrl+Z"] }"#).unwrap();
+        let mouse = Input::<MouseButton>::default();
+        let mut keys = Input::<KeyCode>::default();
+
+        keys.press(KeyCode::Z);
+        assert!(!bindings.just_pressed(Action::Undo, &keys, &mouse));
+        assert!(bindings.just_pressed(Action::TerraformAdd, &keys, &mouse));
+
+        keys.press(KeyCode::LControl);
+        assert!(bindings.just_pressed(Action::Undo, &keys, &mouse));
+        assert!(!bindings.pressed(Action::TerraformAdd, &keys, &mouse));
+
+        keys.clear();
+        keys.release(KeyCode::Z);
+        assert!(bindings.just_released(Action::TerraformAdd, &keys, &mouse));
+    }
+}
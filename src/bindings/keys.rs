@@ -0,0 +1,130 @@
+use super::Trigger;
+
+use bevy::input::prelude::*;
+
+/// The names of the keys that can be bound. Where a key has more than one name, the first one is
+/// used when it's printed.
+const KEYS: &[(&str, KeyCode)] = &[
+    ("A", KeyCode::A),
+    ("B", KeyCode::B),
+    ("C", KeyCode::C),
+    ("D", KeyCode::D),
+    ("E", KeyCode::E),
+    ("F", KeyCode::F),
+    ("G", KeyCode::G),
+    ("H", KeyCode::H),
+    ("I", KeyCode::I),
+    ("J", KeyCode::J),
+    ("K", KeyCode::K),
+    ("L", KeyCode::L),
+    ("M", KeyCode::M),
+    ("N", KeyCode::N),
+    ("O", KeyCode::O),
+    ("P", KeyCode::P),
+    ("Q", KeyCode::Q),
+    ("R", KeyCode::R),
+    ("S", KeyCode::S),
+    ("T", KeyCode::T),
+    ("U", KeyCode::U),
+    ("V", KeyCode::V),
+    ("W", KeyCode::W),
+    ("X", KeyCode::X),
+    ("Y", KeyCode::Y),
+    ("Z", KeyCode::Z),
+    ("1", KeyCode::Key1),
+    ("2", KeyCode::Key2),
+    ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4),
+    ("5", KeyCode::Key5),
+    ("6", KeyCode::Key6),
+    ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8),
+    ("9", KeyCode::Key9),
+    ("0", KeyCode::Key0),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Escape", KeyCode::Escape),
+    ("Esc", KeyCode::Escape),
+    ("Return", KeyCode::Return),
+    ("Enter", KeyCode::Return),
+    ("Back", KeyCode::Back),
+    ("Backspace", KeyCode::Back),
+    ("Space", KeyCode::Space),
+    ("Tab", KeyCode::Tab),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("LBracket", KeyCode::LBracket),
+    ("[", KeyCode::LBracket),
+    ("RBracket", KeyCode::RBracket),
+    ("]", KeyCode::RBracket),
+    ("Minus", KeyCode::Minus),
+    ("-", KeyCode::Minus),
+    ("Equals", KeyCode::Equals),
+    ("=", KeyCode::Equals),
+    ("Comma", KeyCode::Comma),
+    (",", KeyCode::Comma),
+    ("Period", KeyCode::Period),
+    (".", KeyCode::Period),
+    ("Slash", KeyCode::Slash),
+    ("/", KeyCode::Slash),
+    ("Backslash", KeyCode::Backslash),
+    ("Semicolon", KeyCode::Semicolon),
+    (";", KeyCode::Semicolon),
+    ("Apostrophe", KeyCode::Apostrophe),
+    ("Grave", KeyCode::Grave),
+    ("`", KeyCode::Grave),
+];
+
+/// Only the buttons that `VoxelCursor` keeps track of.
+const MOUSE_BUTTONS: &[(&str, MouseButton)] = &[
+    ("MouseLeft", MouseButton::Left),
+    ("MouseMiddle", MouseButton::Middle),
+    ("MouseRight", MouseButton::Right),
+];
+
+/// Letters are case-insensitive.
+pub fn parse_trigger(name: &str) -> Option<Trigger> {
+    let key = KEYS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, key)| Trigger::Key(*key));
+    let button = || {
+        MOUSE_BUTTONS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, button)| Trigger::Mouse(*button))
+    };
+
+    key.or_else(button)
+}
+
+pub fn trigger_name(trigger: Trigger) -> &'static str {
+    let name = match trigger {
+        Trigger::Key(key) => KEYS.iter().find(|(_, k)| *k == key).map(|(n, _)| *n),
+        Trigger::Mouse(button) => MOUSE_BUTTONS
+            .iter()
+            .find(|(_, b)| *b == button)
+            .map(|(n, _)| *n),
+    };
+
+    // Every trigger that can be parsed has a name.
+    name.unwrap_or("?")
+}
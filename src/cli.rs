@@ -0,0 +1,171 @@
+use crate::{CameraConfig, Config};
+
+use log::LevelFilter;
+
+pub const EDITOR_USAGE: &str = "\
+Usage: editor [OPTIONS]
+
+Options:
+    --config <PATH>       The config file to read (default: config.ron)
+    --db <PATH>           The world database to open, instead of database_path from the config
+    --camera <TYPE>       unreal or orbit, instead of the camera from the config
+    --read-only           Open a copy of the database, so nothing is written to the original
+    --log-level <LEVEL>   off, error, warn, info, debug or trace (default: RUST_LOG, or error)
+    --help                Print this message
+
+Options override the values in the config file.";
+
+/// The editor's command line, so launchers can open a specific world without editing the config.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EditorArgs {
+    pub config_path: String,
+    pub database_path: Option<String>,
+    pub camera: Option<CameraType>,
+    pub read_only: bool,
+    pub log_level: Option<LevelFilter>,
+    pub help: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CameraType {
+    Unreal,
+    Orbit,
+}
+
+impl Default for EditorArgs {
+    fn default() -> Self {
+        Self {
+            config_path: "config.ron".to_string(),
+            database_path: None,
+            camera: None,
+            read_only: false,
+            log_level: None,
+            help: false,
+        }
+    }
+}
+
+impl EditorArgs {
+    /// Parses the arguments after the program name. The error says which argument was wrong.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--config" => parsed.config_path = value()?,
+                "--db" => parsed.database_path = Some(value()?),
+                "--camera" => {
+                    parsed.camera = Some(match value()?.as_str() {
+                        "unreal" => CameraType::Unreal,
+                        "orbit" => CameraType::Orbit,
+                        other => return Err(format!("unknown camera type {}", other)),
+                    })
+                }
+                "--read-only" => parsed.read_only = true,
+                "--log-level" => {
+                    let level = value()?;
+                    parsed.log_level = Some(
+                        level
+                            .parse()
+                            .map_err(|_| format!("unknown log level {}", level))?,
+                    );
+                }
+                "--help" | "-h" => parsed.help = true,
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Overrides the values in `config` that were given on the command line.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(path) = &self.database_path {
+            config.database_path = path.clone();
+        }
+        if self.read_only {
+            config.read_only = true;
+        }
+        // Switching types loses the sensitivities from the config, since they don't carry over.
+        match (self.camera, config.camera) {
+            (Some(CameraType::Unreal), CameraConfig::Orbit(_)) => {
+                config.camera = CameraConfig::Unreal(Default::default())
+            }
+            (Some(CameraType::Orbit), CameraConfig::Unreal(_)) => {
+                config.camera = CameraConfig::Orbit(Default::default())
+            }
+            _ => {}
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<EditorArgs, String> {
+        EditorArgs::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_every_option() {
+        assert_eq!(parse(&[]).unwrap(), EditorArgs::default());
+
+        let args = parse(&[
+            "--config",
+            "other.ron",
+            "--db",
+            "/tmp/world2",
+            "--camera",
+            "orbit",
+            "--read-only",
+            "--log-level",
+            "debug",
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            EditorArgs {
+                config_path: "other.ron".to_string(),
+                database_path: Some("/tmp/world2".to_string()),
+                camera: Some(CameraType::Orbit),
+                read_only: true,
+                log_level: Some(LevelFilter::Debug),
+                help: false,
+            }
+        );
+
+        assert!(parse(&["--db"]).is_err());
+        assert!(parse(&["--camera", "fly"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["world1"]).is_err());
+    }
+
+    #[test]
+    fn overrides_the_config() {
+        let mut config = Config {
+            database_path: "/tmp/world1".to_string(),
+            camera: CameraConfig::Unreal(Default::default()),
+            ..Default::default()
+        };
+        parse(&["--db", "/tmp/world2", "--camera", "orbit", "--read-only"])
+            .unwrap()
+            .apply(&mut config);
+
+        assert_eq!(config.database_path, "/tmp/world2");
+        assert!(matches!(config.camera, CameraConfig::Orbit(_)));
+        assert!(config.read_only);
+
+        parse(&[]).unwrap().apply(&mut config);
+        assert_eq!(config.database_path, "/tmp/world2");
+        assert!(config.read_only);
+    }
+}
@@ -1,3 +1,10 @@
+mod watcher;
+
+pub(crate) use watcher::{reload_config, ConfigWatcher};
+
+use crate::{EditorError, EditorResult, KeyBindings, WINDOW_TITLE};
+
+use bevy::render::{color::Color, pass::ClearColor};
 use serde::Deserialize;
 use smooth_bevy_cameras::controllers::{
     orbit::OrbitCameraController, unreal::UnrealCameraController,
@@ -7,20 +14,210 @@ use smooth_bevy_cameras::controllers::{
 pub struct Config {
     pub feldspar: feldspar::Config,
     pub bevy: BevyConfig,
+    #[serde(default)]
+    pub window: WindowConfig,
+    /// The point lights that light the world.
+    #[serde(default = "default_lights")]
+    pub lights: Vec<LightConfig>,
     pub database_path: String,
     pub camera: CameraConfig,
+    /// The number of edits kept in the undo history when it's pruned.
+    #[serde(default = "default_undo_history_limit")]
+    pub undo_history_limit: usize,
+    /// How often unsaved edits are saved automatically, or never if `None`.
+    #[serde(default = "default_autosave_interval_secs")]
+    pub autosave_interval_secs: Option<f64>,
+    /// How to fill a database that doesn't have a world yet. Existing worlds are left alone.
+    #[serde(default)]
+    pub new_world: NewWorldConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// The keys and mouse buttons for each action, on top of the defaults.
+    #[serde(default)]
+    pub bindings: KeyBindings,
+    /// Opens a copy of the database instead, so the world can be looked at and edited without
+    /// anything being written to it.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+fn default_undo_history_limit() -> usize {
+    1000
+}
+
+fn default_autosave_interval_secs() -> Option<f64> {
+    Some(300.0)
 }
 
-#[derive(Clone, Copy, Deserialize, Default)]
+/// Four lights high above the corners of the origin.
+fn default_lights() -> Vec<LightConfig> {
+    let mut lights = Vec::new();
+    for &x in [-100.0, 100.0].iter() {
+        for &z in [-100.0, 100.0].iter() {
+            lights.push(LightConfig {
+                position: [x, 100.0, z],
+                ..Default::default()
+            });
+        }
+    }
+
+    lights
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default)]
 pub struct BevyConfig {
     pub wireframes: bool,
+    /// The RGB color behind the world.
+    pub clear_color: [f32; 3],
+    /// The number of samples per pixel for antialiasing, or 1 to turn it off.
+    pub msaa_samples: u32,
+}
+
+impl BevyConfig {
+    pub fn clear_color(&self) -> ClearColor {
+        let [r, g, b] = self.clear_color;
+        ClearColor(Color::rgb(r, g, b))
+    }
+}
+
+impl Default for BevyConfig {
+    fn default() -> Self {
+        Self {
+            wireframes: false,
+            clear_color: [0.2, 0.2, 0.4],
+            msaa_samples: 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: f32,
+    pub height: f32,
+    pub title: String,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 1600.0,
+            height: 900.0,
+            title: WINDOW_TITLE.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LightConfig {
+    pub position: [f32; 3],
+    /// RGB.
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// How far the light reaches.
+    pub range: f32,
+}
+
+impl Default for LightConfig {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            color: [1.0; 3],
+            intensity: 40000.0,
+            range: 800.0,
+        }
+    }
+}
+
+/// How the world database is compressed. Compare the options on a real world with
+/// `world_db <DATABASE_PATH> bench`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// How each chunk is compressed before it's written. Chunks that were written with another
+    /// level can still be read.
+    pub chunk_codec: ChunkCodec,
+    /// Also compresses the whole database with zstd at this level, or not at all if `None`. This
+    /// needs sled's `compression` feature, and can't be changed for an existing database.
+    pub sled_compression_factor: Option<i32>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            chunk_codec: ChunkCodec::default(),
+            sled_compression_factor: None,
+        }
+    }
+}
+
+/// feldspar's `VoxelDb` only supports LZ4, so for now the level is the only choice.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub enum ChunkCodec {
+    /// Higher levels compress better, but writing is slower. Decompressing is fast at any level.
+    Lz4 { level: u32 },
+}
+
+impl Default for ChunkCodec {
+    fn default() -> Self {
+        ChunkCodec::Lz4 { level: 10 }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct NewWorldConfig {
+    pub template: WorldTemplate,
+    /// The size of the world along the X and Z axes, starting at the origin. Heightmaps are one
+    /// voxel per pixel instead.
+    pub size: [i32; 2],
+    /// The voxel type of the ground.
+    pub voxel_type: u8,
+}
+
+impl Default for NewWorldConfig {
+    fn default() -> Self {
+        Self {
+            template: WorldTemplate::Flat { height: 2 },
+            size: [64, 64],
+            voxel_type: 2,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub enum WorldTemplate {
+    /// Nothing but a ground plane one voxel thick, to build on.
+    Empty,
+    /// Ground that's `height` voxels thick.
+    Flat { height: i32 },
+    /// Rolling hills around `base_height`, made of `octaves` layers of value noise. Each layer has
+    /// half the wavelength and amplitude of the one before.
+    Noise {
+        seed: u32,
+        base_height: f32,
+        amplitude: f32,
+        wavelength: f32,
+        octaves: u32,
+    },
+    /// Ground whose height is read from the first channel of an 8-bit image, where white is
+    /// `max_height`.
+    Heightmap { path: String, max_height: f32 },
 }
 
 impl Config {
-    pub fn read_file(path: &str) -> Result<Self, ron::Error> {
-        let reader = std::fs::File::open(path)?;
+    pub fn read_file(path: &str) -> EditorResult<Self> {
+        let reader = std::fs::File::open(path).map_err(|error| EditorError::ReadConfig {
+            path: path.to_string(),
+            error,
+        })?;
 
-        ron::de::from_reader(reader)
+        ron::de::from_reader(reader).map_err(|error| EditorError::Config {
+            path: path.to_string(),
+            error,
+        })
     }
 }
 
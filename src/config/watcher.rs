@@ -0,0 +1,267 @@
+use crate::{BevyConfig, CameraConfig, Config, EditorArgs, EditorError};
+
+use bevy::{
+    core::Time,
+    ecs::prelude::*,
+    render::{pass::ClearColor, wireframe::WireframeConfig},
+};
+use smooth_bevy_cameras::{
+    controllers::{orbit::OrbitCameraController, unreal::UnrealCameraController},
+    Smoother,
+};
+use std::time::SystemTime;
+
+/// Reloads the config file when it changes, so camera feel and debug rendering can be tweaked
+/// without restarting. The command line is applied again on top of every reload.
+pub struct ConfigWatcher {
+    args: EditorArgs,
+    last_modified: Option<SystemTime>,
+    secs_since_check: f64,
+    /// The wireframe plugin is only added when wireframes are enabled at startup.
+    wireframe_plugin_loaded: bool,
+}
+
+impl ConfigWatcher {
+    pub fn new(args: EditorArgs, wireframe_plugin_loaded: bool) -> Self {
+        Self {
+            last_modified: modified_time(&args.config_path),
+            args,
+            secs_since_check: 0.0,
+            wireframe_plugin_loaded,
+        }
+    }
+}
+
+const CHECK_INTERVAL_SECS: f64 = 1.0;
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Settings that only take effect at startup are kept as they were, and a warning says which ones
+/// need a restart. A config that fails to parse is reported and otherwise ignored.
+pub fn reload_config(
+    watcher: Option<ResMut<ConfigWatcher>>,
+    mut config: ResMut<Config>,
+    mut feldspar_config: ResMut<feldspar::Config>,
+    mut wireframe_config: ResMut<WireframeConfig>,
+    mut clear_color: ResMut<ClearColor>,
+    time: Res<Time>,
+    mut unreal_controllers: Query<&mut UnrealCameraController>,
+    mut orbit_controllers: Query<&mut OrbitCameraController>,
+    mut smoothers: Query<&mut Smoother>,
+    mut errors: EventWriter<EditorError>,
+) {
+    let mut watcher = if let Some(watcher) = watcher {
+        watcher
+    } else {
+        return;
+    };
+
+    watcher.secs_since_check += time.delta_seconds_f64();
+    if watcher.secs_since_check < CHECK_INTERVAL_SECS {
+        return;
+    }
+    watcher.secs_since_check = 0.0;
+
+    let modified = modified_time(&watcher.args.config_path);
+    if modified == watcher.last_modified {
+        return;
+    }
+    watcher.last_modified = modified;
+
+    let mut new_config = match Config::read_file(&watcher.args.config_path) {
+        Ok(new_config) => new_config,
+        Err(e) => {
+            errors.send(e);
+            return;
+        }
+    };
+    watcher.args.apply(&mut new_config);
+
+    for setting in restart_only_changes(&config, &new_config, watcher.wireframe_plugin_loaded) {
+        log::warn!(
+            "{} changed in {}, restart the editor to apply it",
+            setting,
+            watcher.args.config_path
+        );
+    }
+    let new_config = live_settings(&config, new_config);
+
+    match new_config.camera {
+        CameraConfig::Unreal(new_controller) => {
+            for mut controller in unreal_controllers.iter_mut() {
+                // Tools turn the controller off while they use the mouse.
+                *controller = UnrealCameraController {
+                    enabled: controller.enabled,
+                    ..new_controller
+                };
+            }
+            for mut smoother in smoothers.iter_mut() {
+                smoother.set_lag_weight(new_controller.smoothing_weight);
+            }
+        }
+        CameraConfig::Orbit(new_controller) => {
+            for mut controller in orbit_controllers.iter_mut() {
+                *controller = OrbitCameraController {
+                    enabled: controller.enabled,
+                    ..new_controller
+                };
+            }
+            for mut smoother in smoothers.iter_mut() {
+                smoother.set_lag_weight(new_controller.smoothing_weight);
+            }
+        }
+    }
+    wireframe_config.global = new_config.bevy.wireframes;
+    *clear_color = new_config.bevy.clear_color();
+    // feldspar reads its render settings from this resource every frame.
+    *feldspar_config = new_config.feldspar;
+
+    *config = new_config;
+    log::info!("Reloaded {}", watcher.args.config_path);
+}
+
+/// The names of the settings in `new_config` that differ from `config`, but can't be applied
+/// until the editor restarts.
+fn restart_only_changes(
+    config: &Config,
+    new_config: &Config,
+    wireframe_plugin_loaded: bool,
+) -> Vec<&'static str> {
+    let (old_map, new_map) = (&config.feldspar.map, &new_config.feldspar.map);
+    let (old_cache, new_cache) = (
+        &config.feldspar.chunk_cache,
+        &new_config.feldspar.chunk_cache,
+    );
+    let changes = [
+        (
+            old_map.chunk_exponent != new_map.chunk_exponent
+                || old_map.superchunk_exponent != new_map.superchunk_exponent
+                || old_map.num_lods != new_map.num_lods,
+            "feldspar.map",
+        ),
+        (
+            old_cache.max_cached_chunks != new_cache.max_cached_chunks
+                || old_cache.max_chunks_compressed_per_frame_per_thread
+                    != new_cache.max_chunks_compressed_per_frame_per_thread,
+            "feldspar.chunk_cache",
+        ),
+        (
+            config.feldspar.witness_radius != new_config.feldspar.witness_radius,
+            "feldspar.witness_radius",
+        ),
+        (
+            std::mem::discriminant(&config.camera) != std::mem::discriminant(&new_config.camera),
+            "The camera type",
+        ),
+        (
+            new_config.bevy.wireframes && !config.bevy.wireframes && !wireframe_plugin_loaded,
+            "bevy.wireframes",
+        ),
+        (
+            config.bevy.msaa_samples != new_config.bevy.msaa_samples,
+            "bevy.msaa_samples",
+        ),
+        (config.window != new_config.window, "window"),
+        (config.lights != new_config.lights, "lights"),
+        (
+            config.database_path != new_config.database_path,
+            "database_path",
+        ),
+        (config.storage != new_config.storage, "storage"),
+        (config.read_only != new_config.read_only, "read_only"),
+    ];
+
+    changes
+        .iter()
+        .filter(|(changed, _)| *changed)
+        .map(|(_, setting)| *setting)
+        .collect()
+}
+
+/// `new_config` with the settings that need a restart put back the way they are in `config`.
+fn live_settings(config: &Config, new_config: Config) -> Config {
+    let camera =
+        if std::mem::discriminant(&config.camera) == std::mem::discriminant(&new_config.camera) {
+            new_config.camera
+        } else {
+            config.camera
+        };
+
+    Config {
+        feldspar: feldspar::Config {
+            render: new_config.feldspar.render,
+            ..config.feldspar
+        },
+        bevy: BevyConfig {
+            msaa_samples: config.bevy.msaa_samples,
+            ..new_config.bevy
+        },
+        window: config.window.clone(),
+        lights: config.lights.clone(),
+        camera,
+        database_path: config.database_path.clone(),
+        storage: config.storage,
+        read_only: config.read_only,
+        ..new_config
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{ChunkCodec, LightConfig};
+
+    #[test]
+    fn restart_only_settings_are_flagged_and_kept() {
+        let config = Config {
+            database_path: "/tmp/world1".to_string(),
+            camera: CameraConfig::Unreal(Default::default()),
+            ..Default::default()
+        };
+        let mut new_config = config.clone();
+        new_config.undo_history_limit = 10;
+        new_config.feldspar.render.debug_chunk_boundaries = true;
+        assert!(restart_only_changes(&config, &new_config, false).is_empty());
+
+        new_config.database_path = "/tmp/world2".to_string();
+        new_config.storage.chunk_codec = ChunkCodec::Lz4 { level: 1 };
+        new_config.camera = CameraConfig::Orbit(Default::default());
+        new_config.bevy.wireframes = true;
+        new_config.bevy.clear_color = [0.0; 3];
+        new_config.lights.push(LightConfig::default());
+        assert_eq!(
+            restart_only_changes(&config, &new_config, false),
+            vec![
+                "The camera type",
+                "bevy.wireframes",
+                "lights",
+                "database_path",
+                "storage"
+            ]
+        );
+        assert_eq!(
+            restart_only_changes(&config, &new_config, true),
+            vec!["The camera type", "lights", "database_path", "storage"]
+        );
+
+        let applied = live_settings(&config, new_config);
+        assert_eq!(applied.undo_history_limit, 10);
+        assert!(applied.feldspar.render.debug_chunk_boundaries);
+        assert!(applied.bevy.wireframes);
+        assert_eq!(applied.bevy.clear_color, [0.0; 3]);
+        assert!(applied.lights.is_empty());
+        assert_eq!(applied.database_path, "/tmp/world1");
+        assert_eq!(applied.storage.chunk_codec, ChunkCodec::default());
+        assert!(matches!(applied.camera, CameraConfig::Unreal(_)));
+    }
+}
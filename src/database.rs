@@ -1,4 +1,38 @@
-use crate::Config;
+mod archive;
+mod benchmark;
+mod forest_builder;
+mod gc;
+mod journal;
+mod new_world;
+mod save;
+mod session;
+mod streaming;
+mod versions;
+mod world_db;
+
+pub use archive::{export_world_archive, import_world_archive, ArchiveCompression, ArchiveHeader};
+pub use benchmark::{benchmark_storage, StorageBenchmark};
+pub use gc::{collect_garbage, GcReport, RetentionPolicy};
+pub use journal::{
+    close_editor_session, replay_edit_journal, write_edit_journal, EditJournal,
+    PendingJournalReplay,
+};
+pub use new_world::{create_new_world, PendingNewWorld};
+pub use save::{autosave_map, report_save_progress, save_map_to_db, MapSaver};
+pub use session::{
+    restore_editor_session, save_editor_session, EditorSession, PendingSessionRestore,
+};
+pub use streaming::{stream_chunks, ChunkStreamer};
+pub use versions::{
+    list_versions, version_command_system, version_diff_system, ChunkChange, DiffCommand,
+    MergeResolutions, MergeSide, PendingMerge, VersionCommand, VersionDiff, VersionInfo,
+};
+pub use world_db::{compact_world_db, IntegrityReport, LodStats, WorldDb};
+
+use versions::MAIN_BRANCH;
+use world_db::{open_sled, open_temporary_copy};
+
+use crate::{ChunkCodec, Config, EditTimeline, EditorError, EditorResult, HistoryChanges};
 
 use feldspar::{
     bb::{
@@ -9,17 +43,16 @@ use feldspar::{
                 open_snapshot_forest, transactions::create_snapshot_tree,
                 TransactionalVersionForest,
             },
-            Delta, VersionedChunkDb3,
+            VersionedChunkDb3,
         },
     },
-    prelude::{SdfVoxelMap, VoxelDb},
+    prelude::VoxelDb,
 };
 
-use bevy::input::Input;
-use bevy::prelude::KeyCode;
-use bevy::{ecs::prelude::*, tasks::ComputeTaskPool};
+use bevy::ecs::prelude::*;
 
 /// Holds persistent metadata about editor state.
+#[derive(Clone)]
 pub struct EditorDb {
     tree: Tree,
 }
@@ -29,97 +62,411 @@ impl EditorDb {
         Self { tree }
     }
 
-    pub fn current_version(&self) -> sled::Result<Option<u64>> {
+    pub fn current_version(&self) -> EditorResult<Option<u64>> {
         let version_bytes = self.tree.get(CURRENT_VERSION_KEY)?;
-        Ok(version_bytes.map(|b| u64_from_be_slice(&b)))
+        version_bytes.map(|b| u64_from_be_slice(&b)).transpose()
     }
 
-    pub fn write_current_version(&self, current_version: u64) -> sled::Result<()> {
+    /// Like `current_version`, for once the database has been opened, which always sets it.
+    pub fn require_current_version(&self) -> EditorResult<u64> {
+        self.current_version()?
+            .ok_or_else(|| EditorError::Corrupt("there is no current version".to_string()))
+    }
+
+    pub fn write_current_version(&self, current_version: u64) -> EditorResult<()> {
         self.tree
             .insert(CURRENT_VERSION_KEY, &current_version.to_be_bytes())?;
         Ok(())
     }
+
+    /// The node of the `EditTimeline` that matches the saved map.
+    pub fn current_edit(&self) -> EditorResult<Option<u64>> {
+        let edit_bytes = self.tree.get(CURRENT_EDIT_KEY)?;
+        edit_bytes.map(|b| u64_from_be_slice(&b)).transpose()
+    }
+
+    pub fn write_current_edit(&self, current_edit: u64) -> EditorResult<()> {
+        self.tree
+            .insert(CURRENT_EDIT_KEY, &current_edit.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Whether an editor session is using this database. If it's still set when the editor starts,
+    /// the last session crashed or closed with unsaved edits.
+    pub fn session_open(&self) -> EditorResult<bool> {
+        let open_bytes = self.tree.get(SESSION_OPEN_KEY)?;
+        Ok(open_bytes.map(|b| b.as_ref() == [1]).unwrap_or(false))
+    }
+
+    pub fn write_session_open(&self, open: bool) -> EditorResult<()> {
+        self.tree.insert(SESSION_OPEN_KEY, &[open as u8])?;
+        // The process might exit right after this, so don't wait for sled to flush on its own.
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    pub fn read_session(&self) -> EditorResult<Option<EditorSession>> {
+        let session_bytes = self.tree.get(SESSION_KEY)?;
+        session_bytes
+            .map(|b| {
+                let s = std::str::from_utf8(&b)
+                    .map_err(|e| EditorError::Corrupt(format!("session is not UTF-8: {}", e)))?;
+                EditorSession::decode(s)
+            })
+            .transpose()
+    }
+
+    pub fn write_session(&self, session: &EditorSession) -> EditorResult<()> {
+        self.tree
+            .insert(SESSION_KEY, session.encode()?.as_bytes())?;
+        // Written on exit, like the session open flag.
+        self.tree.flush()?;
+        Ok(())
+    }
 }
 
+impl EditorDb {
+    pub fn read_version(&self, version: u64) -> EditorResult<Option<VersionInfo>> {
+        let info_bytes = self.tree.get(version_key(version))?;
+        Ok(info_bytes.map(|b| bincode::deserialize(&b)).transpose()?)
+    }
+
+    pub fn write_version(&self, version: u64, info: &VersionInfo) -> EditorResult<()> {
+        self.tree
+            .insert(version_key(version), bincode::serialize(info)?)?;
+        Ok(())
+    }
+
+    pub fn remove_version(&self, version: u64) -> EditorResult<()> {
+        self.tree.remove(version_key(version))?;
+        Ok(())
+    }
+
+    /// The whole version graph, in order of creation.
+    pub fn read_versions(&self) -> EditorResult<Vec<(u64, VersionInfo)>> {
+        self.tree
+            .scan_prefix(VERSION_KEY_PREFIX)
+            .map(|entry| {
+                let (k, v) = entry?;
+                Ok((
+                    u64_from_be_slice(&k[VERSION_KEY_PREFIX.len()..])?,
+                    bincode::deserialize(&v)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+fn version_key(version: u64) -> Vec<u8> {
+    let mut key = VERSION_KEY_PREFIX.to_vec();
+    key.extend_from_slice(&version.to_be_bytes());
+    key
+}
+
+const VERSION_KEY_PREFIX: &[u8] = b"version/";
 const CURRENT_VERSION_KEY: &str = "current_version";
+const CURRENT_EDIT_KEY: &str = "current_edit";
+const SESSION_OPEN_KEY: &str = "session_open";
+const SESSION_KEY: &str = "session";
 
-fn u64_from_be_slice(s: &[u8]) -> u64 {
+/// Holds the encoded nodes of the `EditTimeline`, so edits can be undone in later sessions.
+#[derive(Clone)]
+pub struct EditHistoryDb {
+    tree: Tree,
+}
+
+impl EditHistoryDb {
+    pub fn new(tree: Tree) -> Self {
+        Self { tree }
+    }
+
+    pub fn read_nodes(&self) -> EditorResult<Vec<(u64, Vec<u8>)>> {
+        self.tree
+            .iter()
+            .map(|entry| {
+                let (k, v) = entry?;
+                Ok((u64_from_be_slice(&k)?, v.to_vec()))
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) -> EditorResult<()> {
+        Ok(self.tree.clear()?)
+    }
+
+    pub fn write_changes(&self, changes: HistoryChanges) -> EditorResult<()> {
+        let mut batch = sled::Batch::default();
+        for (id, node) in changes.written.into_iter() {
+            batch.insert(&id.to_be_bytes(), node);
+        }
+        for id in changes.removed.into_iter() {
+            batch.remove(&id.to_be_bytes());
+        }
+        Ok(self.tree.apply_batch(batch)?)
+    }
+}
+
+/// The sled trees behind the chunk database, which can be opened at any version.
+#[derive(Clone)]
+pub struct ChunkTrees {
+    chunks: Tree,
+    versions: Tree,
+    deltas: Tree,
+    codec: ChunkCodec,
+}
+
+impl ChunkTrees {
+    pub fn open_voxel_db(&self, version: u64) -> VoxelDb {
+        VoxelDb::new(VersionedChunkDb3::new_with_compression(
+            version,
+            self.chunks.clone(),
+            self.versions.clone(),
+            self.deltas.clone(),
+            match self.codec {
+                ChunkCodec::Lz4 { level } => {
+                    FastArrayCompressionNx2::from_bytes_compression(Lz4 { level })
+                }
+            },
+        ))
+    }
+
+    /// Copies the trees into a temporary database, which is gone once the copy is dropped.
+    /// Switching the copy to another version leaves these trees on the current one.
+    pub fn temporary_copy(&self) -> EditorResult<Self> {
+        let db = sled::Config::default().temporary(true).open()?;
+        let copy_tree = |tree: &Tree, name: &str| -> EditorResult<Tree> {
+            let copy = db.open_tree(name)?;
+            for entry in tree.iter() {
+                let (key, value) = entry?;
+                copy.insert(key, value)?;
+            }
+            Ok(copy)
+        };
+
+        Ok(Self {
+            chunks: copy_tree(&self.chunks, "chunks")?,
+            versions: copy_tree(&self.versions, "chunk_versions")?,
+            deltas: copy_tree(&self.deltas, "chunk_deltas")?,
+            codec: self.codec,
+        })
+    }
+}
+
+fn u64_from_be_slice(s: &[u8]) -> EditorResult<u64> {
     let mut bytes = [0u8; 8];
+    if s.len() != bytes.len() {
+        return Err(EditorError::Corrupt(format!(
+            "expected an 8 byte number, found {:?}",
+            s
+        )));
+    }
     bytes.copy_from_slice(s);
-    u64::from_be_bytes(bytes)
+
+    Ok(u64::from_be_bytes(bytes))
 }
 
-pub fn open_voxel_database(mut commands: Commands, config: Res<Config>) {
-    let db = sled::Config::default()
-        .path(config.database_path.clone())
-        .use_compression(false)
-        .mode(sled::Mode::LowSpace)
-        .open()
-        .expect("Failed to open world database");
-
-    let editor_tree = db
-        .open_tree("editor")
-        .expect("Failed to open saves database");
-    let editor_db = EditorDb::new(editor_tree);
-    let current_version = editor_db
-        .current_version()
-        .expect("Failed to read current version");
-
-    let chunks = db
-        .open_tree("chunks")
-        .expect("Failed to open chunk database");
-    let (chunk_versions, chunk_deltas) =
-        open_snapshot_forest(&db, "chunks").expect("Failed to open snapshot forest");
-
-    let current_version = current_version.unwrap_or_else(|| {
-        chunk_versions
-            .transaction(|versions| create_snapshot_tree(TransactionalVersionForest(versions)))
-            .expect("Failed to create initial chunk snapshot tree")
-    });
-    editor_db
-        .write_current_version(current_version)
-        .expect("Failed to write current version");
-
-    let voxel_db = VoxelDb::new(VersionedChunkDb3::new_with_compression(
+/// Opens the database at `Config::database_path`, or a copy of it with `Config::read_only`. If that
+/// fails, the error is reported and a temporary database is opened instead, so the editor keeps
+/// running, but nothing is saved.
+pub fn open_voxel_database(
+    mut commands: Commands,
+    config: Res<Config>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut errors: EventWriter<EditorError>,
+) {
+    let db = if config.read_only {
+        log::info!(
+            "Opening a copy of {}, nothing will be saved to it",
+            config.database_path
+        );
+        open_temporary_copy(&config.database_path, &config.storage)
+    } else {
+        open_sled(&config.database_path, &config.storage).map_err(|error| {
+            EditorError::OpenDatabase {
+                path: config.database_path.clone(),
+                error,
+            }
+        })
+    };
+    let opened =
+        db.and_then(|db| OpenedDatabase::open(&db, &mut edit_timeline, config.storage.chunk_codec));
+    let opened = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            errors.send(e);
+            log::warn!("Using a temporary database instead, nothing will be saved");
+            // Some of the history might have been loaded before the error.
+            *edit_timeline = EditTimeline::new(config.feldspar.map.chunk_shape());
+            let db = sled::Config::default()
+                .temporary(true)
+                .open()
+                .expect("Failed to open a temporary database");
+            OpenedDatabase::open(&db, &mut edit_timeline, config.storage.chunk_codec)
+                .expect("Failed to initialize a temporary database")
+        }
+    };
+
+    let OpenedDatabase {
+        editor_db,
+        history_db,
+        journal,
+        trees,
         current_version,
-        chunks,
-        chunk_versions,
-        chunk_deltas,
-        FastArrayCompressionNx2::from_bytes_compression(Lz4 { level: 10 }),
-    ));
+        pending_replay,
+        session,
+        is_new,
+    } = opened;
+    // Saves run in the background, so they get their own handle to the same trees.
+    let map_saver = MapSaver::new(
+        trees.open_voxel_db(current_version),
+        editor_db.clone(),
+        history_db.clone(),
+        journal.clone(),
+    );
+    let voxel_db = trees.open_voxel_db(current_version);
 
+    if let Some(pending_replay) = pending_replay {
+        commands.insert_resource(pending_replay);
+    }
+    if let Some(session) = session {
+        commands.insert_resource(PendingSessionRestore(session));
+    }
+    if is_new {
+        commands.insert_resource(PendingNewWorld);
+    }
     commands.insert_resource(editor_db);
+    commands.insert_resource(history_db);
+    commands.insert_resource(journal);
+    commands.insert_resource(map_saver);
     commands.insert_resource(voxel_db);
+    commands.insert_resource(trees);
+    commands.insert_resource(ChunkStreamer::new(config.feldspar.map.superchunk_exponent));
 }
 
-pub fn save_map_to_db(
-    voxel_db: Res<VoxelDb>,
-    voxel_map: ResMut<SdfVoxelMap>,
-    pool: Res<ComputeTaskPool>,
-    keys: Res<Input<KeyCode>>,
-) {
-    if !keys.just_pressed(KeyCode::S) {
-        return;
+/// Everything that `open_voxel_database` reads from the database before it inserts any resources,
+/// so a failure part of the way through doesn't leave a mix of two databases behind.
+struct OpenedDatabase {
+    editor_db: EditorDb,
+    history_db: EditHistoryDb,
+    journal: EditJournal,
+    trees: ChunkTrees,
+    current_version: u64,
+    pending_replay: Option<PendingJournalReplay>,
+    session: Option<EditorSession>,
+    is_new: bool,
+}
+
+impl OpenedDatabase {
+    fn open(
+        db: &sled::Db,
+        edit_timeline: &mut EditTimeline,
+        codec: ChunkCodec,
+    ) -> EditorResult<Self> {
+        let editor_db = EditorDb::new(db.open_tree("editor")?);
+        let current_version = editor_db.current_version()?;
+
+        let history_db = EditHistoryDb::new(db.open_tree("edit_history")?);
+        if let Some(current_edit) = editor_db.current_edit()? {
+            let nodes = history_db.read_nodes()?;
+            log::info!("Loading {} edit history nodes", nodes.len());
+            if let Err(e) = edit_timeline.load_history(current_edit, nodes.into_iter()) {
+                // The history is only a convenience, so don't refuse to open the map over it.
+                log::warn!("Discarding edit history that failed to decode: {}", e);
+                history_db.clear()?;
+            }
+        }
+
+        let journal = EditJournal::new(db.open_tree("edit_journal")?);
+        let left_unsaved = editor_db.session_open()?;
+        editor_db.write_session_open(true)?;
+        let mut pending_replay = None;
+        if left_unsaved {
+            let records = journal.records()?;
+            if !records.is_empty() {
+                log::warn!("Recovering the last session's unsaved edits from the journal");
+                pending_replay = Some(PendingJournalReplay(records));
+            }
+        } else {
+            // The last session only closed once everything was saved, so any records left here are
+            // already in the map.
+            journal.clear()?;
+        }
+
+        let session = editor_db.read_session().unwrap_or_else(|e| {
+            // Like the history, this isn't worth refusing to open the map over.
+            log::warn!("Discarding editor session: {}", e);
+            None
+        });
+
+        let chunks = db.open_tree("chunks")?;
+        let (chunk_versions, chunk_deltas) =
+            open_snapshot_forest(db, "chunks").map_err(EditorError::chunk_db)?;
+
+        let is_new = current_version.is_none();
+        let current_version = match current_version {
+            Some(version) => version,
+            None => chunk_versions
+                .transaction(|versions| create_snapshot_tree(TransactionalVersionForest(versions)))
+                .map_err(EditorError::chunk_db)?,
+        };
+        editor_db.write_current_version(current_version)?;
+        if editor_db.read_version(current_version)?.is_none() {
+            // Databases from before the version graph existed only have this one version.
+            editor_db.write_version(
+                current_version,
+                &VersionInfo::new(None, MAIN_BRANCH.to_string()),
+            )?;
+        }
+
+        Ok(Self {
+            editor_db,
+            history_db,
+            journal,
+            trees: ChunkTrees {
+                chunks,
+                versions: chunk_versions,
+                deltas: chunk_deltas,
+                codec,
+            },
+            current_version,
+            pending_replay,
+            session,
+            is_new,
+        })
     }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    log::info!("Saving map to DB");
+    #[test]
+    fn corrupt_keys_are_errors_instead_of_panics() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let tree = db.open_tree("editor").unwrap();
+        let editor_db = EditorDb::new(tree.clone());
 
-    let deltas: Vec<_> = voxel_map
-        .voxels
-        .storage()
-        .into_iter()
-        .map(|(k, v)| Delta::Insert(*k, v))
-        .collect();
+        editor_db.write_current_version(7).unwrap();
+        assert_eq!(editor_db.current_version().unwrap(), Some(7));
 
-    log::info!("Writing {} deltas", deltas.len());
+        tree.insert(CURRENT_VERSION_KEY, &[1, 2, 3]).unwrap();
+        assert!(matches!(
+            editor_db.current_version(),
+            Err(EditorError::Corrupt(_))
+        ));
 
-    let chunk_db = voxel_db.chunks();
-    let mut batch = chunk_db.start_delta_batch();
-    let compressed_future = batch.add_and_compress_deltas(deltas.into_iter());
-    pool.scope(|scope| scope.spawn(compressed_future));
-    let result = chunk_db.apply_deltas_to_current_version(batch.build());
-    if result.is_err() {
-        panic!("Error saving to DB: {:?}", result);
+        tree.insert(version_key(7), &[0xff]).unwrap();
+        assert!(matches!(
+            editor_db.read_version(7),
+            Err(EditorError::Encoding(_))
+        ));
+        assert!(editor_db.read_versions().is_err());
     }
-    futures::executor::block_on(chunk_db.flush()).expect("Failed to flush chunk DB");
 }
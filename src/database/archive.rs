@@ -0,0 +1,482 @@
+use super::{
+    forest_builder::{chunk_differences, read_all_lods, write_chunks, ForestBuilder},
+    world_db::{chunk_lods, open_sled, WorldDb},
+    EditorDb, VersionInfo,
+};
+
+use crate::{decode_chunk, encode_chunk, ChunkCodec, EditorError, EditorResult, StorageConfig};
+
+use feldspar::{
+    bb::{prelude::*, storage::database::sled},
+    prelude::SdfArray,
+};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// A world in a single file, so it can be sent around or checked in. The file starts with
+/// `ARCHIVE_MAGIC` and an `ArchiveHeader`, followed by `num_versions` versions, each written as
+/// the chunks that changed since its parent, parents first.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchiveHeader {
+    pub format_version: u32,
+    pub chunk_shape: [i32; 3],
+    /// One more than the highest LOD with chunks in any of the versions.
+    pub num_lods: u8,
+    pub compression: ArchiveCompression,
+    /// Whether every version is included, or only the current one.
+    pub full_history: bool,
+    pub num_versions: usize,
+    /// The number of the current version in the archive.
+    pub current_version: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ArchiveCompression {
+    /// The voxels of each chunk as runs of `(length, voxel type, distance)`.
+    RunLength,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ArchivedVersion {
+    number: u64,
+    info: VersionInfo,
+    /// The LOD and minimum of every chunk that was removed.
+    removed: Vec<(u8, [i32; 3])>,
+    /// Every chunk that was added or changed.
+    chunks: Vec<ArchivedChunk>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ArchivedChunk {
+    lod: u8,
+    minimum: [i32; 3],
+    runs: Vec<(u32, u8, i8)>,
+}
+
+const ARCHIVE_MAGIC: &[u8; 8] = b"FSPRWRLD";
+/// Version 2 added the merged version to the version info.
+const ARCHIVE_FORMAT_VERSION: u32 = 2;
+
+/// Writes the current version of the database at `db_path`, or every version if `full_history`,
+/// to a new archive at `archive_path`.
+pub fn export_world_archive(
+    db_path: &str,
+    storage: &StorageConfig,
+    archive_path: &str,
+    chunk_shape: Point3i,
+    full_history: bool,
+) -> EditorResult<ArchiveHeader> {
+    let world = WorldDb::open_read_only(db_path, storage)?;
+    let (versions, current) = world.versions()?;
+    let versions = if full_history {
+        versions
+    } else {
+        let info = versions
+            .into_iter()
+            .find(|(v, _)| *v == current)
+            .map(|(_, info)| info)
+            .ok_or_else(|| {
+                EditorError::Corrupt(format!("current version {} has no version info", current))
+            })?;
+        vec![(
+            current,
+            VersionInfo {
+                parent: None,
+                merged: None,
+                ..info
+            },
+        )]
+    };
+
+    let mut num_lods = 0;
+    for (version, _) in versions.iter() {
+        let lods = world.with_version(*version, |_| chunk_lods(&world.trees.chunks))?;
+        if let Some(highest) = lods.into_iter().next_back() {
+            num_lods = num_lods.max(highest.saturating_add(1));
+        }
+    }
+
+    let header = ArchiveHeader {
+        format_version: ARCHIVE_FORMAT_VERSION,
+        chunk_shape: chunk_shape.0,
+        num_lods,
+        compression: ArchiveCompression::RunLength,
+        full_history,
+        num_versions: versions.len(),
+        current_version: current,
+    };
+    let file = File::create(archive_path).map_err(io_error)?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(ARCHIVE_MAGIC).map_err(io_error)?;
+    bincode::serialize_into(&mut writer, &header)?;
+
+    for (version, info) in versions.iter() {
+        let read_version = |v| world.with_version(v, |db| read_all_lods(db, &world.trees.chunks));
+        let chunks = read_version(*version)?;
+        let parent_chunks = match info.parent {
+            Some(parent) => read_version(parent)?,
+            None => HashMap::new(),
+        };
+        let (removed, changed) = chunk_differences(&parent_chunks, &chunks);
+        let archived = ArchivedVersion {
+            number: *version,
+            info: info.clone(),
+            removed: removed.iter().map(|key| (key.lod, key.minimum.0)).collect(),
+            chunks: changed
+                .into_iter()
+                .map(|(key, chunk)| ArchivedChunk {
+                    lod: key.lod,
+                    minimum: key.minimum.0,
+                    runs: run_length_encode(&encode_chunk(chunk)),
+                })
+                .collect(),
+        };
+        bincode::serialize_into(&mut writer, &archived)?;
+    }
+    writer.flush().map_err(io_error)?;
+
+    Ok(header)
+}
+
+/// Creates a new database at `db_path` from the archive at `archive_path`. Versions are
+/// renumbered, but keep their branches, messages and timestamps. The undo history isn't part of
+/// the archive.
+///
+/// `chunk_shape` and `num_lods` come from the config that the world will be opened with, so the
+/// archive has to have the same chunk shape, and can't have more LODs.
+pub fn import_world_archive(
+    archive_path: &str,
+    db_path: &str,
+    storage: &StorageConfig,
+    chunk_shape: Point3i,
+    num_lods: u8,
+) -> EditorResult<ArchiveHeader> {
+    if Path::new(db_path).exists() {
+        return Err(EditorError::Archive(format!(
+            "{} already exists, import into a new path",
+            db_path
+        )));
+    }
+
+    let file = File::open(archive_path).map_err(io_error)?;
+    let mut reader = BufReader::new(file);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic).map_err(io_error)?;
+    if &magic != ARCHIVE_MAGIC {
+        return Err(EditorError::Archive(format!(
+            "{} is not a world archive",
+            archive_path
+        )));
+    }
+    let header: ArchiveHeader = bincode::deserialize_from(&mut reader)?;
+    if header.format_version != ARCHIVE_FORMAT_VERSION {
+        return Err(EditorError::Archive(format!(
+            "format version {} isn't supported by this editor, which reads version {}",
+            header.format_version, ARCHIVE_FORMAT_VERSION
+        )));
+    }
+    if header.chunk_shape != chunk_shape.0 {
+        return Err(EditorError::Archive(format!(
+            "the archive has {:?} chunks, but the config has {:?}",
+            header.chunk_shape, chunk_shape.0
+        )));
+    }
+    if header.num_lods > num_lods {
+        return Err(EditorError::Archive(format!(
+            "the archive has {} LODs, but the config only has {}",
+            header.num_lods, num_lods
+        )));
+    }
+
+    // Only move the database into place once it's complete.
+    let importing_path = format!("{}.importing", db_path);
+    let imported = open_sled(&importing_path, storage)
+        .map_err(EditorError::from)
+        .and_then(|db| {
+            import_versions(&mut reader, &db, &header, storage.chunk_codec)?;
+            db.flush()?;
+            Ok(())
+        });
+    if let Err(e) = imported {
+        // Don't leave half of a world behind.
+        let _ = std::fs::remove_dir_all(&importing_path);
+        return Err(e);
+    }
+    std::fs::rename(&importing_path, db_path).map_err(io_error)?;
+
+    Ok(header)
+}
+
+fn import_versions(
+    reader: &mut impl Read,
+    db: &sled::Db,
+    header: &ArchiveHeader,
+    codec: ChunkCodec,
+) -> EditorResult<()> {
+    let chunk_shape = PointN(header.chunk_shape);
+    let editor_db = EditorDb::new(db.open_tree("editor")?);
+    let mut builder = ForestBuilder::new(db, codec)?;
+    let mut new_numbers = HashMap::new();
+    let mut infos = Vec::new();
+    for _ in 0..header.num_versions {
+        let archived: ArchivedVersion = bincode::deserialize_from(&mut *reader)?;
+        let new_parent = match archived.info.parent {
+            Some(parent) => Some(*new_numbers.get(&parent).ok_or_else(|| {
+                EditorError::Archive(format!(
+                    "version {} comes before its parent {}",
+                    archived.number, parent
+                ))
+            })?),
+            None => None,
+        };
+        let mut lods = archived
+            .removed
+            .iter()
+            .map(|(lod, _)| *lod)
+            .chain(archived.chunks.iter().map(|chunk| chunk.lod));
+        if let Some(lod) = lods.find(|lod| *lod >= header.num_lods) {
+            return Err(EditorError::Archive(format!(
+                "version {} has chunks at LOD {}, but the header says there are {} LODs",
+                archived.number, lod, header.num_lods
+            )));
+        }
+        let (new_version, voxel_db) = builder.add_version(new_parent)?;
+
+        let chunks = archived
+            .chunks
+            .into_iter()
+            .map(|chunk| {
+                let key = ChunkKey::new(chunk.lod, PointN(chunk.minimum));
+                let extent = Extent3i::from_min_and_shape(key.minimum, chunk_shape);
+                let voxels = run_length_decode(chunk.runs, extent.num_points() as u64)?;
+
+                let chunk = decode_chunk(voxels, extent)
+                    .map_err(|e| EditorError::Archive(e.to_string()))?;
+
+                Ok((key, chunk))
+            })
+            .collect::<EditorResult<Vec<(ChunkKey3, SdfArray)>>>()?;
+        write_chunks(
+            &voxel_db,
+            archived
+                .removed
+                .into_iter()
+                .map(|(lod, minimum)| ChunkKey::new(lod, PointN(minimum))),
+            chunks.iter().map(|(key, chunk)| (*key, chunk)),
+        )?;
+
+        infos.push((
+            new_version,
+            VersionInfo {
+                parent: new_parent,
+                ..archived.info
+            },
+        ));
+        new_numbers.insert(archived.number, new_version);
+    }
+    // A merged version can come after the merge commit, so it's renumbered once all are known.
+    for (version, mut info) in infos.into_iter() {
+        info.merged = info.merged.and_then(|m| new_numbers.get(&m).cloned());
+        editor_db.write_version(version, &info)?;
+    }
+
+    let current = *new_numbers.get(&header.current_version).ok_or_else(|| {
+        EditorError::Archive(format!(
+            "the current version {} is missing",
+            header.current_version
+        ))
+    })?;
+    builder.checkout(current)?;
+    editor_db.write_current_version(current)
+}
+
+fn run_length_encode(voxels: &[(u8, i8)]) -> Vec<(u32, u8, i8)> {
+    let mut runs: Vec<(u32, u8, i8)> = Vec::new();
+    for &(voxel_type, dist) in voxels.iter() {
+        match runs.last_mut() {
+            Some((length, t, d)) if *t == voxel_type && *d == dist => *length += 1,
+            _ => runs.push((1, voxel_type, dist)),
+        }
+    }
+
+    runs
+}
+
+/// Fails unless the runs add up to exactly `num_points` voxels, since a chunk with too few would
+/// otherwise be padded with empty space.
+fn run_length_decode(runs: Vec<(u32, u8, i8)>, num_points: u64) -> EditorResult<Vec<(u8, i8)>> {
+    let length: u64 = runs.iter().map(|(length, _, _)| *length as u64).sum();
+    if length != num_points {
+        return Err(EditorError::Archive(format!(
+            "a chunk has {} voxels instead of {}",
+            length, num_points
+        )));
+    }
+
+    Ok(runs
+        .into_iter()
+        .flat_map(|(length, voxel_type, dist)| {
+            std::iter::repeat((voxel_type, dist)).take(length as usize)
+        })
+        .collect())
+}
+
+fn io_error(e: io::Error) -> EditorError {
+    EditorError::Archive(e.to_string())
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::forest_builder::tests::{
+        solid_voxels, write_test_world, Voxels, CHUNK_SHAPE,
+    };
+
+    #[test]
+    fn run_length_round_trip() {
+        let voxels = vec![(0, 1), (0, 1), (0, 1), (2, -1), (2, -1), (0, 1)];
+        let runs = run_length_encode(&voxels);
+
+        assert_eq!(runs, vec![(3, 0, 1), (2, 2, -1), (1, 0, 1)]);
+        assert_eq!(run_length_decode(runs, 6).unwrap(), voxels);
+        assert!(run_length_encode(&[]).is_empty());
+    }
+
+    #[test]
+    fn runs_of_the_wrong_length_are_errors() {
+        let runs = vec![(3, 0, 1), (2, 2, -1)];
+
+        assert!(matches!(
+            run_length_decode(runs.clone(), 6),
+            Err(EditorError::Archive(_))
+        ));
+        assert!(matches!(
+            run_length_decode(runs, 4),
+            Err(EditorError::Archive(_))
+        ));
+    }
+
+    fn read_world(path: &str, storage: &StorageConfig) -> Vec<(u64, VersionInfo, Voxels)> {
+        let world = WorldDb::open(path, storage).unwrap();
+        let (versions, _) = world.versions().unwrap();
+        versions
+            .into_iter()
+            .map(|(version, info)| {
+                let chunks = world
+                    .with_version(version, |db| read_all_lods(db, &world.trees.chunks))
+                    .unwrap();
+                (version, info, solid_voxels(&chunks))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn export_and_import_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "feldspar_editor_round_trip_test_{}",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let storage = StorageConfig::default();
+        let test_world = {
+            let db = open_sled(path, &storage).unwrap();
+            let test_world = write_test_world(&db, storage.chunk_codec, 0).unwrap();
+            db.flush().unwrap();
+            test_world
+        };
+        let original = read_world(path, &storage);
+
+        for &full_history in [true, false].iter() {
+            let archive_path = format!("{}.fsw", path);
+            let imported_path = format!("{}.imported", path);
+            let header =
+                export_world_archive(path, &storage, &archive_path, CHUNK_SHAPE, full_history)
+                    .unwrap();
+            assert_eq!(header.num_lods, 1);
+            import_world_archive(&archive_path, &imported_path, &storage, CHUNK_SHAPE, 1).unwrap();
+
+            let imported = read_world(&imported_path, &storage);
+            let imported_current = WorldDb::open(&imported_path, &storage)
+                .unwrap()
+                .versions()
+                .unwrap()
+                .1;
+            if full_history {
+                assert_eq!(imported.len(), original.len());
+                let new_numbers: HashMap<u64, u64> = original
+                    .iter()
+                    .zip(imported.iter())
+                    .map(|((old, _, _), (new, _, _))| (*old, *new))
+                    .collect();
+                for ((_, old_info, old_voxels), (_, new_info, new_voxels)) in
+                    original.iter().zip(imported.iter())
+                {
+                    assert_eq!(new_voxels, old_voxels);
+                    assert_eq!(new_info.branch, old_info.branch);
+                    assert_eq!(new_info.message, old_info.message);
+                    assert_eq!(new_info.timestamp, old_info.timestamp);
+                    assert_eq!(new_info.parent, old_info.parent.map(|p| new_numbers[&p]));
+                    assert_eq!(new_info.merged, old_info.merged.map(|m| new_numbers[&m]));
+                }
+                assert_eq!(imported_current, new_numbers[&test_world.current]);
+            } else {
+                let (_, current_voxels) = test_world.versions.last().unwrap();
+                assert_eq!(imported.len(), 1);
+                let (version, info, voxels) = &imported[0];
+                assert_eq!(voxels, current_voxels);
+                assert_eq!((info.parent, info.merged), (None, None));
+                assert_eq!(imported_current, *version);
+            }
+
+            // The archive can't be imported into a config with fewer LODs.
+            let result = import_world_archive(
+                &archive_path,
+                &format!("{}.no_lods", path),
+                &storage,
+                CHUNK_SHAPE,
+                0,
+            );
+            assert!(matches!(result, Err(EditorError::Archive(_))));
+
+            std::fs::remove_file(&archive_path).unwrap();
+            std::fs::remove_dir_all(&imported_path).unwrap();
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn import_refuses_other_files() {
+        let path = std::env::temp_dir().join(format!(
+            "feldspar_editor_archive_test_{}",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, b"not an archive").unwrap();
+
+        let db_path = format!("{}.db", path);
+        let result = import_world_archive(
+            path,
+            &db_path,
+            &StorageConfig::default(),
+            PointN([16; 3]),
+            1,
+        );
+        assert!(matches!(result, Err(EditorError::Archive(_))));
+        assert!(!Path::new(&db_path).exists());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use super::{
+    forest_builder::{read_all_lods, ForestBuilder},
+    world_db::{chunk_lods, sled_config, WorldDb},
+};
+
+use crate::{EditorError, EditorResult, StorageConfig};
+
+use feldspar::bb::{prelude::*, storage::database::Delta};
+
+use std::time::Instant;
+
+/// How well one set of storage settings did on the chunks of a world.
+#[derive(Clone, Copy, Debug)]
+pub struct StorageBenchmark {
+    pub storage: StorageConfig,
+    pub num_chunks: usize,
+    /// The size of the chunks in memory.
+    pub raw_bytes: u64,
+    /// The size of the chunks after the chunk codec compressed them.
+    pub compressed_bytes: u64,
+    /// The size of the whole database, after sled compressed it too, if it does.
+    pub size_on_disk: u64,
+    /// The time it took to compress every chunk and write it to disk.
+    pub encode_secs: f64,
+    /// The time it took to read and decompress every chunk.
+    pub decode_secs: f64,
+}
+
+impl StorageBenchmark {
+    pub fn compression_ratio(&self) -> f64 {
+        self.raw_bytes as f64 / self.compressed_bytes.max(1) as f64
+    }
+
+    /// Uncompressed megabytes per second.
+    pub fn encode_throughput(&self) -> f64 {
+        throughput(self.raw_bytes, self.encode_secs)
+    }
+
+    /// Uncompressed megabytes per second.
+    pub fn decode_throughput(&self) -> f64 {
+        throughput(self.raw_bytes, self.decode_secs)
+    }
+}
+
+fn throughput(bytes: u64, secs: f64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / secs.max(f64::EPSILON)
+}
+
+/// Writes the current version of `world` into a temporary database with each of `options`, and
+/// measures how long it takes and how much space it uses. `world` isn't changed.
+///
+/// Every option gets its own result, since sled compression fails without sled's `compression`
+/// feature.
+pub fn benchmark_storage(
+    world: &WorldDb,
+    options: &[StorageConfig],
+) -> EditorResult<Vec<EditorResult<StorageBenchmark>>> {
+    let (_, current) = world.versions()?;
+    let chunks = world.with_version(current, |voxel_db| {
+        read_all_lods(voxel_db, &world.trees.chunks)
+    })?;
+    let raw_bytes = chunks
+        .values()
+        .map(|chunk| chunk.extent().num_points() as u64 * 2)
+        .sum();
+
+    Ok(options
+        .iter()
+        .map(|&storage| {
+            let db = sled_config(&storage).temporary(true).open()?;
+            let mut builder = ForestBuilder::new(&db, storage.chunk_codec)?;
+            let (_, voxel_db) = builder.add_version(None)?;
+            let chunk_db = voxel_db.chunks();
+
+            let deltas = chunks
+                .iter()
+                .map(|(key, chunk)| Delta::Insert(*key, MaybeCompressed::Decompressed(chunk)));
+            let mut batch = chunk_db.start_delta_batch();
+            let start = Instant::now();
+            futures::executor::block_on(batch.add_and_compress_deltas(deltas));
+            chunk_db
+                .apply_deltas_to_current_version(batch.build())
+                .map_err(EditorError::chunk_db)?;
+            // sled compresses when it writes to disk.
+            db.flush()?;
+            let encode_secs = start.elapsed().as_secs_f64();
+
+            let mut compressed_bytes = 0;
+            for entry in builder.trees.chunks.iter() {
+                let (_, value) = entry?;
+                compressed_bytes += value.len() as u64;
+            }
+
+            let start = Instant::now();
+            for lod in chunk_lods(&builder.trees.chunks)?.into_iter() {
+                for (_, chunk) in chunk_db
+                    .read_all_chunks(lod)
+                    .map_err(EditorError::chunk_db)?
+                    .into_iter()
+                {
+                    chunk.decompress();
+                }
+            }
+            let decode_secs = start.elapsed().as_secs_f64();
+
+            Ok(StorageBenchmark {
+                storage,
+                num_chunks: chunks.len(),
+                raw_bytes,
+                compressed_bytes,
+                size_on_disk: db.size_on_disk()?,
+                encode_secs,
+                decode_secs,
+            })
+        })
+        .collect())
+}
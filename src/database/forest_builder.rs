@@ -0,0 +1,351 @@
+use super::{world_db::chunk_lods, ChunkTrees};
+
+use crate::{ChunkCodec, EditorError, EditorResult};
+
+use feldspar::{
+    bb::{
+        prelude::*,
+        storage::database::{
+            sled,
+            sled_snapshots::{
+                open_snapshot_forest,
+                transactions::{create_child_version, create_snapshot_tree},
+                TransactionalVersionForest,
+            },
+            Delta,
+        },
+    },
+    prelude::{SdfArray, VoxelDb, VoxelType},
+};
+
+use std::collections::HashMap;
+
+/// Fills the chunk trees of a fresh database one version at a time, for when versions are copied
+/// over from somewhere else. Parents have to be added before their children.
+pub(super) struct ForestBuilder {
+    pub trees: ChunkTrees,
+    checked_out: Option<u64>,
+}
+
+impl ForestBuilder {
+    pub fn new(db: &sled::Db, codec: ChunkCodec) -> EditorResult<Self> {
+        let (versions, deltas) =
+            open_snapshot_forest(db, "chunks").map_err(EditorError::chunk_db)?;
+
+        Ok(Self {
+            trees: ChunkTrees {
+                chunks: db.open_tree("chunks")?,
+                versions,
+                deltas,
+                codec,
+            },
+            checked_out: None,
+        })
+    }
+
+    /// Creates a version after `parent`, or a new root, and checks it out. It starts out with the
+    /// same chunks as its parent.
+    pub fn add_version(&mut self, parent: Option<u64>) -> EditorResult<(u64, VoxelDb)> {
+        let version = self
+            .trees
+            .versions
+            .transaction(|versions| {
+                let forest = TransactionalVersionForest(versions);
+                match parent {
+                    Some(parent) => create_child_version(forest, parent),
+                    None => create_snapshot_tree(forest),
+                }
+            })
+            .map_err(EditorError::chunk_db)?;
+
+        Ok((version, self.checkout(version)?))
+    }
+
+    pub fn checkout(&mut self, version: u64) -> EditorResult<VoxelDb> {
+        let voxel_db = self
+            .trees
+            .open_voxel_db(self.checked_out.unwrap_or(version));
+        voxel_db
+            .chunks()
+            .switch_version(version)
+            .map_err(EditorError::chunk_db)?;
+        self.checked_out = Some(version);
+
+        Ok(self.trees.open_voxel_db(version))
+    }
+}
+
+/// Every chunk of every LOD in the version that `voxel_db` is on.
+pub(super) fn read_all_lods(
+    voxel_db: &VoxelDb,
+    chunks_tree: &sled::Tree,
+) -> EditorResult<HashMap<ChunkKey3, SdfArray>> {
+    let mut chunks = HashMap::new();
+    for lod in chunk_lods(chunks_tree)?.into_iter() {
+        let lod_chunks = voxel_db
+            .chunks()
+            .read_all_chunks(lod)
+            .map_err(EditorError::chunk_db)?;
+        chunks.extend(
+            lod_chunks
+                .into_iter()
+                .map(|(key, chunk)| (key, chunk.decompress())),
+        );
+    }
+
+    Ok(chunks)
+}
+
+/// The chunks that were removed between `old_chunks` and `new_chunks`, and the ones that were
+/// added or changed.
+pub(super) fn chunk_differences<'a>(
+    old_chunks: &'a HashMap<ChunkKey3, SdfArray>,
+    new_chunks: &'a HashMap<ChunkKey3, SdfArray>,
+) -> (Vec<ChunkKey3>, Vec<(ChunkKey3, &'a SdfArray)>) {
+    let removed = old_chunks
+        .keys()
+        .filter(|key| !new_chunks.contains_key(key))
+        .cloned()
+        .collect();
+    let changed = new_chunks
+        .iter()
+        .filter(|(key, chunk)| {
+            old_chunks
+                .get(key)
+                .map(|old_chunk| !same_voxels(old_chunk, chunk))
+                .unwrap_or(true)
+        })
+        .map(|(key, chunk)| (*key, chunk))
+        .collect();
+
+    (removed, changed)
+}
+
+/// Writes to the version that `voxel_db` is on.
+pub(super) fn write_chunks<'a>(
+    voxel_db: &VoxelDb,
+    removed: impl Iterator<Item = ChunkKey3>,
+    changed: impl Iterator<Item = (ChunkKey3, &'a SdfArray)>,
+) -> EditorResult<()> {
+    let deltas = removed.map(Delta::Remove).chain(
+        changed.map(|(key, chunk)| Delta::Insert(key, MaybeCompressed::Decompressed(chunk))),
+    );
+
+    let chunk_db = voxel_db.chunks();
+    let mut batch = chunk_db.start_delta_batch();
+    futures::executor::block_on(batch.add_and_compress_deltas(deltas));
+    chunk_db
+        .apply_deltas_to_current_version(batch.build())
+        .map_err(EditorError::chunk_db)
+}
+
+fn same_voxels(a: &SdfArray, b: &SdfArray) -> bool {
+    if a.extent() != b.extent() {
+        return false;
+    }
+
+    let mut same = true;
+    a.for_each(a.extent(), |p: Point3i, a_voxel: (VoxelType, Sd8)| {
+        let b_voxel: (VoxelType, Sd8) = b.get(p);
+        same &= a_voxel == b_voxel;
+    });
+
+    same
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    use crate::database::{EditorDb, VersionInfo};
+
+    use feldspar::prelude::ambient_sdf_array;
+
+    use std::collections::BTreeSet;
+
+    pub const CHUNK_SHAPE: Point3i = PointN([4; 3]);
+
+    /// The solid voxels of a version, as positions and voxel types.
+    pub type Voxels = BTreeSet<([i32; 3], u8)>;
+
+    pub struct TestWorld {
+        /// Every version in order of creation, with its solid voxels.
+        pub versions: Vec<(u64, Voxels)>,
+        pub current: u64,
+    }
+
+    impl TestWorld {
+        /// The number of the `i`th version that was created.
+        pub fn version(&self, i: usize) -> u64 {
+            self.versions[i].0
+        }
+    }
+
+    /// Writes a world to `db` with six versions on three branches, where every version has
+    /// different chunks:
+    ///
+    /// ```text
+    /// 0 main - 1 cave - 2 cave ------ 4 cave (merges 3) - 5 cave (current)
+    ///            \- 3 tunnel ---------/
+    /// ```
+    ///
+    /// Version 1 also gets `garbage_chunks` chunks of random voxels above the others, which its
+    /// children remove again, so that only version 1 takes up that space. They aren't part of its
+    /// `Voxels`.
+    pub fn write_test_world(
+        db: &sled::Db,
+        codec: ChunkCodec,
+        garbage_chunks: usize,
+    ) -> EditorResult<TestWorld> {
+        type Spec<'a> = (
+            Option<usize>,
+            Option<usize>,
+            &'a str,
+            Option<&'a str>,
+            &'a [([i32; 3], u8)],
+        );
+        let specs: [Spec; 6] = [
+            (
+                None,
+                None,
+                "main",
+                Some("ground"),
+                &[([0; 3], 1), ([1, 0, 0], 1)],
+            ),
+            (
+                Some(0),
+                None,
+                "cave",
+                Some("dig"),
+                &[([0; 3], 1), ([4, 0, 0], 2)],
+            ),
+            (
+                Some(1),
+                None,
+                "cave",
+                Some("deeper"),
+                &[([0; 3], 1), ([4, 0, 0], 2), ([0, 4, 0], 3)],
+            ),
+            // Removes the chunk at [4, 0, 0].
+            (
+                Some(1),
+                None,
+                "tunnel",
+                Some("tunnel"),
+                &[([0; 3], 1), ([8, 0, 0], 1)],
+            ),
+            (
+                Some(2),
+                Some(3),
+                "cave",
+                Some("merge tunnel"),
+                &[([0; 3], 1), ([0, 4, 0], 3), ([8, 0, 0], 1)],
+            ),
+            (
+                Some(4),
+                None,
+                "cave",
+                None,
+                &[([0; 3], 2), ([0, 4, 0], 3), ([8, 0, 0], 1)],
+            ),
+        ];
+
+        let editor_db = EditorDb::new(db.open_tree("editor")?);
+        let mut builder = ForestBuilder::new(db, codec)?;
+        let mut versions: Vec<(u64, Voxels)> = Vec::new();
+        let mut version_chunks: Vec<HashMap<ChunkKey3, SdfArray>> = Vec::new();
+        let no_chunks = HashMap::new();
+        for (index, (parent, merged, branch, message, solid)) in specs.iter().enumerate() {
+            let parent_version = parent.map(|i| versions[i].0);
+            let (version, voxel_db) = builder.add_version(parent_version)?;
+
+            let mut chunks = chunks_with(solid);
+            if index == 1 {
+                chunks.extend(random_chunks(garbage_chunks));
+            }
+            let parent_chunks = parent.map(|i| &version_chunks[i]).unwrap_or(&no_chunks);
+            let (removed, changed) = chunk_differences(parent_chunks, &chunks);
+            write_chunks(&voxel_db, removed.into_iter(), changed.into_iter())?;
+
+            let mut info = VersionInfo::new(parent_version, branch.to_string());
+            info.merged = merged.map(|i| versions[i].0);
+            info.message = message.map(str::to_string);
+            editor_db.write_version(version, &info)?;
+
+            versions.push((version, solid.iter().cloned().collect()));
+            version_chunks.push(chunks);
+        }
+        // The last version is still checked out.
+        let current = versions.last().unwrap().0;
+        editor_db.write_current_version(current)?;
+
+        Ok(TestWorld { versions, current })
+    }
+
+    fn chunks_with(solid: &[([i32; 3], u8)]) -> HashMap<ChunkKey3, SdfArray> {
+        let indexer = ChunkIndexer3::new(CHUNK_SHAPE);
+        let mut chunks = HashMap::new();
+        for (p, voxel_type) in solid.iter() {
+            let p = PointN(*p);
+            let chunk_min = indexer.min_of_chunk_containing_point(p);
+            let chunk = chunks
+                .entry(ChunkKey::new(0, chunk_min))
+                .or_insert_with(|| ambient_sdf_array(indexer.extent_for_chunk_with_min(chunk_min)));
+            let (t, d) = chunk.get_mut(p);
+            *t = VoxelType(*voxel_type);
+            *d = Sd8(-1);
+        }
+
+        chunks
+    }
+
+    /// Starts at a height of 64, out of the way of the chunks in the version specs.
+    fn random_chunks(num_chunks: usize) -> Vec<(ChunkKey3, SdfArray)> {
+        let indexer = ChunkIndexer3::new(CHUNK_SHAPE);
+        // Xorshift, so the voxels don't compress well.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random_byte = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        };
+
+        (0..num_chunks as i32)
+            .map(|i| {
+                let chunk_min = PointN([i % 32, 16 + i / 32 / 32, i / 32 % 32]) * CHUNK_SHAPE;
+                let extent = indexer.extent_for_chunk_with_min(chunk_min);
+                let mut chunk = ambient_sdf_array(extent);
+                chunk.for_each_mut(
+                    &extent,
+                    |_p: Point3i, (t, d): (&mut VoxelType, &mut Sd8)| {
+                        *t = VoxelType(random_byte());
+                        *d = Sd8(random_byte() as i8);
+                    },
+                );
+
+                (ChunkKey::new(0, chunk_min), chunk)
+            })
+            .collect()
+    }
+
+    pub fn solid_voxels(chunks: &HashMap<ChunkKey3, SdfArray>) -> Voxels {
+        let mut voxels = Voxels::new();
+        for chunk in chunks.values() {
+            chunk.for_each(chunk.extent(), |p: Point3i, (t, _d): (VoxelType, Sd8)| {
+                if t.0 != 0 {
+                    voxels.insert((p.0, t.0));
+                }
+            });
+        }
+
+        voxels
+    }
+}
@@ -0,0 +1,313 @@
+use super::{
+    forest_builder::{chunk_differences, read_all_lods, write_chunks, ForestBuilder},
+    world_db::{open_sled, replace_database, WorldDb},
+    EditorDb, VersionInfo,
+};
+
+use crate::{EditorError, EditorResult, StorageConfig};
+
+use feldspar::bb::storage::database::sled;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+/// Which versions survive `collect_garbage`. The current version and the newest version of every
+/// branch are always kept, because those are the ones with names.
+#[derive(Clone, Copy, Debug)]
+pub struct RetentionPolicy {
+    /// The number of most recently created versions to keep on top of the named ones.
+    pub keep_last: usize,
+}
+
+impl RetentionPolicy {
+    pub fn retained_versions(
+        &self,
+        versions: &[(u64, VersionInfo)],
+        current: u64,
+    ) -> BTreeSet<u64> {
+        let mut retained: BTreeSet<u64> = versions
+            .iter()
+            .rev()
+            .take(self.keep_last)
+            .map(|(v, _)| *v)
+            .collect();
+        retained.insert(current);
+
+        // Versions are listed in order of creation, so the last one of each branch wins.
+        let mut branch_tips = HashMap::new();
+        for (version, info) in versions.iter() {
+            branch_tips.insert(info.branch.as_str(), *version);
+        }
+        retained.extend(branch_tips.values());
+
+        retained
+    }
+}
+
+#[derive(Debug)]
+pub struct GcReport {
+    /// The versions that were removed, or that would be removed by a dry run.
+    pub removed_versions: Vec<u64>,
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+impl GcReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.old_size.saturating_sub(self.new_size)
+    }
+}
+
+/// Rebuilds the database at `path` with only the versions that `policy` retains, so that the
+/// snapshot forest no longer holds any deltas for the others. A retained version whose parent was
+/// removed is attached to its closest retained ancestor instead.
+///
+/// Versions get new numbers in the rebuilt database, but they keep their branches, messages and
+/// timestamps, and everything besides the chunks is copied as it is.
+pub fn collect_garbage(
+    path: &str,
+    storage: &StorageConfig,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> EditorResult<GcReport> {
+    let collected_path = format!("{}.gc", path);
+    let (removed_versions, old_size) = {
+        let world = WorldDb::open(path, storage)?;
+        let (versions, current) = world.versions()?;
+        let retained = policy.retained_versions(&versions, current);
+        let removed_versions: Vec<u64> = versions
+            .iter()
+            .map(|(v, _)| *v)
+            .filter(|v| !retained.contains(v))
+            .collect();
+        let old_size = world.size_on_disk()?;
+        if removed_versions.is_empty() || dry_run {
+            return Ok(GcReport {
+                removed_versions,
+                old_size,
+                new_size: old_size,
+            });
+        }
+
+        if Path::new(&collected_path).exists() {
+            // Left behind by a collection that failed.
+            std::fs::remove_dir_all(&collected_path)
+                .map_err(|e| EditorError::Database(sled::Error::Io(e)))?;
+        }
+        let new_db = open_sled(&collected_path, storage)?;
+        rebuild(&world, &new_db, &versions, current, &retained)?;
+        new_db.flush()?;
+
+        (removed_versions, old_size)
+    };
+    replace_database(path, &collected_path)?;
+    let new_size = WorldDb::open(path, storage)?.size_on_disk()?;
+
+    Ok(GcReport {
+        removed_versions,
+        old_size,
+        new_size,
+    })
+}
+
+fn rebuild(
+    world: &WorldDb,
+    new_db: &sled::Db,
+    versions: &[(u64, VersionInfo)],
+    current: u64,
+    retained: &BTreeSet<u64>,
+) -> EditorResult<()> {
+    let chunk_tree_names: HashSet<_> = [
+        &world.trees.chunks,
+        &world.trees.versions,
+        &world.trees.deltas,
+    ]
+    .iter()
+    .map(|tree| tree.name())
+    .collect();
+    for name in world.db.tree_names().into_iter() {
+        if chunk_tree_names.contains(&name) {
+            continue;
+        }
+        let old_tree = world.db.open_tree(&name)?;
+        let new_tree = new_db.open_tree(&name)?;
+        for entry in old_tree.iter() {
+            let (key, value) = entry?;
+            new_tree.insert(key, value)?;
+        }
+    }
+
+    // The version info is written again under the new version numbers.
+    let editor_db = EditorDb::new(new_db.open_tree("editor")?);
+    for (version, _) in versions.iter() {
+        editor_db.remove_version(*version)?;
+    }
+
+    let mut builder = ForestBuilder::new(new_db, world.trees.codec)?;
+    let parents: HashMap<u64, Option<u64>> =
+        versions.iter().map(|(v, info)| (*v, info.parent)).collect();
+    let mut new_numbers = HashMap::new();
+    let mut infos = Vec::new();
+    for (version, info) in versions.iter().filter(|(v, _)| retained.contains(v)) {
+        // Parents are created before their children, so the parent was already rebuilt.
+        let new_parent =
+            retained_ancestor(&parents, info.parent, retained).map(|p| new_numbers[&p]);
+        let (new_version, voxel_db) = builder.add_version(new_parent)?;
+        new_numbers.insert(*version, new_version);
+
+        let parent_chunks = read_all_lods(&voxel_db, &builder.trees.chunks)?;
+        let chunks = world.with_version(*version, |old_voxel_db| {
+            read_all_lods(old_voxel_db, &world.trees.chunks)
+        })?;
+        let (removed, changed) = chunk_differences(&parent_chunks, &chunks);
+        write_chunks(&voxel_db, removed.into_iter(), changed.into_iter())?;
+
+        infos.push((
+            new_version,
+            VersionInfo {
+                parent: new_parent,
+                ..info.clone()
+            },
+        ));
+    }
+    // A merged version can be newer than the merge commit, so it's renumbered once all are known.
+    // If it was removed, its closest retained ancestor is still an ancestor of the merge.
+    for (version, mut info) in infos.into_iter() {
+        info.merged = retained_ancestor(&parents, info.merged, retained).map(|m| new_numbers[&m]);
+        editor_db.write_version(version, &info)?;
+    }
+
+    let new_current = new_numbers[&current];
+    builder.checkout(new_current)?;
+    editor_db.write_current_version(new_current)
+}
+
+fn retained_ancestor(
+    parents: &HashMap<u64, Option<u64>>,
+    mut parent: Option<u64>,
+    retained: &BTreeSet<u64>,
+) -> Option<u64> {
+    while let Some(p) = parent {
+        if retained.contains(&p) {
+            return Some(p);
+        }
+        parent = parents.get(&p).cloned().flatten();
+    }
+
+    None
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::forest_builder::tests::{solid_voxels, write_test_world};
+
+    fn version(parent: Option<u64>, branch: &str) -> VersionInfo {
+        VersionInfo::new(parent, branch.to_string())
+    }
+
+    #[test]
+    fn keeps_the_last_versions_branch_tips_and_current() {
+        let versions = vec![
+            (1, version(None, "main")),
+            (2, version(Some(1), "main")),
+            (3, version(Some(2), "main")),
+            (4, version(Some(2), "cave")),
+            (5, version(Some(3), "main")),
+            (6, version(Some(5), "main")),
+        ];
+
+        let retained = RetentionPolicy { keep_last: 1 }.retained_versions(&versions, 2);
+        assert_eq!(retained, [2, 4, 6].iter().cloned().collect());
+
+        let retained = RetentionPolicy { keep_last: 3 }.retained_versions(&versions, 6);
+        assert_eq!(retained, [4, 5, 6].iter().cloned().collect());
+    }
+
+    #[test]
+    fn removed_parents_are_skipped() {
+        let parents = vec![(1, None), (2, Some(1)), (3, Some(2)), (4, Some(3))]
+            .into_iter()
+            .collect();
+        let retained = [1, 4].iter().cloned().collect();
+
+        assert_eq!(retained_ancestor(&parents, Some(3), &retained), Some(1));
+        assert_eq!(retained_ancestor(&parents, Some(1), &retained), Some(1));
+        assert_eq!(retained_ancestor(&parents, None, &retained), None);
+        let retained = [4].iter().cloned().collect();
+        assert_eq!(retained_ancestor(&parents, Some(3), &retained), None);
+    }
+
+    #[test]
+    fn collecting_garbage_rebuilds_the_retained_versions() {
+        let path =
+            std::env::temp_dir().join(format!("feldspar_editor_gc_test_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let storage = StorageConfig::default();
+        let test_world = {
+            let db = open_sled(path, &storage).unwrap();
+            let test_world = write_test_world(&db, storage.chunk_codec, 16384).unwrap();
+            db.flush().unwrap();
+            test_world
+        };
+        let policy = RetentionPolicy { keep_last: 2 };
+        let removed = vec![test_world.version(1), test_world.version(2)];
+
+        let report = collect_garbage(path, &storage, &policy, true).unwrap();
+        assert_eq!(report.removed_versions, removed);
+        let (versions, _) = WorldDb::open(path, &storage).unwrap().versions().unwrap();
+        assert_eq!(versions.len(), test_world.versions.len());
+
+        let report = collect_garbage(path, &storage, &policy, false).unwrap();
+        assert_eq!(report.removed_versions, removed);
+        // Version 1 was the only one with the garbage chunks.
+        assert!(
+            report.new_size < report.old_size,
+            "{} bytes before, {} after",
+            report.old_size,
+            report.new_size
+        );
+
+        let world = WorldDb::open(path, &storage).unwrap();
+        let (versions, current) = world.versions().unwrap();
+        // The retained versions are rebuilt in the same order.
+        let retained = [0, 3, 4, 5];
+        assert_eq!(versions.len(), retained.len());
+        let new_number = |i: usize| versions[retained.iter().position(|r| *r == i).unwrap()].0;
+        for (i, (version, info)) in retained.iter().zip(versions.iter()) {
+            let chunks = world
+                .with_version(*version, |db| read_all_lods(db, &world.trees.chunks))
+                .unwrap();
+            assert_eq!(solid_voxels(&chunks), test_world.versions[*i].1);
+            assert!(info.is_committed() || *i == 5);
+        }
+
+        let parents: Vec<_> = versions
+            .iter()
+            .map(|(_, info)| (info.branch.as_str(), info.parent, info.merged))
+            .collect();
+        assert_eq!(
+            parents,
+            vec![
+                ("main", None, None),
+                // Their parents were removed, so they're attached to version 0 instead.
+                ("tunnel", Some(new_number(0)), None),
+                ("cave", Some(new_number(0)), Some(new_number(3))),
+                ("cave", Some(new_number(4)), None),
+            ]
+        );
+        assert_eq!(current, new_number(5));
+        assert!(world.check_integrity().unwrap().is_ok());
+        drop(world);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
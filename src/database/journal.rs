@@ -0,0 +1,164 @@
+use super::{u64_from_be_slice, EditorDb, MapSaver};
+
+use crate::{EditTimeline, EditorError, EditorResult, StreamedMap};
+
+use feldspar::{
+    bb::storage::database::sled,
+    prelude::{VoxelDb, VoxelEditor},
+};
+
+use bevy::{app::AppExit, ecs::prelude::*};
+
+/// A write-ahead log of the edits that haven't been saved yet, so they can be recovered if the
+/// editor crashes. Each record is written by `EditTimeline::take_journal_record`, and records are
+/// removed once a save that includes them has finished.
+#[derive(Clone)]
+pub struct EditJournal {
+    tree: sled::Tree,
+}
+
+impl EditJournal {
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Returns the sequence number of the new record.
+    pub fn append(&self, record: Vec<u8>) -> EditorResult<u64> {
+        let seq = self.last_seq()?.map(|s| s + 1).unwrap_or(0);
+        self.tree.insert(&seq.to_be_bytes(), record)?;
+
+        Ok(seq)
+    }
+
+    /// The sequence number of the most recent record.
+    pub fn last_seq(&self) -> EditorResult<Option<u64>> {
+        self.tree
+            .last()?
+            .map(|(k, _)| u64_from_be_slice(&k))
+            .transpose()
+    }
+
+    /// All records, in the order they were appended.
+    pub fn records(&self) -> EditorResult<Vec<Vec<u8>>> {
+        self.tree.iter().values().map(|v| Ok(v?.to_vec())).collect()
+    }
+
+    /// Removes every record up to and including `seq`.
+    pub fn remove_through(&self, seq: u64) -> EditorResult<()> {
+        let mut batch = sled::Batch::default();
+        for key in self.tree.range(..=seq.to_be_bytes()).keys() {
+            batch.remove(key?);
+        }
+        Ok(self.tree.apply_batch(batch)?)
+    }
+
+    pub fn clear(&self) -> EditorResult<()> {
+        Ok(self.tree.clear()?)
+    }
+
+    pub fn flush(&self) -> EditorResult<()> {
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+/// Journal records left over from a session that crashed or closed without saving, waiting to be
+/// replayed once the map is loaded.
+pub struct PendingJournalReplay(pub Vec<Vec<u8>>);
+
+pub fn replay_edit_journal(
+    mut commands: Commands,
+    pending_replay: Option<Res<PendingJournalReplay>>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut editor: VoxelEditor,
+    voxel_db: Res<VoxelDb>,
+) {
+    let pending_replay = if let Some(p) = pending_replay {
+        p
+    } else {
+        return;
+    };
+
+    log::info!(
+        "Replaying {} journaled edits that weren't saved",
+        pending_replay.0.len()
+    );
+    let mut map = StreamedMap::new(&mut editor, &voxel_db);
+    for record in pending_replay.0.iter() {
+        if let Err(e) = edit_timeline.replay_journal_record(record, &mut map) {
+            log::warn!("Stopped replaying the edit journal: {}", e);
+            break;
+        }
+    }
+
+    commands.remove_resource::<PendingJournalReplay>();
+}
+
+/// Appends every finished edit, undo and redo to the journal.
+pub fn write_edit_journal(
+    journal: Res<EditJournal>,
+    pending_replay: Option<Res<PendingJournalReplay>>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut editor: VoxelEditor,
+    voxel_db: Res<VoxelDb>,
+    mut errors: EventWriter<EditorError>,
+) {
+    if pending_replay.is_some() {
+        // Don't journal anything on top of a journal that hasn't been replayed.
+        return;
+    }
+
+    let map = StreamedMap::new(&mut editor, &voxel_db);
+    if let Some(record) = edit_timeline.take_journal_record(&map) {
+        if let Err(e) = journal.append(record) {
+            errors.send(e);
+        }
+    }
+}
+
+/// Remembers that the editor shut down with everything saved, so the journal isn't replayed next
+/// time. A save that's still running is waited for first. If there are edits that never got saved,
+/// the session is left open, and they're recovered from the journal the next time the database is
+/// opened.
+pub fn close_editor_session(
+    mut exit_events: EventReader<AppExit>,
+    editor_db: Option<Res<EditorDb>>,
+    journal: Option<Res<EditJournal>>,
+    saver: Option<ResMut<MapSaver>>,
+    edit_timeline: Option<ResMut<EditTimeline>>,
+) {
+    if exit_events.iter().next().is_none() {
+        return;
+    }
+
+    let (editor_db, journal) = match (editor_db, journal) {
+        (Some(editor_db), Some(journal)) => (editor_db, journal),
+        _ => return,
+    };
+
+    if let (Some(mut saver), Some(mut edit_timeline)) = (saver, edit_timeline) {
+        if saver.is_saving() {
+            log::info!("Waiting for the save to finish");
+        }
+        if let Err(e) = saver.finish_running_save(&mut edit_timeline) {
+            log::error!("{}", e);
+        }
+    }
+
+    match journal.last_seq() {
+        Ok(None) => {
+            if let Err(e) = editor_db.write_session_open(false) {
+                log::error!("Failed to close the editor session: {}", e);
+            }
+        }
+        Ok(Some(_)) => {
+            log::warn!(
+                "Closing with unsaved edits, they'll be recovered from the journal next time"
+            );
+            if let Err(e) = journal.flush() {
+                log::error!("Failed to flush the edit journal: {}", e);
+            }
+        }
+        Err(e) => log::error!("Failed to read the edit journal: {}", e),
+    }
+}
@@ -0,0 +1,251 @@
+use super::{save::start_save, MapSaver};
+
+use crate::{Config, EditTimeline, EditorError, EditorResult, NewWorldConfig, WorldTemplate};
+
+use feldspar::{
+    bb::{core::prelude::*, storage::prelude::Sd8},
+    prelude::{VoxelEditor, VoxelType},
+};
+
+use bevy::{
+    ecs::prelude::*,
+    render::texture::{ImageType, Texture},
+    tasks::IoTaskPool,
+};
+use std::path::Path;
+
+/// Inserted by `open_voxel_database` when the database doesn't have a world yet, so one is
+/// created from `Config::new_world`.
+pub struct PendingNewWorld;
+
+pub fn create_new_world(
+    mut commands: Commands,
+    pending: Option<Res<PendingNewWorld>>,
+    config: Res<Config>,
+    mut editor: VoxelEditor,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut saver: ResMut<MapSaver>,
+    pool: Res<IoTaskPool>,
+    mut errors: EventWriter<EditorError>,
+) {
+    if pending.is_none() {
+        return;
+    }
+    commands.remove_resource::<PendingNewWorld>();
+
+    let heightfield = Heightfield::new(&config.new_world).unwrap_or_else(|e| {
+        errors.send(e);
+        Heightfield::from_fn(config.new_world.size, |_, _| 1.0)
+    });
+    let ground = VoxelType(config.new_world.voxel_type);
+    let extent = heightfield.extent();
+    log::info!("Creating a new world in {:?}", extent);
+    editor.edit_extent_and_touch_neighbors(extent, |p, (voxel_type, dist)| {
+        // Distance to the surface straight above or below the center of the voxel.
+        let d = p.y() as f32 + 0.5 - heightfield.get(p.x(), p.z());
+        *voxel_type = if d < 0.0 { ground } else { VoxelType(0) };
+        *dist = Sd8::from(d);
+    });
+
+    // Save it right away, so the next session doesn't find an empty world that isn't new.
+    edit_timeline.mark_extent_written(extent);
+    start_save(&mut saver, &editor.map, &mut edit_timeline, &pool);
+}
+
+/// The height of the ground in every column of a new world, starting at the origin.
+struct Heightfield {
+    shape: [i32; 2],
+    heights: Vec<f32>,
+}
+
+impl Heightfield {
+    fn new(config: &NewWorldConfig) -> EditorResult<Self> {
+        let heightfield = match &config.template {
+            WorldTemplate::Empty => Self::from_fn(config.size, |_, _| 1.0),
+            WorldTemplate::Flat { height } => Self::from_fn(config.size, |_, _| *height as f32),
+            WorldTemplate::Noise {
+                seed,
+                base_height,
+                amplitude,
+                wavelength,
+                octaves,
+            } => {
+                let wavelength = wavelength.max(1.0);
+                Self::from_fn(config.size, |x, z| {
+                    let n = fractal_noise(
+                        *seed,
+                        x as f32 / wavelength,
+                        z as f32 / wavelength,
+                        *octaves,
+                    );
+
+                    base_height + amplitude * n
+                })
+            }
+            WorldTemplate::Heightmap { path, max_height } => read_heightmap(path, *max_height)?,
+        };
+
+        Ok(heightfield)
+    }
+
+    /// Every column gets at least one voxel of ground, so there's always something to build on.
+    fn from_fn(shape: [i32; 2], height: impl Fn(i32, i32) -> f32) -> Self {
+        let shape = [shape[0].max(1), shape[1].max(1)];
+        let mut heights = Vec::with_capacity((shape[0] * shape[1]) as usize);
+        for z in 0..shape[1] {
+            for x in 0..shape[0] {
+                heights.push(height(x, z).max(1.0));
+            }
+        }
+
+        Self { shape, heights }
+    }
+
+    fn get(&self, x: i32, z: i32) -> f32 {
+        self.heights[(z * self.shape[0] + x) as usize]
+    }
+
+    /// Includes one layer of voxels above the highest ground, so the surface is smooth.
+    fn extent(&self) -> Extent3i {
+        let max_height = self.heights.iter().cloned().fold(1.0, f32::max);
+
+        Extent3i::from_min_and_shape(
+            Point3i::ZERO,
+            PointN([self.shape[0], max_height.ceil() as i32 + 1, self.shape[1]]),
+        )
+    }
+}
+
+fn read_heightmap(path: &str, max_height: f32) -> EditorResult<Heightfield> {
+    let to_editor_error = |e: String| EditorError::WorldTemplate(format!("{}: {}", path, e));
+    let bytes = std::fs::read(path).map_err(|e| to_editor_error(e.to_string()))?;
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("png");
+    let texture = Texture::from_buffer(&bytes, ImageType::Extension(extension))
+        .map_err(|e| to_editor_error(format!("{:?}", e)))?;
+
+    let pixel_size = texture.format.pixel_size();
+    let shape = [texture.size.width as i32, texture.size.height as i32];
+
+    Ok(Heightfield::from_fn(shape, |x, z| {
+        let i = (z * shape[0] + x) as usize * pixel_size;
+
+        texture.data[i] as f32 / 255.0 * max_height
+    }))
+}
+
+/// Value noise in `[-1, 1]`, summed over `octaves` that each double the frequency and halve the
+/// amplitude.
+fn fractal_noise(seed: u32, x: f32, z: f32, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut total_amplitude = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for octave in 0..octaves.max(1) {
+        sum += amplitude * value_noise(seed.wrapping_add(octave), x * frequency, z * frequency);
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    sum / total_amplitude
+}
+
+fn value_noise(seed: u32, x: f32, z: f32) -> f32 {
+    let (x0, z0) = (x.floor(), z.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, tz) = (smooth(x - x0), smooth(z - z0));
+    let (x0, z0) = (x0 as i32, z0 as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    lerp(
+        lerp(
+            lattice_value(seed, x0, z0),
+            lattice_value(seed, x0 + 1, z0),
+            tx,
+        ),
+        lerp(
+            lattice_value(seed, x0, z0 + 1),
+            lattice_value(seed, x0 + 1, z0 + 1),
+            tx,
+        ),
+        tz,
+    )
+}
+
+/// A pseudorandom value in `[-1, 1]` for each lattice point.
+fn lattice_value(seed: u32, x: i32, z: i32) -> f32 {
+    let mut h = seed.wrapping_mul(0x9e37_79b9)
+        ^ (x as u32).wrapping_mul(0x85eb_ca6b)
+        ^ (z as u32).wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+
+    h as f32 / std::u32::MAX as f32 * 2.0 - 1.0
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_template_matches_the_old_slab() {
+        let config = NewWorldConfig::default();
+        let heightfield = Heightfield::new(&config).unwrap();
+
+        assert_eq!(
+            heightfield.extent(),
+            Extent3i::from_min_and_shape(Point3i::ZERO, PointN([64, 3, 64]))
+        );
+        assert_eq!(heightfield.get(63, 63), 2.0);
+    }
+
+    #[test]
+    fn noise_is_deterministic_and_bounded() {
+        let config = NewWorldConfig {
+            template: WorldTemplate::Noise {
+                seed: 7,
+                base_height: 16.0,
+                amplitude: 8.0,
+                wavelength: 32.0,
+                octaves: 3,
+            },
+            size: [100, 50],
+            voxel_type: 1,
+        };
+        let a = Heightfield::new(&config).unwrap();
+        let b = Heightfield::new(&config).unwrap();
+
+        assert_eq!(a.heights, b.heights);
+        assert!(a.heights.iter().all(|h| (8.0..=24.0).contains(h)));
+        assert!(a.heights.iter().any(|h| *h != a.heights[0]));
+    }
+
+    #[test]
+    fn missing_heightmap_is_an_error() {
+        let config = NewWorldConfig {
+            template: WorldTemplate::Heightmap {
+                path: "does/not/exist.png".to_string(),
+                max_height: 10.0,
+            },
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            Heightfield::new(&config),
+            Err(EditorError::WorldTemplate(_))
+        ));
+    }
+}
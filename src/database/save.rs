@@ -0,0 +1,327 @@
+use super::{EditHistoryDb, EditJournal, EditorDb};
+
+use crate::{Action, Config, EditTimeline, EditorError, EditorResult};
+
+use feldspar::{
+    bb::{prelude::*, storage::database::Delta},
+    prelude::{ambient_sdf_array, SdfArray, SdfVoxelMap, VoxelDb, VoxelType},
+};
+
+use bevy::{core::Time, ecs::prelude::*, tasks::IoTaskPool, window::Windows};
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Writes the map and edit history to the database on a background task, so the editor keeps
+/// running while it saves.
+pub struct MapSaver {
+    voxel_db: Arc<VoxelDb>,
+    editor_db: EditorDb,
+    history_db: EditHistoryDb,
+    journal: EditJournal,
+    current_save: Option<SaveJob>,
+}
+
+/// Everything we need to know about a save that's still running.
+struct SaveJob {
+    progress: Arc<Mutex<SaveProgress>>,
+    reported: Option<SaveProgress>,
+    chunk_keys: Vec<ChunkKey3>,
+    written_nodes: Vec<u64>,
+    removed_nodes: Vec<u64>,
+    /// The last journal record from before the save started, which is no longer needed once the
+    /// save finishes.
+    last_journal_seq: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SaveProgress {
+    CompressingChunks { num_chunks: usize },
+    WritingChunks,
+    WritingHistory { num_nodes: usize },
+    Flushing,
+    Finished,
+    Failed(String),
+}
+
+impl SaveProgress {
+    fn is_done(&self) -> bool {
+        matches!(self, SaveProgress::Finished | SaveProgress::Failed(_))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            SaveProgress::CompressingChunks { num_chunks } => {
+                format!("Saving (1/4): compressing {} chunks", num_chunks)
+            }
+            SaveProgress::WritingChunks => "Saving (2/4): writing chunks".to_string(),
+            SaveProgress::WritingHistory { num_nodes } => {
+                format!("Saving (3/4): writing {} edit history nodes", num_nodes)
+            }
+            SaveProgress::Flushing => "Saving (4/4): flushing".to_string(),
+            SaveProgress::Finished => "Saved".to_string(),
+            SaveProgress::Failed(e) => format!("Save failed: {}", e),
+        }
+    }
+}
+
+impl MapSaver {
+    pub fn new(
+        voxel_db: VoxelDb,
+        editor_db: EditorDb,
+        history_db: EditHistoryDb,
+        journal: EditJournal,
+    ) -> Self {
+        Self {
+            voxel_db: Arc::new(voxel_db),
+            editor_db,
+            history_db,
+            journal,
+            current_save: None,
+        }
+    }
+
+    pub fn is_saving(&self) -> bool {
+        self.current_save.is_some()
+    }
+
+    /// Saves started after this write to `voxel_db` instead.
+    pub fn set_voxel_db(&mut self, voxel_db: VoxelDb) {
+        self.voxel_db = Arc::new(voxel_db);
+    }
+
+    /// Blocks until the running save, if there is one, is done, and then handles its outcome the
+    /// same way `report_save_progress` does.
+    pub fn finish_running_save(&mut self, edit_timeline: &mut EditTimeline) -> EditorResult<()> {
+        let progress = loop {
+            let progress = match &self.current_save {
+                Some(job) => job.progress.lock().unwrap().clone(),
+                None => return Ok(()),
+            };
+            if progress.is_done() {
+                break progress;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        self.finish_save(progress, edit_timeline)
+    }
+
+    fn finish_save(
+        &mut self,
+        progress: SaveProgress,
+        edit_timeline: &mut EditTimeline,
+    ) -> EditorResult<()> {
+        let job = self.current_save.take().unwrap();
+        match progress {
+            SaveProgress::Failed(e) => {
+                // Nothing from this save can be trusted to be on disk, so try all of it again
+                // next time. The edits are still in the map, so nothing is lost.
+                edit_timeline.restore_unsaved(
+                    job.chunk_keys.into_iter(),
+                    job.written_nodes.into_iter(),
+                    job.removed_nodes.into_iter(),
+                );
+                Err(EditorError::SaveFailed(e))
+            }
+            _ => match job.last_journal_seq {
+                Some(seq) => self.journal.remove_through(seq),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// Starts a background save when the `save` action runs.
+pub fn save_map_to_db(
+    mut saver: ResMut<MapSaver>,
+    voxel_map: Res<SdfVoxelMap>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    pool: Res<IoTaskPool>,
+    mut actions: EventReader<Action>,
+) {
+    if !actions.iter().any(|action| *action == Action::Save) {
+        return;
+    }
+
+    if saver.is_saving() {
+        log::info!("Already saving, edits made since will be saved next time");
+        return;
+    }
+
+    log::info!("Saving map to DB");
+    start_save(&mut saver, &voxel_map, &mut edit_timeline, &pool);
+}
+
+/// Starts a background save every `Config::autosave_interval_secs`, as long as there's something
+/// to save and no edit is in progress.
+pub fn autosave_map(
+    mut saver: ResMut<MapSaver>,
+    voxel_map: Res<SdfVoxelMap>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    pool: Res<IoTaskPool>,
+    config: Res<Config>,
+    time: Res<Time>,
+    mut secs_since_save: Local<f64>,
+) {
+    let interval = if let Some(interval) = config.autosave_interval_secs {
+        interval
+    } else {
+        return;
+    };
+
+    if saver.is_saving() {
+        *secs_since_save = 0.0;
+        return;
+    }
+
+    *secs_since_save += time.delta_seconds_f64();
+    if *secs_since_save < interval
+        || edit_timeline.is_editing()
+        || !edit_timeline.has_unsaved_changes()
+    {
+        return;
+    }
+    *secs_since_save = 0.0;
+
+    log::info!("Autosaving map to DB");
+    start_save(&mut saver, &voxel_map, &mut edit_timeline, &pool);
+}
+
+/// The dirty chunks and history are copied out of the map and timeline before the save starts.
+/// Anything edited while the save is running is left dirty, and gets written by the next save.
+pub(super) fn start_save(
+    saver: &mut MapSaver,
+    voxel_map: &SdfVoxelMap,
+    edit_timeline: &mut EditTimeline,
+    pool: &IoTaskPool,
+) {
+    // Only the chunks that were edited since the last save need to be written. Chunks that are
+    // entirely ambient now are removed instead, so that erased regions don't take up space.
+    let storage = voxel_map.voxels.storage();
+    let dirty_chunks: Vec<_> = edit_timeline
+        .take_dirty_chunks()
+        .into_iter()
+        .map(|key| {
+            let chunk = storage
+                .copy_without_caching(key)
+                .map(|c| c.into_decompressed())
+                .filter(|c| !is_ambient(c));
+            (key, chunk)
+        })
+        .collect();
+
+    // The edit history is only written along with the map, so that the current node of the
+    // timeline always matches the saved map.
+    let history_changes = edit_timeline.take_history_changes();
+
+    let progress = Arc::new(Mutex::new(SaveProgress::CompressingChunks {
+        num_chunks: dirty_chunks.len(),
+    }));
+    let job = SaveJob {
+        progress: progress.clone(),
+        reported: None,
+        chunk_keys: dirty_chunks.iter().map(|(key, _)| *key).collect(),
+        written_nodes: history_changes.written.iter().map(|(id, _)| *id).collect(),
+        removed_nodes: history_changes.removed.clone(),
+        last_journal_seq: saver.journal.last_seq().unwrap_or_else(|e| {
+            log::error!("Failed to read the edit journal: {}", e);
+            None
+        }),
+    };
+
+    let voxel_db = saver.voxel_db.clone();
+    let editor_db = saver.editor_db.clone();
+    let history_db = saver.history_db.clone();
+    let set_progress = move |p| *progress.lock().unwrap() = p;
+    pool.spawn(async move {
+        let num_removed = dirty_chunks.iter().filter(|(_, c)| c.is_none()).count();
+        log::info!(
+            "Writing {} deltas ({} chunks removed)",
+            dirty_chunks.len(),
+            num_removed
+        );
+        let deltas = dirty_chunks.iter().map(|(key, chunk)| match chunk {
+            Some(chunk) => Delta::Insert(*key, MaybeCompressed::Decompressed(chunk)),
+            None => Delta::Remove(*key),
+        });
+
+        let chunk_db = voxel_db.chunks();
+        let mut batch = chunk_db.start_delta_batch();
+        batch.add_and_compress_deltas(deltas).await;
+
+        set_progress(SaveProgress::WritingChunks);
+        if let Err(e) = chunk_db.apply_deltas_to_current_version(batch.build()) {
+            set_progress(SaveProgress::Failed(format!("{:?}", e)));
+            return;
+        }
+
+        set_progress(SaveProgress::WritingHistory {
+            num_nodes: history_changes.written.len(),
+        });
+        let history_result = editor_db
+            .write_current_edit(history_changes.current)
+            .and_then(|()| history_db.write_changes(history_changes));
+        if let Err(e) = history_result {
+            set_progress(SaveProgress::Failed(e.to_string()));
+            return;
+        }
+
+        set_progress(SaveProgress::Flushing);
+        set_progress(match chunk_db.flush().await {
+            Ok(_) => SaveProgress::Finished,
+            Err(e) => SaveProgress::Failed(e.to_string()),
+        });
+    })
+    .detach();
+
+    saver.current_save = Some(job);
+}
+
+/// Shows the progress of the current save in the window title, and logs the outcome.
+pub fn report_save_progress(
+    mut saver: ResMut<MapSaver>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut windows: ResMut<Windows>,
+    mut errors: EventWriter<EditorError>,
+    config: Res<Config>,
+) {
+    let job = if let Some(job) = &mut saver.current_save {
+        job
+    } else {
+        return;
+    };
+
+    let progress = job.progress.lock().unwrap().clone();
+    if job.reported.as_ref() == Some(&progress) {
+        return;
+    }
+
+    log::info!("{}", progress.describe());
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_title(format!("{} - {}", config.window.title, progress.describe()));
+    }
+
+    if progress.is_done() {
+        if let Err(e) = saver.finish_save(progress, &mut edit_timeline) {
+            errors.send(e);
+        }
+    } else {
+        job.reported = Some(progress);
+    }
+}
+
+pub(super) fn is_ambient(chunk: &SdfArray) -> bool {
+    let ambient_value: (VoxelType, Sd8) =
+        ambient_sdf_array(Extent3i::from_min_and_shape(Point3i::ZERO, PointN([1; 3])))
+            .get(Point3i::ZERO);
+
+    let mut ambient = true;
+    chunk.for_each(chunk.extent(), |_p: Point3i, v: (VoxelType, Sd8)| {
+        ambient &= v == ambient_value;
+    });
+
+    ambient
+}
@@ -0,0 +1,167 @@
+use super::EditorDb;
+
+use crate::{
+    create_camera_entity, Config, CurrentTool, CursorRayCameraTag, EditTimeline, EditorError,
+    EditorResult, SelectionState, Terraformer, ToolSession,
+};
+
+use bevy::{app::AppExit, ecs::prelude::*, math::prelude::*};
+use serde::{Deserialize, Serialize};
+use smooth_bevy_cameras::LookTransform;
+
+/// Where the user was when they last closed this world. It's written as RON, so fields can be
+/// added without losing the sessions saved by older editors.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EditorSession {
+    pub camera: Option<CameraPose>,
+    pub tools: ToolSession,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct CameraPose {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+}
+
+impl From<&LookTransform> for CameraPose {
+    fn from(look: &LookTransform) -> Self {
+        Self {
+            eye: look.eye.into(),
+            target: look.target.into(),
+        }
+    }
+}
+
+impl EditorSession {
+    pub fn encode(&self) -> EditorResult<String> {
+        ron::to_string(self)
+            .map_err(|e| EditorError::Corrupt(format!("failed to encode the session: {}", e)))
+    }
+
+    pub fn decode(s: &str) -> EditorResult<Self> {
+        ron::from_str(s)
+            .map_err(|e| EditorError::Corrupt(format!("failed to decode the session: {}", e)))
+    }
+}
+
+/// The session read by `open_voxel_database`, applied once the editor entities exist.
+pub struct PendingSessionRestore(pub EditorSession);
+
+pub fn restore_editor_session(
+    mut commands: Commands,
+    pending: Option<Res<PendingSessionRestore>>,
+    config: Res<Config>,
+    cameras: Query<Entity, With<CursorRayCameraTag>>,
+    editor_db: Res<EditorDb>,
+    edit_timeline: Res<EditTimeline>,
+    mut current_tool: ResMut<CurrentTool>,
+    mut terraformer: ResMut<Terraformer>,
+    mut selection: ResMut<SelectionState>,
+    mut errors: EventWriter<EditorError>,
+) {
+    let session = if let Some(pending) = pending {
+        pending.0.clone()
+    } else {
+        return;
+    };
+    commands.remove_resource::<PendingSessionRestore>();
+
+    if let Some(pose) = session.camera {
+        // The camera controllers smooth every change to the look transform, so replace the camera
+        // instead of flying it over from the default pose.
+        for entity in cameras.iter() {
+            commands.entity(entity).despawn();
+        }
+        create_camera_entity(
+            &mut commands,
+            config.camera,
+            Vec3::from(pose.eye),
+            Vec3::from(pose.target),
+        );
+    }
+
+    if let Err(e) = session.tools.restore(
+        &mut current_tool,
+        &mut terraformer,
+        &mut selection,
+        &editor_db,
+        &edit_timeline,
+        &config.bindings,
+    ) {
+        errors.send(e);
+    }
+}
+
+pub fn save_editor_session(
+    mut exit_events: EventReader<AppExit>,
+    editor_db: Option<Res<EditorDb>>,
+    cameras: Query<&LookTransform, With<CursorRayCameraTag>>,
+    current_tool: Res<CurrentTool>,
+    terraformer: Res<Terraformer>,
+    selection: Option<Res<SelectionState>>,
+) {
+    if exit_events.iter().next().is_none() {
+        return;
+    }
+    let editor_db = if let Some(editor_db) = editor_db {
+        editor_db
+    } else {
+        return;
+    };
+
+    let session = EditorSession {
+        camera: cameras.iter().next().map(CameraPose::from),
+        tools: ToolSession::capture(
+            &current_tool,
+            &terraformer,
+            selection
+                .as_deref()
+                .unwrap_or(&SelectionState::SelectingFirstCorner),
+        ),
+    };
+    if let Err(e) = editor_db.write_session(&session) {
+        log::error!("Failed to save the editor session: {}", e);
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use feldspar::bb::storage::database::sled;
+
+    #[test]
+    fn session_round_trips_and_missing_fields_are_defaults() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let editor_db = EditorDb::new(db.open_tree("editor").unwrap());
+        assert!(editor_db.read_session().unwrap().is_none());
+
+        let mut session = EditorSession::default();
+        session.camera = Some(CameraPose {
+            eye: [1.0, 2.0, 3.0],
+            target: [4.0, 5.0, 6.0],
+        });
+        session.tools.edit_radius = 3;
+        editor_db.write_session(&session).unwrap();
+        let read = editor_db.read_session().unwrap().unwrap();
+        assert_eq!(read.camera.unwrap().eye, [1.0, 2.0, 3.0]);
+        assert_eq!(read.tools.edit_radius, 3);
+
+        // A session saved before the tools were.
+        let read = EditorSession::decode("(camera: None)").unwrap();
+        assert_eq!(read.tools.edit_radius, ToolSession::default().edit_radius);
+
+        assert!(matches!(
+            EditorSession::decode("not a session"),
+            Err(EditorError::Corrupt(_))
+        ));
+    }
+}
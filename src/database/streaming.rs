@@ -0,0 +1,303 @@
+use super::{save::start_save, MapSaver};
+
+use crate::{Config, EditTimeline, EditorError};
+
+use feldspar::{
+    bb::prelude::*,
+    prelude::{VoxelDb, VoxelEditor, Witness},
+};
+
+use bevy::{
+    ecs::prelude::*, math::prelude::*, tasks::IoTaskPool, transform::components::Transform,
+};
+use std::collections::HashSet;
+
+/// Keeps only the chunks near a `Witness` in the map. The map is divided into cubic regions the
+/// size of a superchunk, and a region is loaded from the `VoxelDb` as soon as any part of it is
+/// within the witness radius.
+///
+/// Regions are evicted once they're a little further away than that, so moving back and forth
+/// across the boundary doesn't keep reloading them. A chunk is never evicted while it has unsaved
+/// edits. Edits, undo and redo read the chunks that aren't loaded from the `VoxelDb` through a
+/// `StreamedMap`.
+pub struct ChunkStreamer {
+    region_exponent: u8,
+    /// The minimum of every loaded region.
+    loaded_regions: HashSet<Point3i>,
+}
+
+impl ChunkStreamer {
+    pub fn new(region_exponent: u8) -> Self {
+        Self {
+            region_exponent,
+            loaded_regions: HashSet::new(),
+        }
+    }
+
+    /// Forgets which regions were loaded, so they're streamed in again from the current version.
+    pub fn reset(&mut self) {
+        self.loaded_regions.clear();
+    }
+
+    fn region_extent(&self, region_min: Point3i) -> Extent3i {
+        Extent3i::from_min_and_shape(region_min, PointN([1 << self.region_exponent; 3]))
+    }
+}
+
+pub fn stream_chunks(
+    mut streamer: ResMut<ChunkStreamer>,
+    witnesses: Query<&Transform, With<Witness>>,
+    voxel_db: Res<VoxelDb>,
+    mut saver: ResMut<MapSaver>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut editor: VoxelEditor,
+    pool: Res<IoTaskPool>,
+    config: Res<Config>,
+    mut errors: EventWriter<EditorError>,
+) {
+    // Chunks are only written by edits in between.
+    if edit_timeline.is_editing() {
+        return;
+    }
+
+    let load_radius = config.feldspar.witness_radius as f32;
+    let region_edge = (1 << streamer.region_exponent) as f32;
+    let evict_radius = load_radius + region_edge / 2.0;
+    let mut in_load_range = HashSet::new();
+    let mut in_evict_range = HashSet::new();
+    for transform in witnesses.iter() {
+        let center = transform.translation;
+        in_load_range.extend(regions_in_range(
+            center,
+            load_radius,
+            streamer.region_exponent,
+        ));
+        in_evict_range.extend(regions_in_range(
+            center,
+            evict_radius,
+            streamer.region_exponent,
+        ));
+    }
+
+    let mut to_load: Vec<Point3i> = in_load_range
+        .difference(&streamer.loaded_regions)
+        .cloned()
+        .collect();
+    to_load.sort_by_key(|p| p.0);
+    for region_min in to_load.into_iter() {
+        let orthant = Orthant::new_unchecked(region_min, streamer.region_exponent);
+        let region_keys = chunk_keys_in(&editor, streamer.region_extent(region_min));
+        // A region that fails to load is still marked as loaded, so the error isn't reported
+        // again every frame.
+        streamer.loaded_regions.insert(region_min);
+        let chunks = match voxel_db.chunks().read_chunks_in_orthant(0, orthant) {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                errors.send(EditorError::chunk_db(e));
+                continue;
+            }
+        };
+        for (key, chunk) in chunks.into_iter() {
+            // Chunks that are already in the map were written by edits made before the region was
+            // loaded, so they win over the saved chunk, even if a save is writing them right now.
+            if !region_keys.contains(&key) {
+                editor.write_chunk_and_touch_neighbors(key.minimum, chunk.decompress());
+            }
+        }
+    }
+
+    // Evicted chunks have to be saved, and a running save might not have written them yet.
+    if saver.is_saving() {
+        return;
+    }
+
+    let to_evict: Vec<Point3i> = streamer
+        .loaded_regions
+        .difference(&in_evict_range)
+        .cloned()
+        .collect();
+    if to_evict.is_empty() {
+        return;
+    }
+
+    let mut needs_save = false;
+    for region_min in to_evict.into_iter() {
+        let region_keys = chunk_keys_in(&editor, streamer.region_extent(region_min));
+        if region_keys.iter().any(|k| edit_timeline.is_chunk_dirty(*k)) {
+            // Try again once the save is done.
+            needs_save = true;
+            continue;
+        }
+
+        // The renderer drops the meshes outside of the witness radius by itself.
+        for key in region_keys.into_iter() {
+            editor.map.voxels.pop_chunk(key);
+        }
+        streamer.loaded_regions.remove(&region_min);
+    }
+
+    if needs_save {
+        log::info!("Saving edits before unloading them");
+        start_save(&mut saver, &editor.map, &mut edit_timeline, &pool);
+    }
+}
+
+/// The keys of the chunks in `region` that are in the map.
+fn chunk_keys_in(editor: &VoxelEditor, region: Extent3i) -> HashSet<ChunkKey3> {
+    editor
+        .map
+        .voxels
+        .storage()
+        .into_iter()
+        .map(|(k, _)| *k)
+        .filter(|k| region.contains(k.minimum))
+        .collect()
+}
+
+/// The minimum of every region that's at least partly within `radius` of `center`.
+fn regions_in_range(center: Vec3, radius: f32, region_exponent: u8) -> Vec<Point3i> {
+    let region_edge = 1 << region_exponent;
+    let to_region_min = |x: f32| (x.floor() as i32) & !(region_edge - 1);
+    let min = PointN([
+        to_region_min(center.x - radius),
+        to_region_min(center.y - radius),
+        to_region_min(center.z - radius),
+    ]);
+    let max = PointN([
+        to_region_min(center.x + radius),
+        to_region_min(center.y + radius),
+        to_region_min(center.z + radius),
+    ]);
+
+    let mut regions = Vec::new();
+    for z in (min.z()..=max.z()).step_by(region_edge as usize) {
+        for y in (min.y()..=max.y()).step_by(region_edge as usize) {
+            for x in (min.x()..=max.x()).step_by(region_edge as usize) {
+                // The closest point of the region to the center.
+                let closest = Vec3::new(
+                    center.x.max(x as f32).min((x + region_edge) as f32),
+                    center.y.max(y as f32).min((y + region_edge) as f32),
+                    center.z.max(z as f32).min((z + region_edge) as f32),
+                );
+                if closest.distance(center) <= radius {
+                    regions.push(PointN([x, y, z]));
+                }
+            }
+        }
+    }
+
+    regions
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        database::forest_builder::{
+            read_all_lods,
+            tests::{solid_voxels, CHUNK_SHAPE},
+            write_chunks, ForestBuilder,
+        },
+        ChunkCodec, StreamedMap,
+    };
+
+    use feldspar::{
+        bb::storage::database::sled,
+        prelude::{
+            ambient_sdf_array, empty_sdf_chunk_hash_map, SdfArray, SdfChunkHashMap, VoxelType,
+        },
+    };
+    use std::collections::HashMap;
+
+    /// Writes the dirty chunks of `map` like `start_save` does.
+    fn save(timeline: &mut EditTimeline, map: &SdfChunkHashMap, voxel_db: &VoxelDb) {
+        let dirty: Vec<(ChunkKey3, SdfArray)> = timeline
+            .take_dirty_chunks()
+            .into_iter()
+            .filter_map(|key| map.storage().get(&key).cloned().map(|chunk| (key, chunk)))
+            .collect();
+        write_chunks(
+            voxel_db,
+            std::iter::empty(),
+            dirty.iter().map(|(key, chunk)| (*key, chunk)),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn undoing_an_edit_of_an_unloaded_chunk_keeps_the_saved_voxels() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut builder = ForestBuilder::new(&db, ChunkCodec::default()).unwrap();
+        let (_, voxel_db) = builder.add_version(None).unwrap();
+
+        // A solid chunk of saved terrain.
+        let key = ChunkKey::new(0, Point3i::ZERO);
+        let extent = Extent3i::from_min_and_shape(Point3i::ZERO, CHUNK_SHAPE);
+        let mut saved = ambient_sdf_array(extent);
+        saved.for_each_mut(
+            &extent,
+            |_p: Point3i, (t, d): (&mut VoxelType, &mut Sd8)| {
+                *t = VoxelType(1);
+                *d = Sd8(-1);
+            },
+        );
+        write_chunks(
+            &voxel_db,
+            std::iter::empty(),
+            std::iter::once((key, &saved)),
+        )
+        .unwrap();
+
+        // Dig into it and save, then start over with nothing loaded, like after a restart.
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let dug = Extent3i::from_min_and_shape(PointN([1; 3]), PointN([2; 3]));
+        let mut streamed = StreamedMap::new(&mut map, &voxel_db);
+        streamed.load_chunks_in(dug);
+        timeline.add_extent_to_current_edit(dug, &streamed);
+        let mut chunk = map.storage().get(&key).cloned().unwrap();
+        chunk.for_each_mut(&dug, |_p: Point3i, (t, d): (&mut VoxelType, &mut Sd8)| {
+            *t = VoxelType(0);
+            *d = Sd8(1);
+        });
+        map.write_chunk(key, chunk);
+        timeline.store_current_edit("dig", &StreamedMap::new(&mut map, &voxel_db));
+        save(&mut timeline, &map, &voxel_db);
+
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        timeline.undo(&mut StreamedMap::new(&mut map, &voxel_db));
+        save(&mut timeline, &map, &voxel_db);
+
+        let saved_chunks = read_all_lods(&voxel_db, &builder.trees.chunks).unwrap();
+        let expected: HashMap<ChunkKey3, SdfArray> = std::iter::once((key, saved)).collect();
+        assert_eq!(solid_voxels(&saved_chunks), solid_voxels(&expected));
+    }
+
+    #[test]
+    fn regions_in_range_are_the_ones_touching_the_sphere() {
+        let mut regions = regions_in_range(Vec3::new(8.0, 8.0, 8.0), 4.0, 4);
+        regions.sort_by_key(|p| p.0);
+        assert_eq!(regions, vec![PointN([0, 0, 0])]);
+
+        // Near a corner, all eight regions around it are in range.
+        let regions = regions_in_range(Vec3::new(15.0, 15.0, 15.0), 2.0, 4);
+        assert_eq!(regions.len(), 8);
+
+        // The corner region diagonal from the center is out of range, even though its bounding
+        // box overlaps the bounding box of the sphere.
+        let mut regions = regions_in_range(Vec3::new(2.0, 2.0, 8.0), 2.5, 4);
+        regions.sort_by_key(|p| p.0);
+        assert_eq!(
+            regions,
+            vec![PointN([-16, 0, 0]), PointN([0, -16, 0]), PointN([0, 0, 0])]
+        );
+    }
+}
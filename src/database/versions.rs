@@ -0,0 +1,618 @@
+mod diff;
+mod merge;
+
+pub use diff::{ChunkChange, ChunkDiff, VersionDiff};
+pub use merge::{common_ancestor, three_way_merge, MergeConflict, MergeResolutions, MergeSide};
+
+use super::{
+    save::is_ambient, ChunkStreamer, ChunkTrees, EditHistoryDb, EditJournal, EditorDb, MapSaver,
+};
+
+use crate::{Action, Config, EditTimeline, EditorError, EditorResult};
+
+use feldspar::{
+    bb::{
+        prelude::*,
+        storage::database::{
+            sled_snapshots::{transactions::create_child_version, TransactionalVersionForest},
+            Delta,
+        },
+    },
+    prelude::{ambient_sdf_array, SdfArray, VoxelDb, VoxelEditor},
+};
+
+use bevy::ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A node of the version graph. Every version is a snapshot in the chunk database's snapshot
+/// forest.
+///
+/// Only the current version is edited. Committing it freezes it and starts a new working version
+/// on top of it, so committed versions never change.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VersionInfo {
+    pub parent: Option<u64>,
+    /// For merge commits, the version that was merged in, as a second parent.
+    pub merged: Option<u64>,
+    pub branch: String,
+    /// `None` until the version is committed.
+    pub message: Option<String>,
+    /// When the version was created or committed, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl VersionInfo {
+    pub fn new(parent: Option<u64>, branch: String) -> Self {
+        Self {
+            parent,
+            merged: None,
+            branch,
+            message: None,
+            timestamp: now_secs(),
+        }
+    }
+
+    pub fn is_committed(&self) -> bool {
+        self.message.is_some()
+    }
+
+    /// The parent, followed by the merged version.
+    pub fn parents(&self) -> impl Iterator<Item = u64> {
+        self.parent.into_iter().chain(self.merged)
+    }
+}
+
+pub const MAIN_BRANCH: &str = "main";
+
+pub enum VersionCommand {
+    /// Freezes the current version with a message, and continues editing on a new version after
+    /// it.
+    Commit { message: String },
+    /// Starts a new branch after `from`, and checks it out.
+    Branch { name: String, from: u64 },
+    /// Reloads the map from `version`. Checking out a committed version continues on its working
+    /// version on the same branch, or starts one if there isn't one yet.
+    Checkout(u64),
+    /// Merges `theirs` into the current version, and commits the result. Without a `base`, the
+    /// most recent common ancestor of both versions is used.
+    ///
+    /// If any conflicts are left unresolved, nothing is written, and the `PendingMerge` resource
+    /// is inserted so they can be resolved.
+    Merge {
+        theirs: u64,
+        base: Option<u64>,
+        resolutions: MergeResolutions,
+    },
+}
+
+/// A merge that stopped because of conflicts. Send `VersionCommand::Merge` again with more
+/// resolutions to finish it.
+pub struct PendingMerge {
+    pub theirs: u64,
+    pub base: u64,
+    pub conflicts: Vec<MergeConflict>,
+    pub resolutions: MergeResolutions,
+}
+
+pub enum DiffCommand {
+    /// Compares the saved chunks of two versions, and highlights the chunks that changed.
+    Show {
+        from: u64,
+        to: u64,
+    },
+    Clear,
+}
+
+/// Versions can only be created or checked out once the map is saved, because the undo history
+/// is discarded on checkout, and unsaved edits would either be lost or end up in the wrong version.
+pub fn version_command_system(
+    mut commands: Commands,
+    mut events: EventReader<VersionCommand>,
+    editor_db: Res<EditorDb>,
+    history_db: Res<EditHistoryDb>,
+    journal: Res<EditJournal>,
+    trees: Res<ChunkTrees>,
+    voxel_db: Res<VoxelDb>,
+    mut saver: ResMut<MapSaver>,
+    mut streamer: ResMut<ChunkStreamer>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut editor: VoxelEditor,
+    config: Res<Config>,
+    mut errors: EventWriter<EditorError>,
+) {
+    for event in events.iter() {
+        if saver.is_saving() || edit_timeline.has_unsaved_changes() {
+            log::warn!(
+                "Save the map ({}) before changing versions",
+                config.bindings.chord_names(Action::Save)
+            );
+            continue;
+        }
+
+        let mut run_command = || -> EditorResult<()> {
+            let current = editor_db.require_current_version()?;
+            let current_info = editor_db
+                .read_version(current)?
+                .unwrap_or_else(|| VersionInfo::new(None, MAIN_BRANCH.to_string()));
+
+            let checkout_target = match event {
+                VersionCommand::Commit { message } => {
+                    let mut committed = current_info.clone();
+                    committed.message = Some(message.clone());
+                    committed.timestamp = now_secs();
+                    editor_db.write_version(current, &committed)?;
+                    let working =
+                        new_working_version(&editor_db, &trees, current, current_info.branch)?;
+                    log::info!("Committed version {}: {}", current, message);
+
+                    // The new version starts out with the same chunks, so there's nothing to
+                    // reload.
+                    editor_db.write_current_version(working)?;
+                    replace_chunk_db(&mut commands, &mut saver, &trees, working);
+                    return Ok(());
+                }
+                VersionCommand::Branch { name, from } => {
+                    if let Err(reason) = check_can_have_children(&editor_db, *from)? {
+                        log::warn!("Can't branch from version {}: {}", from, reason);
+                        return Ok(());
+                    }
+                    let working = new_working_version(&editor_db, &trees, *from, name.clone())?;
+                    log::info!("Created branch {} from version {}", name, from);
+                    working
+                }
+                VersionCommand::Checkout(version) => match editor_db.read_version(*version)? {
+                    Some(info) if info.is_committed() => {
+                        match working_child(&editor_db, *version, &info.branch)? {
+                            Some(working) => working,
+                            None => new_working_version(&editor_db, &trees, *version, info.branch)?,
+                        }
+                    }
+                    Some(_) => {
+                        if has_children(&editor_db, *version)? {
+                            log::warn!(
+                                "Version {} has children but isn't committed, so it can't be edited",
+                                version
+                            );
+                            return Ok(());
+                        }
+                        *version
+                    }
+                    None => {
+                        log::warn!("There is no version {}", version);
+                        return Ok(());
+                    }
+                },
+                VersionCommand::Merge {
+                    theirs,
+                    base,
+                    resolutions,
+                } => {
+                    let base = match base {
+                        Some(base) => Some(*base),
+                        None => {
+                            let parents = list_versions(&editor_db)?
+                                .into_iter()
+                                .map(|(v, info)| (v, info.parents().collect()))
+                                .collect();
+                            common_ancestor(&parents, current, *theirs)
+                        }
+                    };
+                    let base = if let Some(base) = base {
+                        base
+                    } else {
+                        log::warn!(
+                            "Versions {} and {} have no common ancestor",
+                            current,
+                            theirs
+                        );
+                        return Ok(());
+                    };
+                    let merged = merge_into_current(
+                        &mut commands,
+                        &voxel_db,
+                        &trees,
+                        current,
+                        *theirs,
+                        base,
+                        resolutions,
+                        &ChunkIndexer3::new(config.feldspar.map.chunk_shape()),
+                    )?;
+                    if !merged {
+                        return Ok(());
+                    }
+
+                    let mut committed = current_info.clone();
+                    committed.message = Some(format!("Merge version {} into {}", theirs, current));
+                    // So the next merge from the same branch starts from here.
+                    committed.merged = Some(*theirs);
+                    committed.timestamp = now_secs();
+                    editor_db.write_version(current, &committed)?;
+                    log::info!("Committed version {}: merged version {}", current, theirs);
+
+                    // Check out the merge result, so the merged chunks are loaded into the map.
+                    new_working_version(&editor_db, &trees, current, current_info.branch)?
+                }
+            };
+
+            if checkout_target == current {
+                return Ok(());
+            }
+            log::info!("Checking out version {}", checkout_target);
+            voxel_db
+                .chunks()
+                .switch_version(checkout_target)
+                .map_err(EditorError::chunk_db)?;
+            editor_db.write_current_version(checkout_target)?;
+            replace_chunk_db(&mut commands, &mut saver, &trees, checkout_target);
+            unload_map(&mut editor, &mut streamer);
+
+            // The undo history and journal describe edits to the version we just left.
+            *edit_timeline = EditTimeline::new(config.feldspar.map.chunk_shape());
+            history_db.clear()?;
+            journal.clear()
+        };
+        if let Err(e) = run_command() {
+            errors.send(e);
+        }
+    }
+}
+
+/// Writes the merged chunks directly to the current version, so they can be committed. Returns
+/// false if there are conflicts left to resolve.
+fn merge_into_current(
+    commands: &mut Commands,
+    voxel_db: &VoxelDb,
+    trees: &ChunkTrees,
+    current: u64,
+    theirs: u64,
+    base: u64,
+    resolutions: &MergeResolutions,
+    indexer: &ChunkIndexer3,
+) -> EditorResult<bool> {
+    let base_chunks = read_version_chunks(voxel_db, trees, base, current)?;
+    let ours_chunks = read_all_chunks(voxel_db)?;
+    let theirs_chunks = read_version_chunks(voxel_db, trees, theirs, current)?;
+    let merged = match three_way_merge(
+        &base_chunks,
+        &ours_chunks,
+        &theirs_chunks,
+        indexer,
+        resolutions,
+    ) {
+        Ok(merged) => merged,
+        Err(conflicts) => {
+            log_conflicts(&conflicts);
+            commands.insert_resource(PendingMerge {
+                theirs,
+                base,
+                conflicts,
+                resolutions: resolutions.clone(),
+            });
+            return Ok(false);
+        }
+    };
+    commands.remove_resource::<PendingMerge>();
+
+    log::info!(
+        "Merging {} chunks from version {} (base {})",
+        merged.len(),
+        theirs,
+        base
+    );
+    let deltas = merged.iter().map(|(key, chunk)| {
+        if is_ambient(chunk) {
+            Delta::Remove(*key)
+        } else {
+            Delta::Insert(*key, MaybeCompressed::Decompressed(chunk))
+        }
+    });
+    let chunk_db = voxel_db.chunks();
+    let mut batch = chunk_db.start_delta_batch();
+    futures::executor::block_on(batch.add_and_compress_deltas(deltas));
+    chunk_db
+        .apply_deltas_to_current_version(batch.build())
+        .map_err(EditorError::chunk_db)?;
+
+    Ok(true)
+}
+
+fn log_conflicts(conflicts: &[MergeConflict]) {
+    log::warn!(
+        "The merge has {} conflicting chunks. Choose a side for each one, then merge again",
+        conflicts.len()
+    );
+    for conflict in conflicts.iter() {
+        log::warn!(
+            "  chunk at {:?}: {} conflicting voxels",
+            conflict.key.minimum.0,
+            conflict.num_unresolved_voxels
+        );
+    }
+}
+
+/// Inserts the `VersionDiff` resource, which is rendered by the `DiffViewPlugin`.
+pub fn version_diff_system(
+    mut commands: Commands,
+    mut events: EventReader<DiffCommand>,
+    editor_db: Res<EditorDb>,
+    trees: Res<ChunkTrees>,
+    voxel_db: Res<VoxelDb>,
+    saver: Res<MapSaver>,
+    config: Res<Config>,
+    mut errors: EventWriter<EditorError>,
+) {
+    for event in events.iter() {
+        let (from, to) = match event {
+            DiffCommand::Show { from, to } => (*from, *to),
+            DiffCommand::Clear => {
+                commands.remove_resource::<VersionDiff>();
+                continue;
+            }
+        };
+        if saver.is_saving() {
+            log::warn!("Wait for the save to finish before comparing versions");
+            continue;
+        }
+
+        let read_chunks = || -> EditorResult<_> {
+            let current = editor_db.require_current_version()?;
+            Ok((
+                read_version_chunks(&voxel_db, &trees, from, current)?,
+                read_version_chunks(&voxel_db, &trees, to, current)?,
+            ))
+        };
+        let (from_chunks, to_chunks) = match read_chunks() {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                errors.send(e);
+                continue;
+            }
+        };
+        let indexer = ChunkIndexer3::new(config.feldspar.map.chunk_shape());
+        let diff = VersionDiff::new(from, to, &from_chunks, &to_chunks, &indexer);
+
+        log_diff(&diff);
+        commands.insert_resource(diff);
+    }
+}
+
+fn log_diff(diff: &VersionDiff) {
+    let count = |change| diff.chunks.iter().filter(|c| c.change == change).count();
+    log::info!(
+        "Version {} -> {}: {} voxels changed in {} chunks ({} added, {} removed, {} modified)",
+        diff.from,
+        diff.to,
+        diff.num_changed_voxels(),
+        diff.chunks.len(),
+        count(ChunkChange::Added),
+        count(ChunkChange::Removed),
+        count(ChunkChange::Modified),
+    );
+    let mut voxel_types: Vec<u8> = diff
+        .added_voxels
+        .keys()
+        .chain(diff.removed_voxels.keys())
+        .cloned()
+        .collect();
+    voxel_types.sort_unstable();
+    voxel_types.dedup();
+    for voxel_type in voxel_types.into_iter() {
+        log::info!(
+            "  voxel type {}: +{} -{}",
+            voxel_type,
+            diff.added_voxels.get(&voxel_type).unwrap_or(&0),
+            diff.removed_voxels.get(&voxel_type).unwrap_or(&0),
+        );
+    }
+}
+
+/// Every version in the graph, in order of creation.
+pub fn list_versions(editor_db: &EditorDb) -> EditorResult<Vec<(u64, VersionInfo)>> {
+    editor_db.read_versions()
+}
+
+/// Only committed versions can have children, since the working version is edited in place, and
+/// the history under a version must never change. The inner error says why `version` can't be a
+/// parent.
+fn check_can_have_children(
+    editor_db: &EditorDb,
+    version: u64,
+) -> EditorResult<Result<(), &'static str>> {
+    Ok(match editor_db.read_version(version)? {
+        Some(info) if info.is_committed() => Ok(()),
+        Some(_) => Err("commit it first"),
+        None => Err("it doesn't exist"),
+    })
+}
+
+fn has_children(editor_db: &EditorDb, version: u64) -> EditorResult<bool> {
+    Ok(list_versions(editor_db)?
+        .iter()
+        .any(|(_, info)| info.parent == Some(version)))
+}
+
+/// The newest uncommitted child of `version` on `branch`, which checking out `version` again should
+/// return to instead of starting yet another empty working version.
+fn working_child(editor_db: &EditorDb, version: u64, branch: &str) -> EditorResult<Option<u64>> {
+    Ok(list_versions(editor_db)?
+        .into_iter()
+        .filter(|(_, info)| {
+            info.parent == Some(version) && info.branch == branch && !info.is_committed()
+        })
+        .map(|(v, _)| v)
+        .max())
+}
+
+fn new_working_version(
+    editor_db: &EditorDb,
+    trees: &ChunkTrees,
+    parent: u64,
+    branch: String,
+) -> EditorResult<u64> {
+    let version = trees
+        .versions
+        .transaction(|versions| create_child_version(TransactionalVersionForest(versions), parent))
+        .map_err(EditorError::chunk_db)?;
+    editor_db.write_version(version, &VersionInfo::new(Some(parent), branch))?;
+
+    Ok(version)
+}
+
+/// Both handles to the chunk database know which version they write to, so they're replaced
+/// whenever the current version changes.
+fn replace_chunk_db(
+    commands: &mut Commands,
+    saver: &mut MapSaver,
+    trees: &ChunkTrees,
+    version: u64,
+) {
+    saver.set_voxel_db(trees.open_voxel_db(version));
+    commands.insert_resource(trees.open_voxel_db(version));
+}
+
+/// Clears every chunk in the map, so that the `ChunkStreamer` loads them again from the version
+/// that was checked out.
+fn unload_map(editor: &mut VoxelEditor, streamer: &mut ChunkStreamer) {
+    let loaded_keys: Vec<ChunkKey3> = editor
+        .map
+        .voxels
+        .storage()
+        .into_iter()
+        .map(|(k, _)| *k)
+        .collect();
+    for key in loaded_keys.into_iter() {
+        let extent = editor
+            .map
+            .voxels
+            .indexer
+            .extent_for_chunk_with_min(key.minimum);
+        editor.write_chunk_and_touch_neighbors(key.minimum, ambient_sdf_array(extent));
+    }
+    streamer.reset();
+}
+
+fn read_all_chunks(voxel_db: &VoxelDb) -> EditorResult<HashMap<ChunkKey3, SdfArray>> {
+    Ok(voxel_db
+        .chunks()
+        .read_all_chunks(0)
+        .map_err(EditorError::chunk_db)?
+        .into_iter()
+        .map(|(key, chunk)| (key, chunk.decompress()))
+        .collect())
+}
+
+/// Only the chunks of the current version are stored directly, so reading any other version means
+/// switching to it. That's done on a temporary copy of the chunk trees, so the live chunk database
+/// never leaves the current version, even if something fails halfway.
+fn read_version_chunks(
+    voxel_db: &VoxelDb,
+    trees: &ChunkTrees,
+    version: u64,
+    current: u64,
+) -> EditorResult<HashMap<ChunkKey3, SdfArray>> {
+    if version == current {
+        return read_all_chunks(voxel_db);
+    }
+
+    let version_db = trees.temporary_copy()?.open_voxel_db(current);
+    version_db
+        .chunks()
+        .switch_version(version)
+        .map_err(EditorError::chunk_db)?;
+
+    read_all_chunks(&version_db)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        database::forest_builder::{
+            tests::{solid_voxels, write_test_world},
+            ForestBuilder,
+        },
+        ChunkCodec,
+    };
+
+    use feldspar::bb::storage::database::sled;
+
+    #[test]
+    fn only_committed_versions_can_be_branched_from() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let editor_db = EditorDb::new(db.open_tree("editor").unwrap());
+
+        let mut committed = VersionInfo::new(None, MAIN_BRANCH.to_string());
+        committed.message = Some("first".to_string());
+        editor_db.write_version(1, &committed).unwrap();
+        let working = VersionInfo::new(Some(1), MAIN_BRANCH.to_string());
+        editor_db.write_version(2, &working).unwrap();
+
+        assert_eq!(check_can_have_children(&editor_db, 1).unwrap(), Ok(()));
+        assert!(check_can_have_children(&editor_db, 2).unwrap().is_err());
+        assert!(check_can_have_children(&editor_db, 3).unwrap().is_err());
+
+        assert!(has_children(&editor_db, 1).unwrap());
+        assert!(!has_children(&editor_db, 2).unwrap());
+    }
+
+    #[test]
+    fn checkout_reuses_the_working_version_on_the_same_branch() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let editor_db = EditorDb::new(db.open_tree("editor").unwrap());
+
+        let mut committed = VersionInfo::new(None, MAIN_BRANCH.to_string());
+        committed.message = Some("first".to_string());
+        editor_db.write_version(1, &committed).unwrap();
+        assert_eq!(working_child(&editor_db, 1, MAIN_BRANCH).unwrap(), None);
+
+        let mut other_branch = VersionInfo::new(Some(1), "cave".to_string());
+        editor_db.write_version(2, &other_branch).unwrap();
+        assert_eq!(working_child(&editor_db, 1, MAIN_BRANCH).unwrap(), None);
+
+        let working = VersionInfo::new(Some(1), MAIN_BRANCH.to_string());
+        editor_db.write_version(3, &working).unwrap();
+        assert_eq!(working_child(&editor_db, 1, MAIN_BRANCH).unwrap(), Some(3));
+        assert_eq!(working_child(&editor_db, 1, "cave").unwrap(), Some(2));
+
+        other_branch.message = Some("dig".to_string());
+        editor_db.write_version(2, &other_branch).unwrap();
+        assert_eq!(working_child(&editor_db, 1, "cave").unwrap(), None);
+    }
+
+    #[test]
+    fn reading_another_version_leaves_the_current_one_checked_out() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let world = write_test_world(&db, ChunkCodec::default(), 0).unwrap();
+        let trees = ForestBuilder::new(&db, ChunkCodec::default())
+            .unwrap()
+            .trees;
+        let voxel_db = trees.open_voxel_db(world.current);
+
+        let first = world.version(0);
+        let first_chunks = read_version_chunks(&voxel_db, &trees, first, world.current).unwrap();
+        assert_eq!(solid_voxels(&first_chunks), world.versions[0].1);
+
+        let current_chunks = read_all_chunks(&voxel_db).unwrap();
+        assert_eq!(
+            solid_voxels(&current_chunks),
+            world.versions.last().unwrap().1
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use feldspar::{
+    bb::prelude::*,
+    prelude::{ambient_sdf_array, SdfArray, VoxelType},
+};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// The differences between the chunks of two versions, with the chunks that changed in order of
+/// their keys.
+pub struct VersionDiff {
+    pub from: u64,
+    pub to: u64,
+    pub chunks: Vec<ChunkDiff>,
+    /// The number of voxels of each type that became solid.
+    pub added_voxels: BTreeMap<u8, usize>,
+    /// The number of voxels of each type that stopped being solid, or changed to another type.
+    pub removed_voxels: BTreeMap<u8, usize>,
+}
+
+pub struct ChunkDiff {
+    pub key: ChunkKey3,
+    pub extent: Extent3i,
+    pub change: ChunkChange,
+    pub num_changed_voxels: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChunkChange {
+    /// The chunk only exists in the newer version.
+    Added,
+    /// The chunk only exists in the older version.
+    Removed,
+    Modified,
+}
+
+impl VersionDiff {
+    /// Compares every voxel of every chunk in either version. A chunk that's missing from a
+    /// version is treated as ambient.
+    pub fn new(
+        from: u64,
+        to: u64,
+        from_chunks: &HashMap<ChunkKey3, SdfArray>,
+        to_chunks: &HashMap<ChunkKey3, SdfArray>,
+        indexer: &ChunkIndexer3,
+    ) -> Self {
+        let mut keys: Vec<ChunkKey3> = from_chunks
+            .keys()
+            .chain(to_chunks.keys())
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        keys.sort_by_key(|k| (k.lod, k.minimum.0));
+
+        let mut diff = Self {
+            from,
+            to,
+            chunks: Vec::new(),
+            added_voxels: BTreeMap::new(),
+            removed_voxels: BTreeMap::new(),
+        };
+        for key in keys.into_iter() {
+            let extent = indexer.extent_for_chunk_with_min(key.minimum);
+            let (old_chunk, new_chunk) = (from_chunks.get(&key), to_chunks.get(&key));
+            let change = match (old_chunk, new_chunk) {
+                (None, _) => ChunkChange::Added,
+                (_, None) => ChunkChange::Removed,
+                _ => ChunkChange::Modified,
+            };
+            let ambient = ambient_sdf_array(extent);
+            let num_changed_voxels = diff.add_voxel_changes(
+                old_chunk.unwrap_or(&ambient),
+                new_chunk.unwrap_or(&ambient),
+                extent,
+            );
+            if num_changed_voxels > 0 {
+                diff.chunks.push(ChunkDiff {
+                    key,
+                    extent,
+                    change,
+                    num_changed_voxels,
+                });
+            }
+        }
+
+        diff
+    }
+
+    pub fn num_changed_voxels(&self) -> usize {
+        self.chunks.iter().map(|c| c.num_changed_voxels).sum()
+    }
+
+    fn add_voxel_changes(&mut self, old: &SdfArray, new: &SdfArray, extent: Extent3i) -> usize {
+        let mut num_changed = 0;
+        for p in extent.iter_points() {
+            let (old_type, old_dist): (VoxelType, Sd8) = old.get(p);
+            let (new_type, new_dist): (VoxelType, Sd8) = new.get(p);
+            if (old_type, old_dist) == (new_type, new_dist) {
+                continue;
+            }
+            num_changed += 1;
+
+            let (old_solid, new_solid) = (old_dist.0 < 0, new_dist.0 < 0);
+            let same_type = old_type == new_type;
+            if old_solid && (!new_solid || !same_type) {
+                *self.removed_voxels.entry(old_type.0).or_insert(0) += 1;
+            }
+            if new_solid && (!old_solid || !same_type) {
+                *self.added_voxels.entry(new_type.0).or_insert(0) += 1;
+            }
+        }
+
+        num_changed
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK_SHAPE: Point3i = PointN([4; 3]);
+
+    fn chunk_with(min: [i32; 3], solid: &[([i32; 3], u8)]) -> (ChunkKey3, SdfArray) {
+        let indexer = ChunkIndexer3::new(CHUNK_SHAPE);
+        let mut chunk = ambient_sdf_array(indexer.extent_for_chunk_with_min(PointN(min)));
+        for (p, voxel_type) in solid.iter() {
+            let (t, d) = chunk.get_mut(PointN(*p));
+            *t = VoxelType(*voxel_type);
+            *d = Sd8(-1);
+        }
+
+        (ChunkKey::new(0, PointN(min)), chunk)
+    }
+
+    #[test]
+    fn diff_counts_voxels_per_type_and_classifies_chunks() {
+        let indexer = ChunkIndexer3::new(CHUNK_SHAPE);
+        let from: HashMap<_, _> = vec![
+            chunk_with([0; 3], &[([0; 3], 1), ([1, 0, 0], 1)]),
+            chunk_with([4, 0, 0], &[([4, 0, 0], 2)]),
+            chunk_with([8, 0, 0], &[([8, 0, 0], 3)]),
+        ]
+        .into_iter()
+        .collect();
+        let to: HashMap<_, _> = vec![
+            // One voxel changes type, the other stays the same.
+            chunk_with([0; 3], &[([0; 3], 2), ([1, 0, 0], 1)]),
+            // Same contents, so it's not part of the diff.
+            chunk_with([8, 0, 0], &[([8, 0, 0], 3)]),
+            chunk_with([0, 4, 0], &[([0, 4, 0], 1), ([0, 5, 0], 1)]),
+        ]
+        .into_iter()
+        .collect();
+
+        let diff = VersionDiff::new(1, 2, &from, &to, &indexer);
+
+        let changes: Vec<_> = diff
+            .chunks
+            .iter()
+            .map(|c| (c.key.minimum.0, c.change, c.num_changed_voxels))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ([0, 0, 0], ChunkChange::Modified, 1),
+                ([0, 4, 0], ChunkChange::Added, 2),
+                ([4, 0, 0], ChunkChange::Removed, 1),
+            ]
+        );
+        assert_eq!(diff.num_changed_voxels(), 4);
+        assert_eq!(
+            diff.added_voxels,
+            vec![(1, 2), (2, 1)].into_iter().collect::<BTreeMap<_, _>>()
+        );
+        assert_eq!(
+            diff.removed_voxels,
+            vec![(1, 1), (2, 1)].into_iter().collect::<BTreeMap<_, _>>()
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use feldspar::{
+    bb::prelude::*,
+    prelude::{ambient_sdf_array, SdfArray, VoxelType},
+};
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MergeSide {
+    /// The version being merged into.
+    Ours,
+    /// The version being merged.
+    Theirs,
+}
+
+/// Which side to take for voxels that were changed differently on both sides.
+#[derive(Clone, Default)]
+pub struct MergeResolutions {
+    pub chunks: HashMap<ChunkKey3, MergeSide>,
+    /// Later regions take precedence over earlier ones, and chunk resolutions over all regions.
+    pub regions: Vec<(Extent3i, MergeSide)>,
+}
+
+impl MergeResolutions {
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.regions.is_empty()
+    }
+
+    fn side_for(&self, key: ChunkKey3, p: Point3i) -> Option<MergeSide> {
+        self.chunks.get(&key).cloned().or_else(|| {
+            self.regions
+                .iter()
+                .rev()
+                .find(|(region, _)| region.contains(p))
+                .map(|(_, side)| *side)
+        })
+    }
+}
+
+/// A chunk with voxels that were changed differently on both sides, and haven't been resolved.
+#[derive(Clone, Debug)]
+pub struct MergeConflict {
+    pub key: ChunkKey3,
+    pub extent: Extent3i,
+    pub num_unresolved_voxels: usize,
+}
+
+/// Merges `theirs` into `ours`, voxel by voxel. Voxels that only changed on one side since `base`
+/// are merged automatically, and voxels that changed differently on both sides are taken from
+/// the side chosen in `resolutions`. A chunk that's missing from a version is treated as ambient.
+///
+/// Returns the merged chunks that are different from `ours`, or every chunk that still has
+/// unresolved conflicts.
+pub fn three_way_merge(
+    base: &HashMap<ChunkKey3, SdfArray>,
+    ours: &HashMap<ChunkKey3, SdfArray>,
+    theirs: &HashMap<ChunkKey3, SdfArray>,
+    indexer: &ChunkIndexer3,
+    resolutions: &MergeResolutions,
+) -> Result<Vec<(ChunkKey3, SdfArray)>, Vec<MergeConflict>> {
+    let mut keys: Vec<ChunkKey3> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    keys.sort_by_key(|k| (k.lod, k.minimum.0));
+
+    let mut merged_chunks = Vec::new();
+    let mut conflicts = Vec::new();
+    for key in keys.into_iter() {
+        let extent = indexer.extent_for_chunk_with_min(key.minimum);
+        let ambient = ambient_sdf_array(extent);
+        let base_chunk = base.get(&key).unwrap_or(&ambient);
+        let theirs_chunk = theirs.get(&key).unwrap_or(&ambient);
+        let mut merged = ours.get(&key).cloned().unwrap_or_else(|| ambient.clone());
+
+        let mut changed = false;
+        let mut num_unresolved_voxels = 0;
+        for p in extent.iter_points() {
+            let base_voxel: (VoxelType, Sd8) = base_chunk.get(p);
+            let ours_voxel: (VoxelType, Sd8) = merged.get(p);
+            let theirs_voxel: (VoxelType, Sd8) = theirs_chunk.get(p);
+            if ours_voxel == theirs_voxel || theirs_voxel == base_voxel {
+                continue;
+            }
+            let take_theirs = if ours_voxel == base_voxel {
+                true
+            } else {
+                match resolutions.side_for(key, p) {
+                    Some(side) => side == MergeSide::Theirs,
+                    None => {
+                        num_unresolved_voxels += 1;
+                        false
+                    }
+                }
+            };
+            if take_theirs {
+                let (t, d) = merged.get_mut(p);
+                *t = theirs_voxel.0;
+                *d = theirs_voxel.1;
+                changed = true;
+            }
+        }
+
+        if num_unresolved_voxels > 0 {
+            conflicts.push(MergeConflict {
+                key,
+                extent,
+                num_unresolved_voxels,
+            });
+        } else if changed {
+            merged_chunks.push((key, merged));
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(merged_chunks)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// The most recent version that both `a` and `b` descend from, given the parents of every
+/// version. Merge commits have the merged version as a second parent, so merging the same branch
+/// again only considers what changed since the last merge.
+pub fn common_ancestor(parents: &HashMap<u64, Vec<u64>>, a: u64, b: u64) -> Option<u64> {
+    let common: HashSet<u64> = ancestors(parents, a)
+        .intersection(&ancestors(parents, b))
+        .cloned()
+        .collect();
+
+    // Version numbers aren't in topological order, because a working version can be older than
+    // the version merged into it, so pick a common ancestor that no other one descends from.
+    common
+        .iter()
+        .filter(|&&v| {
+            common
+                .iter()
+                .all(|&other| other == v || !ancestors(parents, other).contains(&v))
+        })
+        .max()
+        .cloned()
+}
+
+/// `version` and every version it descends from.
+fn ancestors(parents: &HashMap<u64, Vec<u64>>, version: u64) -> HashSet<u64> {
+    let mut ancestors = HashSet::new();
+    let mut stack = vec![version];
+    while let Some(v) = stack.pop() {
+        if ancestors.insert(v) {
+            stack.extend(parents.get(&v).into_iter().flatten());
+        }
+    }
+
+    ancestors
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK_SHAPE: Point3i = PointN([4; 3]);
+
+    fn map_with(solid: &[([i32; 3], u8)]) -> HashMap<ChunkKey3, SdfArray> {
+        let indexer = ChunkIndexer3::new(CHUNK_SHAPE);
+        let mut map = HashMap::new();
+        for (p, voxel_type) in solid.iter() {
+            let p = PointN(*p);
+            let chunk_min = indexer.min_of_chunk_containing_point(p);
+            let chunk = map
+                .entry(ChunkKey::new(0, chunk_min))
+                .or_insert_with(|| ambient_sdf_array(indexer.extent_for_chunk_with_min(chunk_min)));
+            let (t, d) = chunk.get_mut(p);
+            *t = VoxelType(*voxel_type);
+            *d = Sd8(-1);
+        }
+
+        map
+    }
+
+    fn voxel_type_at(chunks: &[(ChunkKey3, SdfArray)], p: [i32; 3]) -> Option<u8> {
+        chunks
+            .iter()
+            .find(|(_, c)| c.extent().contains(PointN(p)))
+            .map(|(_, c)| {
+                let (t, _): (VoxelType, Sd8) = c.get(PointN(p));
+                t.0
+            })
+    }
+
+    #[test]
+    fn changes_on_one_side_merge_automatically() {
+        let indexer = ChunkIndexer3::new(CHUNK_SHAPE);
+        let base = map_with(&[([0; 3], 1)]);
+        let ours = map_with(&[([0; 3], 1), ([1, 0, 0], 2)]);
+        let theirs = map_with(&[([0; 3], 3), ([8, 0, 0], 3)]);
+
+        let merged = three_way_merge(
+            &base,
+            &ours,
+            &theirs,
+            &indexer,
+            &MergeResolutions::default(),
+        )
+        .unwrap();
+
+        // Only chunks that differ from ours are returned.
+        assert_eq!(merged.len(), 2);
+        assert_eq!(voxel_type_at(&merged, [0; 3]), Some(3));
+        assert_eq!(voxel_type_at(&merged, [1, 0, 0]), Some(2));
+        assert_eq!(voxel_type_at(&merged, [8, 0, 0]), Some(3));
+    }
+
+    #[test]
+    fn conflicts_are_reported_until_resolved() {
+        let indexer = ChunkIndexer3::new(CHUNK_SHAPE);
+        let base = map_with(&[]);
+        let ours = map_with(&[([0; 3], 1), ([4, 0, 0], 1), ([5, 0, 0], 1)]);
+        let theirs = map_with(&[([0; 3], 2), ([4, 0, 0], 2), ([5, 0, 0], 2)]);
+
+        let conflicts = three_way_merge(
+            &base,
+            &ours,
+            &theirs,
+            &indexer,
+            &MergeResolutions::default(),
+        )
+        .unwrap_err();
+        let conflicts: Vec<_> = conflicts
+            .iter()
+            .map(|c| (c.key.minimum.0, c.num_unresolved_voxels))
+            .collect();
+        assert_eq!(conflicts, vec![([0, 0, 0], 1), ([4, 0, 0], 2)]);
+
+        let mut resolutions = MergeResolutions::default();
+        resolutions
+            .chunks
+            .insert(ChunkKey::new(0, PointN([0; 3])), MergeSide::Theirs);
+        resolutions.regions.push((
+            Extent3i::from_min_and_shape(PointN([4, 0, 0]), PointN([1; 3])),
+            MergeSide::Ours,
+        ));
+        // Only one of the conflicting voxels in the second chunk is covered by the region.
+        assert!(three_way_merge(&base, &ours, &theirs, &indexer, &resolutions).is_err());
+
+        resolutions.regions.push((
+            Extent3i::from_min_and_shape(PointN([5, 0, 0]), PointN([1; 3])),
+            MergeSide::Theirs,
+        ));
+        let merged = three_way_merge(&base, &ours, &theirs, &indexer, &resolutions).unwrap();
+        assert_eq!(voxel_type_at(&merged, [0; 3]), Some(2));
+        assert_eq!(voxel_type_at(&merged, [4, 0, 0]), Some(1));
+        assert_eq!(voxel_type_at(&merged, [5, 0, 0]), Some(2));
+    }
+
+    #[test]
+    fn common_ancestor_of_two_branches() {
+        // 1 -> 2 -> 3 -> 4
+        //       \-> 5 -> 6
+        let parents: HashMap<u64, Vec<u64>> = vec![
+            (1, vec![]),
+            (2, vec![1]),
+            (3, vec![2]),
+            (4, vec![3]),
+            (5, vec![2]),
+            (6, vec![5]),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(common_ancestor(&parents, 4, 6), Some(2));
+        assert_eq!(common_ancestor(&parents, 3, 4), Some(3));
+        assert_eq!(common_ancestor(&parents, 1, 6), Some(1));
+    }
+
+    #[test]
+    fn merging_the_same_branch_again_starts_from_the_last_merge() {
+        // 1 -> 2 -----> 3 (merged 6) -> 7 -> 8
+        //  \-> 4 -> 6 -----------------------> 9
+        // 3 was the working version of main when 6 was merged into it, so it has a smaller number.
+        let parents: HashMap<u64, Vec<u64>> = vec![
+            (1, vec![]),
+            (2, vec![1]),
+            (3, vec![2, 6]),
+            (4, vec![1]),
+            (6, vec![4]),
+            (7, vec![3]),
+            (8, vec![7]),
+            (9, vec![6]),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(common_ancestor(&parents, 8, 9), Some(6));
+        assert_eq!(common_ancestor(&parents, 2, 9), Some(1));
+        assert_eq!(common_ancestor(&parents, 3, 6), Some(6));
+    }
+}
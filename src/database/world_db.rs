@@ -0,0 +1,412 @@
+use super::{ChunkTrees, EditorDb, VersionInfo};
+
+use crate::{EditorError, EditorResult, StorageConfig};
+
+use feldspar::{
+    bb::storage::database::{sled, sled_snapshots::open_snapshot_forest},
+    prelude::VoxelDb,
+};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+/// A world database opened without the editor, so it can be inspected and maintained from the
+/// command line. Unlike `open_voxel_database`, it never creates a world, and it doesn't mark the
+/// editor session as open.
+pub struct WorldDb {
+    pub(super) db: sled::Db,
+    pub(super) editor_db: EditorDb,
+    pub(super) trees: ChunkTrees,
+}
+
+/// The chunks of one level of detail in a version.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LodStats {
+    pub num_chunks: usize,
+    /// The size of the chunks as they're stored, after compression.
+    pub compressed_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub num_versions: usize,
+    pub num_chunks: usize,
+    /// Everything that's wrong, one per line.
+    pub problems: Vec<String>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl WorldDb {
+    /// `storage` has to match the config that the database was created with.
+    pub fn open(path: &str, storage: &StorageConfig) -> EditorResult<Self> {
+        Self::from_db(open_existing_sled(path, storage)?, storage)
+    }
+
+    /// Opens a temporary copy of the database at `path`, for commands that only read. Reading
+    /// any version but the current one writes to the database, so they don't touch the original.
+    pub fn open_read_only(path: &str, storage: &StorageConfig) -> EditorResult<Self> {
+        Self::from_db(open_temporary_copy(path, storage)?, storage)
+    }
+
+    fn from_db(db: sled::Db, storage: &StorageConfig) -> EditorResult<Self> {
+        let editor_db = EditorDb::new(db.open_tree("editor")?);
+        let chunks = db.open_tree("chunks")?;
+        let (versions, deltas) =
+            open_snapshot_forest(&db, "chunks").map_err(EditorError::chunk_db)?;
+
+        Ok(Self {
+            db,
+            editor_db,
+            trees: ChunkTrees {
+                chunks,
+                versions,
+                deltas,
+                codec: storage.chunk_codec,
+            },
+        })
+    }
+
+    pub fn size_on_disk(&self) -> EditorResult<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+
+    /// The version graph in order of creation, and the current version.
+    pub fn versions(&self) -> EditorResult<(Vec<(u64, VersionInfo)>, u64)> {
+        Ok((
+            self.editor_db.read_versions()?,
+            self.editor_db.require_current_version()?,
+        ))
+    }
+
+    /// The number and size of the chunks in `version`, by LOD.
+    pub fn chunk_stats(&self, version: u64) -> EditorResult<BTreeMap<u8, LodStats>> {
+        self.with_version(version, |_| {
+            let mut stats = BTreeMap::<u8, LodStats>::new();
+            for entry in self.trees.chunks.iter() {
+                let (key, value) = entry?;
+                let lod_stats = stats.entry(chunk_key_lod(&key)?).or_default();
+                lod_stats.num_chunks += 1;
+                lod_stats.compressed_bytes += value.len() as u64;
+            }
+
+            Ok(stats)
+        })
+    }
+
+    /// Decompresses every chunk of every version, and checks that the version graph is connected.
+    /// Problems are collected in the report instead of stopping the check.
+    pub fn check_integrity(&self) -> EditorResult<IntegrityReport> {
+        let (versions, current) = self.versions()?;
+        let mut report = IntegrityReport {
+            num_versions: versions.len(),
+            ..Default::default()
+        };
+
+        let version_ids: HashSet<u64> = versions.iter().map(|(v, _)| *v).collect();
+        if !version_ids.contains(&current) {
+            report
+                .problems
+                .push(format!("Current version {} has no version info", current));
+        }
+        for (version, info) in versions.iter() {
+            for parent in info.parents() {
+                if !version_ids.contains(&parent) {
+                    report.problems.push(format!(
+                        "Version {} has a parent {} that doesn't exist",
+                        version, parent
+                    ));
+                }
+            }
+        }
+
+        for (version, _) in versions.iter() {
+            let check = self.with_version(*version, |voxel_db| {
+                check_version_chunks(*version, voxel_db, &self.trees.chunks, &mut report)
+            });
+            if let Err(e) = check {
+                report
+                    .problems
+                    .push(format!("Version {} can't be read: {}", version, e));
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Only the chunks of the current version are stored directly, so reading any other version
+    /// means switching to it and back again. That rewrites the chunk trees, so a world that's only
+    /// inspected should be opened with `open_read_only`.
+    pub(super) fn with_version<T>(
+        &self,
+        version: u64,
+        read: impl FnOnce(&VoxelDb) -> EditorResult<T>,
+    ) -> EditorResult<T> {
+        let current = self.editor_db.require_current_version()?;
+        let voxel_db = self.trees.open_voxel_db(current);
+        if version == current {
+            return read(&voxel_db);
+        }
+
+        let chunks = voxel_db.chunks();
+        chunks
+            .switch_version(version)
+            .map_err(EditorError::chunk_db)?;
+        let result = read(&voxel_db);
+        chunks
+            .switch_version(current)
+            .map_err(EditorError::chunk_db)?;
+
+        result
+    }
+}
+
+fn check_version_chunks(
+    version: u64,
+    voxel_db: &VoxelDb,
+    chunks_tree: &sled::Tree,
+    report: &mut IntegrityReport,
+) -> EditorResult<()> {
+    for lod in chunk_lods(chunks_tree)?.into_iter() {
+        let chunks = match voxel_db.chunks().read_all_chunks(lod) {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                report.problems.push(format!(
+                    "Version {} LOD {} failed to decode: {:?}",
+                    version, lod, e
+                ));
+                continue;
+            }
+        };
+        for (key, chunk) in chunks.into_iter() {
+            report.num_chunks += 1;
+            // A corrupt compressed chunk makes the decompressor panic.
+            if catch_unwind(AssertUnwindSafe(|| chunk.decompress())).is_err() {
+                report.problems.push(format!(
+                    "Version {} chunk {:?} at LOD {} failed to decompress",
+                    version, key.minimum, lod
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Every LOD that has chunks in the version that `chunks_tree` holds.
+pub(super) fn chunk_lods(chunks_tree: &sled::Tree) -> EditorResult<BTreeSet<u8>> {
+    let mut lods = BTreeSet::new();
+    for entry in chunks_tree.iter() {
+        let (key, _) = entry?;
+        lods.insert(chunk_key_lod(&key)?);
+    }
+
+    Ok(lods)
+}
+
+/// Chunk keys are ordered by LOD first, so the LOD is the first byte.
+fn chunk_key_lod(key: &[u8]) -> EditorResult<u8> {
+    key.first()
+        .cloned()
+        .ok_or_else(|| EditorError::Corrupt("empty chunk key".to_string()))
+}
+
+/// Rewrites the database at `path` into a fresh copy, which leaves behind the space taken by
+/// overwritten and removed records, like the deltas of versions that were switched away from.
+/// Returns the size on disk before and after.
+pub fn compact_world_db(path: &str, storage: &StorageConfig) -> EditorResult<(u64, u64)> {
+    let old_size = WorldDb::open(path, storage)?.size_on_disk()?;
+
+    let compacted_path = format!("{}.compacted", path);
+    {
+        let old_db = WorldDb::open(path, storage)?.db;
+        let new_db = open_sled(&compacted_path, storage)?;
+        new_db.import(old_db.export());
+        new_db.flush()?;
+    }
+    replace_database(path, &compacted_path)?;
+
+    let new_size = WorldDb::open(path, storage)?.size_on_disk()?;
+
+    Ok((old_size, new_size))
+}
+
+/// Opens the database at `path`, or creates it if there isn't one.
+pub(super) fn open_sled(path: &str, storage: &StorageConfig) -> sled::Result<sled::Db> {
+    sled_config(storage).path(path).open()
+}
+
+/// The sled settings for a database with `storage`, without a path.
+pub(super) fn sled_config(storage: &StorageConfig) -> sled::Config {
+    let config = sled::Config::default()
+        .use_compression(storage.sled_compression_factor.is_some())
+        .mode(sled::Mode::LowSpace);
+    match storage.sled_compression_factor {
+        Some(factor) => config.compression_factor(factor),
+        None => config,
+    }
+}
+
+/// Unlike `open_sled`, fails if there's no database at `path`.
+fn open_existing_sled(path: &str, storage: &StorageConfig) -> EditorResult<sled::Db> {
+    let to_editor_error = |error| EditorError::OpenDatabase {
+        path: path.to_string(),
+        error,
+    };
+    if !Path::new(path).exists() {
+        // Otherwise sled would create an empty database.
+        return Err(to_editor_error(sled::Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no database at this path",
+        ))));
+    }
+
+    open_sled(path, storage).map_err(to_editor_error)
+}
+
+/// Copies the database at `path` into a temporary one, which is gone once it's dropped. The
+/// original is closed again before this returns.
+pub(super) fn open_temporary_copy(path: &str, storage: &StorageConfig) -> EditorResult<sled::Db> {
+    let original = open_existing_sled(path, storage)?;
+    let copy = sled::Config::default().temporary(true).open()?;
+    copy.import(original.export());
+
+    Ok(copy)
+}
+
+/// Moves the database at `new_path` to `path`. The original is only removed once it's out of the
+/// way, so a failure doesn't leave nothing behind.
+pub(super) fn replace_database(path: &str, new_path: &str) -> EditorResult<()> {
+    let backup_path = format!("{}.old", path);
+    let io_error = |e: io::Error| EditorError::Database(sled::Error::Io(e));
+    std::fs::rename(path, &backup_path).map_err(io_error)?;
+    std::fs::rename(new_path, path).map_err(io_error)?;
+    std::fs::remove_dir_all(&backup_path).map_err(io_error)
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::{forest_builder::tests::write_test_world, OpenedDatabase};
+    use crate::EditTimeline;
+
+    use feldspar::bb::prelude::PointN;
+
+    #[test]
+    fn new_world_is_intact_and_survives_compaction() {
+        let path = std::env::temp_dir().join(format!(
+            "feldspar_editor_world_db_test_{}",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let storage = StorageConfig::default();
+        assert!(WorldDb::open(path, &storage).is_err());
+        {
+            let db = sled::open(path).unwrap();
+            let mut edit_timeline = EditTimeline::new(PointN([16; 3]));
+            OpenedDatabase::open(&db, &mut edit_timeline, storage.chunk_codec).unwrap();
+        }
+
+        let world = WorldDb::open(path, &storage).unwrap();
+        let (versions, current) = world.versions().unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].0, current);
+        assert!(world.chunk_stats(current).unwrap().is_empty());
+        assert!(world.check_integrity().unwrap().is_ok());
+        drop(world);
+
+        compact_world_db(path, &storage).unwrap();
+        let world = WorldDb::open(path, &storage).unwrap();
+        assert_eq!(world.versions().unwrap().1, current);
+        assert!(world.check_integrity().unwrap().is_ok());
+        drop(world);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    /// Every entry of every tree in the database at `path`.
+    fn db_contents(
+        path: &str,
+        storage: &StorageConfig,
+    ) -> Vec<(sled::IVec, sled::IVec, sled::IVec)> {
+        let db = open_sled(path, storage).unwrap();
+        let mut contents = Vec::new();
+        for name in db.tree_names().into_iter() {
+            for entry in db.open_tree(&name).unwrap().iter() {
+                let (key, value) = entry.unwrap();
+                contents.push((name.clone(), key, value));
+            }
+        }
+
+        contents
+    }
+
+    #[test]
+    fn reading_other_versions_leaves_the_original_untouched() {
+        let path = std::env::temp_dir().join(format!(
+            "feldspar_editor_read_only_test_{}",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let storage = StorageConfig::default();
+        let test_world = {
+            let db = open_sled(path, &storage).unwrap();
+            let test_world = write_test_world(&db, storage.chunk_codec, 0).unwrap();
+            db.flush().unwrap();
+            test_world
+        };
+        let before = db_contents(path, &storage);
+
+        let world = WorldDb::open_read_only(path, &storage).unwrap();
+        let report = world.check_integrity().unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.num_versions, test_world.versions.len());
+        let stats = world.chunk_stats(test_world.version(0)).unwrap();
+        assert_eq!(stats[&0].num_chunks, 1);
+        assert_eq!(world.versions().unwrap().1, test_world.current);
+        drop(world);
+
+        assert!(db_contents(path, &storage) == before);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn writes_to_a_temporary_copy_stay_there() {
+        let path =
+            std::env::temp_dir().join(format!("feldspar_editor_copy_test_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let storage = StorageConfig::default();
+        assert!(open_temporary_copy(path, &storage).is_err());
+        {
+            let db = open_sled(path, &storage).unwrap();
+            db.insert("key", "original").unwrap();
+            db.flush().unwrap();
+        }
+
+        let copy = open_temporary_copy(path, &storage).unwrap();
+        assert_eq!(copy.get("key").unwrap().unwrap(), "original".as_bytes());
+        copy.insert("key", "edited").unwrap();
+        drop(copy);
+
+        let db = open_sled(path, &storage).unwrap();
+        assert_eq!(db.get("key").unwrap().unwrap(), "original".as_bytes());
+        drop(db);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
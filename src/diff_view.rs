@@ -0,0 +1,157 @@
+use crate::{ChunkChange, EditorState, PendingMerge, VersionDiff};
+
+use feldspar::bb::prelude::Extent3i;
+
+use bevy::{
+    app::prelude::*,
+    asset::prelude::*,
+    ecs::prelude::*,
+    math::prelude::*,
+    pbr::prelude::*,
+    render::{mesh::shape, prelude::*},
+    transform::components::Transform,
+};
+
+/// Draws a tinted box around every chunk in the `VersionDiff` resource, colored by whether the
+/// chunk was added, removed or modified. Chunks with unresolved merge conflicts are also boxed.
+pub struct DiffViewPlugin;
+
+impl Plugin for DiffViewPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(EditorState::Editing).with_system(initialize_diff_view.system()),
+        )
+        .add_system_set(
+            SystemSet::on_update(EditorState::Editing)
+                .with_system(diff_view_system.system())
+                .with_system(merge_conflict_view_system.system()),
+        );
+    }
+}
+
+struct DiffViewAssets {
+    cube: Handle<Mesh>,
+    added: Handle<StandardMaterial>,
+    removed: Handle<StandardMaterial>,
+    modified: Handle<StandardMaterial>,
+    conflict: Handle<StandardMaterial>,
+}
+
+/// Marks the boxes that highlight the current diff.
+struct DiffHighlight;
+
+/// Marks the boxes that highlight unresolved merge conflicts.
+struct ConflictHighlight;
+
+fn initialize_diff_view(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut tint = |mut color: Color| {
+        color.set_a(0.25);
+        materials.add(StandardMaterial::from(color))
+    };
+    let assets = DiffViewAssets {
+        cube: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+        added: tint(Color::GREEN),
+        removed: tint(Color::RED),
+        modified: tint(Color::ORANGE),
+        conflict: tint(Color::FUCHSIA),
+    };
+    commands.insert_resource(assets);
+}
+
+fn diff_view_system(
+    mut commands: Commands,
+    diff: Option<Res<VersionDiff>>,
+    assets: Res<DiffViewAssets>,
+    highlights: Query<Entity, With<DiffHighlight>>,
+) {
+    let needs_update = match &diff {
+        Some(diff) => diff.is_changed(),
+        None => highlights.iter().next().is_some(),
+    };
+    if !needs_update {
+        return;
+    }
+
+    for entity in highlights.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let diff = if let Some(diff) = diff {
+        diff
+    } else {
+        return;
+    };
+    for chunk in diff.chunks.iter() {
+        let material = match chunk.change {
+            ChunkChange::Added => assets.added.clone(),
+            ChunkChange::Removed => assets.removed.clone(),
+            ChunkChange::Modified => assets.modified.clone(),
+        };
+        commands
+            .spawn_bundle(chunk_box(&assets, chunk.extent, material))
+            .insert(DiffHighlight);
+    }
+}
+
+fn merge_conflict_view_system(
+    mut commands: Commands,
+    merge: Option<Res<PendingMerge>>,
+    assets: Res<DiffViewAssets>,
+    highlights: Query<Entity, With<ConflictHighlight>>,
+) {
+    let needs_update = match &merge {
+        Some(merge) => merge.is_changed(),
+        None => highlights.iter().next().is_some(),
+    };
+    if !needs_update {
+        return;
+    }
+
+    for entity in highlights.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let merge = if let Some(merge) = merge {
+        merge
+    } else {
+        return;
+    };
+    for conflict in merge.conflicts.iter() {
+        commands
+            .spawn_bundle(chunk_box(&assets, conflict.extent, assets.conflict.clone()))
+            .insert(ConflictHighlight);
+    }
+}
+
+fn chunk_box(
+    assets: &DiffViewAssets,
+    extent: Extent3i,
+    material: Handle<StandardMaterial>,
+) -> PbrBundle {
+    let min = extent.minimum;
+    let shape = extent.shape;
+    let shape = Vec3::new(shape.x() as f32, shape.y() as f32, shape.z() as f32);
+    let center = Vec3::new(min.x() as f32, min.y() as f32, min.z() as f32) + shape / 2.0;
+
+    PbrBundle {
+        mesh: assets.cube.clone(),
+        material,
+        // Slightly bigger than the chunk, so the box doesn't flicker against the surface.
+        transform: Transform {
+            translation: center,
+            scale: shape * BOX_SCALE,
+            ..Default::default()
+        },
+        visible: Visible {
+            is_transparent: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+const BOX_SCALE: f32 = 1.01;
@@ -1,25 +1,48 @@
 mod drag_face;
 mod edit_timeline;
+mod history_browser;
 mod plugin;
 mod selection;
+mod session;
 mod snapshotting_editor;
 mod terraformer;
 mod tool_switcher;
 mod undo;
+mod version_browser;
 
+pub use edit_timeline::{decode_chunk, encode_chunk, EditTimeline, HistoryChanges, StreamedMap};
 pub use plugin::EditToolsPlugin;
+pub use selection::SelectionState;
+pub use session::ToolSession;
+pub use terraformer::Terraformer;
 
 use drag_face::DragFaceState;
+use history_browser::HistoryBrowserState;
 use snapshotting_editor::SnapshottingVoxelEditor;
+use version_browser::VersionBrowserState;
 
 pub enum CurrentTool {
     DragFace(DragFaceState),
     Terraform,
+    HistoryBrowser(HistoryBrowserState),
+    VersionBrowser(VersionBrowserState),
     PaintMaterial, // TODO
     Tile,          // TODO: tile the current buffer by dragging; replaces DragFace
     Slope,         // TODO: select two edges to slope between
 }
 
+impl CurrentTool {
+    /// True if the current tool is consuming keyboard input as text, so keyboard shortcuts should
+    /// be ignored.
+    pub fn is_typing(&self) -> bool {
+        match self {
+            CurrentTool::HistoryBrowser(state) => state.is_typing(),
+            CurrentTool::VersionBrowser(state) => state.is_typing(),
+            _ => false,
+        }
+    }
+}
+
 // TODO: 3D selection; like the drag face tool, but you drag to size the 3rd dimension of the
 // selection. Move the selection by dragging a face. Allow visibility masking so you can only see
 // the voxels in the selection.
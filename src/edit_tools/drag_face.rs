@@ -1,16 +1,20 @@
 use crate::picking::VoxelFace;
 
-use super::{selection::SelectionState, CurrentTool, SnapshottingVoxelEditor};
+use super::{
+    edit_timeline::{EditTimeline, SnapshotMap},
+    selection::SelectionState,
+    CurrentTool, SnapshottingVoxelEditor,
+};
 
 use crate::{
     camera::CursorRay,
     geometry::{closest_points_on_two_lines, Ray3},
     picking::VoxelCursor,
+    Action, ActionInput,
 };
 
 use bevy::{
     ecs::prelude::*,
-    input::prelude::*,
     prelude::{EventReader, EventWriter},
 };
 use feldspar::{
@@ -27,16 +31,44 @@ use smooth_bevy_cameras::controllers::unreal::UnrealCameraController;
 pub enum DragFaceState {
     SelectionReady,
     DraggingFace {
+        /// The selected quad before dragging started, restored if the drag is cancelled.
+        start_quad_extent: Extent3i,
         quad_extent: Extent3i,
         normal: SignedAxis3,
         previous_drag_point: Point3i,
     },
 }
 
+/// Reverts a drag that's still in progress, and returns the selection it started from. The drag
+/// tool never sees the mouse being released once another tool is switched to, so the switcher has
+/// to end the drag's transaction instead.
+pub fn abort_drag(
+    state: &DragFaceState,
+    timeline: &mut EditTimeline,
+    map: &mut impl SnapshotMap,
+) -> Option<SelectionState> {
+    if let DragFaceState::DraggingFace {
+        start_quad_extent,
+        normal,
+        ..
+    } = *state
+    {
+        timeline.abort_transaction(map);
+
+        Some(SelectionState::SelectionReady {
+            quad_extent: start_quad_extent,
+            normal,
+        })
+    } else {
+        None
+    }
+}
+
 pub enum DragFaceEvents {
     StartDragFace(VoxelFace),
     UpdateDragFace(Point3i),
     FinishDragFace,
+    CancelDragFace,
 }
 
 pub fn drag_face_default_input_map(
@@ -45,6 +77,7 @@ pub fn drag_face_default_input_map(
     current_tool: Res<CurrentTool>,
     selection_state: Res<SelectionState>,
     cursor_ray: Res<CursorRay>,
+    input: ActionInput,
 ) {
     let state = if let CurrentTool::DragFace(state) = *current_tool {
         state
@@ -54,7 +87,11 @@ pub fn drag_face_default_input_map(
     match state {
         DragFaceState::SelectionReady => {
             if let SelectionState::SelectionReady { quad_extent, .. } = *selection_state {
-                if let Some(voxel_face) = voxel_cursor.voxel_just_pressed(MouseButton::Left) {
+                let pressed_face = input
+                    .mouse_buttons(Action::DragFace)
+                    .into_iter()
+                    .find_map(|button| voxel_cursor.voxel_just_pressed(button));
+                if let Some(voxel_face) = pressed_face {
                     if quad_extent.contains(voxel_face.point) {
                         events.send(DragFaceEvents::StartDragFace(voxel_face))
                     }
@@ -85,7 +122,9 @@ pub fn drag_face_default_input_map(
                     }
                 }
             }
-            if voxel_cursor.mouse_input.just_released(MouseButton::Left) {
+            if input.just_pressed(Action::CancelDrag) {
+                events.send(DragFaceEvents::CancelDragFace)
+            } else if input.just_released(Action::DragFace) {
                 events.send(DragFaceEvents::FinishDragFace)
             }
         }
@@ -116,7 +155,10 @@ pub fn drag_face_tool_system(
                     if let Some(mut controller) = mouse_camera_controllers.iter_mut().next() {
                         controller.enabled = false;
                     }
+                    // The whole drag is a single undoable edit, or nothing at all if cancelled.
+                    voxel_editor.begin_transaction();
                     *state = DragFaceState::DraggingFace {
+                        start_quad_extent: quad_extent,
                         quad_extent,
                         normal,
                         previous_drag_point: voxel_face.point,
@@ -126,6 +168,7 @@ pub fn drag_face_tool_system(
             }
             DragFaceEvents::UpdateDragFace(new_drag_point) => {
                 if let DragFaceState::DraggingFace {
+                    start_quad_extent,
                     mut quad_extent,
                     normal,
                     previous_drag_point,
@@ -159,6 +202,7 @@ pub fn drag_face_tool_system(
                     );
 
                     *state = DragFaceState::DraggingFace {
+                        start_quad_extent,
                         quad_extent,
                         normal,
                         previous_drag_point: *new_drag_point,
@@ -170,10 +214,125 @@ pub fn drag_face_tool_system(
                 if let Some(mut controller) = mouse_camera_controllers.iter_mut().next() {
                     controller.enabled = true;
                 }
-                voxel_editor.finish_edit();
+                if let DragFaceState::DraggingFace {
+                    quad_extent,
+                    normal,
+                    ..
+                } = *state
+                {
+                    voxel_editor.commit_transaction(format!(
+                        "Drag face: {} voxel quad along {:?}",
+                        quad_extent.num_points(),
+                        normal
+                    ));
+                }
                 *state = DragFaceState::SelectionReady;
                 *selection_state = SelectionState::SelectingFirstCorner;
             }
+            DragFaceEvents::CancelDragFace => {
+                if let Some(mut controller) = mouse_camera_controllers.iter_mut().next() {
+                    controller.enabled = true;
+                }
+                if let DragFaceState::DraggingFace {
+                    start_quad_extent,
+                    normal,
+                    ..
+                } = *state
+                {
+                    voxel_editor.abort_transaction();
+                    // Go back to the selection we started dragging from.
+                    *selection_state = SelectionState::SelectionReady {
+                        quad_extent: start_quad_extent,
+                        normal,
+                    };
+                }
+                *state = DragFaceState::SelectionReady;
+            }
         }
     }
 }
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use feldspar::prelude::{empty_sdf_chunk_hash_map, SdfChunkHashMap};
+
+    const CHUNK_SHAPE: Point3i = PointN([4; 3]);
+
+    fn fill(timeline: &mut EditTimeline, map: &mut SdfChunkHashMap, extent: Extent3i) {
+        timeline.add_extent_to_current_edit(extent, map);
+        for chunk_min in map.indexer.chunk_mins_for_extent(&extent) {
+            let key = ChunkKey::new(0, chunk_min);
+            let mut chunk = map.copy_chunk(key);
+            let write_extent = extent.intersection(chunk.extent());
+            chunk.for_each_mut(&write_extent, |_p, (t, d): (&mut VoxelType, &mut Sd8)| {
+                *t = VoxelType(2);
+                *d = Sd8::NEG_ONE;
+            });
+            SnapshotMap::write_chunk(map, key, chunk);
+        }
+    }
+
+    fn chunk_at_origin(map: &SdfChunkHashMap) -> Vec<(VoxelType, Sd8)> {
+        let chunk = map.copy_chunk(ChunkKey::new(0, PointN([0; 3])));
+        chunk.extent().iter_points().map(|p| chunk.get(p)).collect()
+    }
+
+    #[test]
+    fn switching_tools_mid_drag_reverts_it_and_closes_the_transaction() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let before = chunk_at_origin(&map);
+
+        let quad_extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([2, 2, 1]));
+        let normal = SignedAxis3::from_vector(PointN([0, 0, 1])).unwrap();
+        let state = DragFaceState::DraggingFace {
+            start_quad_extent: quad_extent,
+            quad_extent,
+            normal,
+            previous_drag_point: PointN([0; 3]),
+        };
+        timeline.begin_transaction(&map);
+        fill(&mut timeline, &mut map, quad_extent);
+
+        let selection = abort_drag(&state, &mut timeline, &mut map);
+        assert!(matches!(
+            selection,
+            Some(SelectionState::SelectionReady { quad_extent: q, .. }) if q == quad_extent
+        ));
+        assert!(!timeline.in_transaction());
+        assert!(!timeline.is_editing());
+        assert_eq!(chunk_at_origin(&map), before);
+
+        // Later edits get their own undo entries again.
+        fill(&mut timeline, &mut map, quad_extent);
+        timeline.store_current_edit("terraform", &map);
+        assert_eq!(timeline.current(), 1);
+        timeline.undo(&mut map);
+        assert_eq!(chunk_at_origin(&map), before);
+    }
+
+    #[test]
+    fn switching_tools_without_a_drag_keeps_the_edits() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([2; 3]));
+        fill(&mut timeline, &mut map, extent);
+        timeline.store_current_edit("drag", &map);
+        let after = chunk_at_origin(&map);
+
+        let selection = abort_drag(&DragFaceState::SelectionReady, &mut timeline, &mut map);
+        assert!(selection.is_none());
+        assert_eq!(chunk_at_origin(&map), after);
+        assert_eq!(timeline.current(), 1);
+    }
+}
@@ -1,109 +1,1404 @@
+mod edit;
+
+pub use edit::{decode_chunk, encode_chunk};
+
+use edit::{Edit, EncodedEdit, PendingEdit};
+
 use feldspar::{
     bb::prelude::*,
-    prelude::{
-        ambient_sdf_array, empty_sdf_chunk_hash_map, CompressibleSdfChunkMap, SdfChunkHashMap,
-        VoxelEditor,
-    },
+    prelude::{ambient_sdf_array, SdfArray, VoxelDb, VoxelEditor},
 };
-use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // TODO: limit the memory usage of the timeline somehow
 
+/// Identifies a node in the `EditTimeline`. IDs increase monotonically, so a larger ID means a more
+/// recent edit.
+pub type EditId = u64;
+
+/// The state of the map before any edits were made.
+pub const ROOT_EDIT_ID: EditId = 0;
+
+/// A tree of edits, where each node is a state of the map. Undoing an edit moves to the parent
+/// node, and making a new edit after undoing starts a new branch instead of discarding the undone
+/// edits.
+///
+/// Like the version forest in `sled_snapshots`, every node only knows its parent and children, and
+/// moving between two nodes means walking through their common ancestor.
 pub struct EditTimeline {
-    undo_queue: VecDeque<Edit>,
-    redo_queue: VecDeque<Edit>,
-    current_edit: Edit,
+    nodes: BTreeMap<EditId, EditNode>,
+    current: EditId,
+    next_id: EditId,
+    indexer: ChunkIndexer3,
+    current_edit: PendingEdit,
+    /// The number of nested transactions that haven't been committed yet.
+    transaction_depth: usize,
+    /// The pending edits of the transactions around the innermost one, so that aborting it only
+    /// reverts its own edits.
+    outer_edits: Vec<PendingEdit>,
+    changes: ChangeTracker,
+}
+
+struct EditNode {
+    parent: Option<EditId>,
+    children: Vec<EditId>,
+    /// The child that `redo` will move to.
+    redo_child: Option<EditId>,
+    /// The changes from the parent's state to this node's state.
+    edit: Edit,
+}
+
+/// What the user sees about an edit in the history.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EditMetadata {
+    pub description: String,
+    /// An optional name given by the user to the state after this edit.
+    pub label: Option<String>,
+    /// When the edit was made, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The number of chunks touched by the edit.
+    pub num_chunks: usize,
+    /// The number of voxels changed by the edit.
+    pub num_voxels: usize,
+}
+
+impl EditMetadata {
+    fn new(description: String, num_chunks: usize, num_voxels: usize) -> Self {
+        Self {
+            description,
+            label: None,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            num_chunks,
+            num_voxels,
+        }
+    }
+}
+
+/// Nodes and chunks that changed since they were last written somewhere.
+#[derive(Default)]
+struct ChangeSet {
+    nodes: BTreeSet<EditId>,
+    /// Nodes that were pruned.
+    removed_nodes: BTreeSet<EditId>,
+    /// Chunks that were written by edits, undos or redos.
+    chunks: HashSet<ChunkKey3>,
+}
+
+/// Tracks changes separately for the saved map and history, and for the edit journal, since
+/// they're written at different times.
+#[derive(Default)]
+struct ChangeTracker {
+    unsaved: ChangeSet,
+    unjournaled: ChangeSet,
+}
+
+impl ChangeTracker {
+    fn node_changed(&mut self, id: EditId) {
+        self.unsaved.nodes.insert(id);
+        self.unjournaled.nodes.insert(id);
+    }
+
+    fn node_removed(&mut self, id: EditId) {
+        self.unsaved.nodes.remove(&id);
+        self.unsaved.removed_nodes.insert(id);
+        self.unjournaled.nodes.remove(&id);
+        self.unjournaled.removed_nodes.insert(id);
+    }
+
+    fn chunks_changed(&mut self, keys: impl Iterator<Item = ChunkKey3>) {
+        for key in keys {
+            self.unsaved.chunks.insert(key);
+            self.unjournaled.chunks.insert(key);
+        }
+    }
+}
+
+/// A single node of the `EditTimeline`, as listed by `EditTimeline::history`.
+pub struct HistoryEntry {
+    pub id: EditId,
+    /// The number of edits between this node and the root.
+    pub depth: usize,
+    pub metadata: EditMetadata,
+    pub is_current: bool,
+}
+
+/// The changes to the `EditTimeline` that need to be written to the database.
+pub struct HistoryChanges {
+    pub current: EditId,
+    pub written: Vec<(EditId, Vec<u8>)>,
+    pub removed: Vec<EditId>,
 }
 
 impl EditTimeline {
     pub fn new(chunk_shape: Point3i) -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(ROOT_EDIT_ID, EditNode::root());
+
         Self {
-            undo_queue: Default::default(),
-            redo_queue: Default::default(),
-            current_edit: Edit {
-                voxels: empty_sdf_chunk_hash_map(chunk_shape),
+            nodes,
+            current: ROOT_EDIT_ID,
+            next_id: ROOT_EDIT_ID + 1,
+            indexer: ChunkIndexer3::new(chunk_shape),
+            current_edit: PendingEdit::default(),
+            transaction_depth: 0,
+            outer_edits: Vec::new(),
+            // The root is saved along with the first edit after it, so a fresh timeline has
+            // nothing to save yet.
+            changes: ChangeTracker::default(),
+        }
+    }
+
+    /// The node for the current state of the map.
+    pub fn current(&self) -> EditId {
+        self.current
+    }
+
+    /// Finalizes the current edit as a new node after the current one. `description` should say
+    /// what the edit did, in a way that makes sense to the user. `map` must contain the results of
+    /// the edit, so we can tell which voxels actually changed.
+    ///
+    /// While a transaction is open, this does nothing, and the edit becomes part of the
+    /// transaction instead.
+    pub fn store_current_edit(&mut self, description: impl Into<String>, map: &impl SnapshotMap) {
+        if self.in_transaction() {
+            return;
+        }
+
+        let finalized_edit =
+            std::mem::take(&mut self.current_edit).finalize(map, description.into());
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.nodes.insert(
+            id,
+            EditNode {
+                parent: Some(self.current),
+                children: Vec::new(),
+                redo_child: None,
+                edit: finalized_edit,
             },
+        );
+
+        // Any "undone edits" after the current node are kept as a separate branch.
+        let parent = self.node_mut(self.current);
+        parent.children.push(id);
+        parent.redo_child = Some(id);
+        self.changes.node_changed(self.current);
+        self.changes.node_changed(id);
+        self.current = id;
+    }
+
+    /// Starts grouping all edits into a single undo entry, until the matching call to
+    /// `commit_transaction`. Transactions can be nested, in which case only the outermost commit
+    /// stores the entry.
+    ///
+    /// Any unfinished edit from before the transaction is stored first, so that aborting the
+    /// transaction doesn't revert it.
+    pub fn begin_transaction(&mut self, map: &impl SnapshotMap) {
+        if self.in_transaction() {
+            self.outer_edits
+                .push(std::mem::take(&mut self.current_edit));
+        } else if !self.current_edit.is_empty() {
+            self.store_current_edit("Unfinished edit", map);
+        }
+        self.transaction_depth += 1;
+    }
+
+    pub fn commit_transaction(&mut self, description: impl Into<String>, map: &impl SnapshotMap) {
+        match self.transaction_depth {
+            0 => log::warn!("Tried to commit a transaction, but none is open"),
+            1 => {
+                self.transaction_depth = 0;
+                self.store_current_edit(description, map);
+            }
+            _ => {
+                self.transaction_depth -= 1;
+                let inner = std::mem::take(&mut self.current_edit);
+                self.current_edit = self.outer_edits.pop().unwrap_or_default();
+                self.current_edit.merge(inner);
+            }
         }
     }
 
-    pub fn store_current_edit(&mut self) {
-        let chunk_shape = self.current_edit.voxels.chunk_shape();
-        let finalized_edit = std::mem::replace(&mut self.current_edit, Edit::new(chunk_shape));
-        self.undo_queue.push_back(finalized_edit);
+    /// Reverts the map to the state it was in when the innermost transaction began. The
+    /// transactions around it stay open.
+    pub fn abort_transaction(&mut self, map: &mut impl SnapshotMap) {
+        if !self.in_transaction() {
+            log::warn!("Tried to abort a transaction, but none is open");
+            return;
+        }
+        self.transaction_depth -= 1;
+        std::mem::take(&mut self.current_edit).revert(map);
+        self.current_edit = self.outer_edits.pop().unwrap_or_default();
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction_depth > 0
+    }
+
+    /// Does nothing while a transaction is open.
+    pub fn undo(&mut self, map: &mut impl SnapshotMap) {
+        if self.in_transaction() {
+            return;
+        }
 
-        // We don't want to keep "undone edits" before this new one.
-        self.redo_queue.clear();
+        let current = self.current;
+        if let Some(parent) = self.node(current).parent {
+            let edit = &self.nodes[&current].edit;
+            edit.undo(map);
+            self.changes.chunks_changed(edit.chunk_keys());
+            self.node_mut(parent).redo_child = Some(current);
+            self.changes.node_changed(parent);
+            self.current = parent;
+        }
     }
 
-    pub fn undo(&mut self, editor: &mut VoxelEditor) {
-        reversible_restore_snapshot(&mut self.undo_queue, &mut self.redo_queue, editor)
+    /// Does nothing while a transaction is open.
+    pub fn redo(&mut self, map: &mut impl SnapshotMap) {
+        if self.in_transaction() {
+            return;
+        }
+
+        if let Some(child) = self.node(self.current).redo_child {
+            let edit = &self.nodes[&child].edit;
+            edit.redo(map);
+            self.changes.chunks_changed(edit.chunk_keys());
+            self.current = child;
+        }
     }
 
-    pub fn redo(&mut self, editor: &mut VoxelEditor) {
-        reversible_restore_snapshot(&mut self.redo_queue, &mut self.undo_queue, editor)
+    /// Gives the current state a name, like "before cave pass", so it's easy to find in the
+    /// history.
+    pub fn label_current(&mut self, label: impl Into<String>) {
+        let current = self.current;
+        self.node_mut(current).edit.metadata.label = Some(label.into());
+        self.changes.node_changed(current);
     }
 
-    pub fn add_extent_to_current_edit(
+    /// Every node in the tree, in depth-first order, so that each branch is listed after its parent.
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        let root = *self.ancestors(self.current).last().unwrap();
+
+        let mut entries = Vec::new();
+        let mut stack = vec![(root, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let node = self.node(id);
+            entries.push(HistoryEntry {
+                id,
+                depth,
+                metadata: node.edit.metadata.clone(),
+                is_current: id == self.current,
+            });
+            // Reversed so that the oldest child is visited first.
+            stack.extend(node.children.iter().rev().map(|child| (*child, depth + 1)));
+        }
+
+        entries
+    }
+
+    /// The most recent edit of every branch, i.e. all of the leaves of the tree, in order of
+    /// creation.
+    pub fn branch_tips(&self) -> Vec<EditId> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.children.is_empty())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// The branch tip that would be reached by redoing as far as possible from the current node.
+    pub fn current_branch_tip(&self) -> EditId {
+        let mut tip = self.current;
+        while let Some(child) = self.node(tip).redo_child {
+            tip = child;
+        }
+        tip
+    }
+
+    /// Undoes and redoes edits until the map is in the state of node `target`. Does nothing if
+    /// `target` doesn't exist, isn't under the same root as the current node, or a transaction is
+    /// open.
+    pub fn jump_to(&mut self, target: EditId, map: &mut impl SnapshotMap) {
+        if !self.nodes.contains_key(&target) || self.in_transaction() {
+            return;
+        }
+
+        // Without a common ancestor, we'd undo up to the root and never get any closer.
+        let target_ancestors = self.ancestors(target);
+        if self.ancestors(self.current).last() != target_ancestors.last() {
+            return;
+        }
+
+        // Undo until we reach a common ancestor.
+        while !target_ancestors.contains(&self.current) {
+            self.undo(map);
+        }
+
+        // Then redo along the path down to the target.
+        let path: Vec<EditId> = target_ancestors
+            .into_iter()
+            .take_while(|id| *id != self.current)
+            .collect();
+        for id in path.into_iter().rev() {
+            let parent = self.node(id).parent.unwrap();
+            self.node_mut(parent).redo_child = Some(id);
+            self.changes.node_changed(parent);
+            self.redo(map);
+        }
+    }
+
+    /// Removes all but the `max_entries` most recent edits, keeping the current one regardless.
+    /// Edits that can no longer be reached from the current state are removed as well.
+    pub fn prune(&mut self, max_entries: usize) {
+        let mut keep: BTreeSet<EditId> =
+            self.nodes.keys().rev().take(max_entries).cloned().collect();
+        keep.insert(self.current);
+
+        // The oldest ancestor of the current node that we're keeping becomes the new root.
+        let new_root = self
+            .ancestors(self.current)
+            .into_iter()
+            .take_while(|id| keep.contains(id))
+            .last()
+            .unwrap();
+
+        // Only nodes that are still reachable from the new root are worth keeping.
+        let mut reachable = BTreeSet::new();
+        let mut stack = vec![new_root];
+        while let Some(id) = stack.pop() {
+            reachable.insert(id);
+            stack.extend(
+                self.node(id)
+                    .children
+                    .iter()
+                    .filter(|child| keep.contains(*child)),
+            );
+        }
+
+        let removed: Vec<EditId> = self
+            .nodes
+            .keys()
+            .filter(|id| !reachable.contains(id))
+            .cloned()
+            .collect();
+        for id in removed.into_iter() {
+            self.nodes.remove(&id);
+            self.changes.node_removed(id);
+        }
+
+        for (id, node) in self.nodes.iter_mut() {
+            let num_children = node.children.len();
+            node.children.retain(|child| reachable.contains(child));
+            if node.children.len() != num_children {
+                node.redo_child = node
+                    .redo_child
+                    .filter(|child| reachable.contains(child))
+                    .or_else(|| node.children.last().cloned());
+                self.changes.node_changed(*id);
+            }
+        }
+
+        // We can't undo past the root, so its changes are useless.
+        let root = self.node_mut(new_root);
+        root.parent = None;
+        root.edit.clear_changes();
+        self.changes.node_changed(new_root);
+    }
+
+    /// Must be called before the voxels in `extent` are written, so the old values can be restored
+    /// by `undo`.
+    pub fn add_extent_to_current_edit(&mut self, extent: Extent3i, src_map: &impl SnapshotMap) {
+        self.current_edit.add_extent(extent, &self.indexer, src_map);
+        self.changes.chunks_changed(
+            self.indexer
+                .chunk_mins_for_extent(&extent)
+                .map(|chunk_min| ChunkKey::new(0, chunk_min)),
+        );
+    }
+
+    /// For voxels that are written without an edit, like a newly created world, so they're saved
+    /// and journaled without being undoable.
+    pub fn mark_extent_written(&mut self, extent: Extent3i) {
+        self.changes.chunks_changed(
+            self.indexer
+                .chunk_mins_for_extent(&extent)
+                .map(|chunk_min| ChunkKey::new(0, chunk_min)),
+        );
+    }
+
+    /// True while an edit or transaction has been started but not stored yet.
+    pub fn is_editing(&self) -> bool {
+        self.in_transaction() || !self.current_edit.is_empty()
+    }
+
+    /// True if anything changed since the last call to `take_history_changes`.
+    pub fn has_unsaved_changes(&self) -> bool {
+        let unsaved = &self.changes.unsaved;
+
+        !unsaved.nodes.is_empty() || !unsaved.removed_nodes.is_empty() || !unsaved.chunks.is_empty()
+    }
+
+    /// Every chunk that was written since the last call, so that only those need to be saved.
+    pub fn take_dirty_chunks(&mut self) -> HashSet<ChunkKey3> {
+        std::mem::take(&mut self.changes.unsaved.chunks)
+    }
+
+    /// True if `key` was written since the last call to `take_dirty_chunks`.
+    pub fn is_chunk_dirty(&self, key: ChunkKey3) -> bool {
+        self.changes.unsaved.chunks.contains(&key)
+    }
+
+    /// Encodes every node that changed since the last call, so they can be written to the
+    /// `EditHistoryDb`.
+    pub fn take_history_changes(&mut self) -> HistoryChanges {
+        let dirty_nodes = std::mem::take(&mut self.changes.unsaved.nodes);
+        let removed_nodes = std::mem::take(&mut self.changes.unsaved.removed_nodes);
+
+        HistoryChanges {
+            current: self.current,
+            written: dirty_nodes
+                .into_iter()
+                .map(|id| (id, self.node(id).encode()))
+                .collect(),
+            removed: removed_nodes.into_iter().collect(),
+        }
+    }
+
+    /// Encodes everything that changed since the last call, both history nodes and the chunks of
+    /// `map`, as a record for the edit journal. Returns `None` if nothing changed, or if an edit is
+    /// still in progress, so that only finished edits are journaled.
+    pub fn take_journal_record(&mut self, map: &impl SnapshotMap) -> Option<Vec<u8>> {
+        let unjournaled = &self.changes.unjournaled;
+        if self.is_editing()
+            || (unjournaled.nodes.is_empty()
+                && unjournaled.removed_nodes.is_empty()
+                && unjournaled.chunks.is_empty())
+        {
+            return None;
+        }
+
+        let changes = std::mem::take(&mut self.changes.unjournaled);
+        let record = JournalRecord {
+            current: self.current,
+            nodes: changes
+                .nodes
+                .into_iter()
+                .map(|id| (id, self.node(id).encode()))
+                .collect(),
+            removed_nodes: changes.removed_nodes.into_iter().collect(),
+            chunks: changes
+                .chunks
+                .into_iter()
+                .map(|key| (key.minimum.0, encode_chunk(&map.copy_chunk(key))))
+                .collect(),
+        };
+
+        Some(bincode::serialize(&record).unwrap())
+    }
+
+    /// Applies a record from `take_journal_record` on top of the saved history and map, so that
+    /// edits which were never saved can be recovered after a crash. Records must be replayed in the
+    /// order they were taken. The replayed changes count as unsaved.
+    pub fn replay_journal_record(
         &mut self,
-        extent: Extent3i,
-        src_map: &CompressibleSdfChunkMap,
+        bytes: &[u8],
+        map: &mut impl SnapshotMap,
+    ) -> bincode::Result<()> {
+        let record: JournalRecord = bincode::deserialize(bytes)?;
+
+        // Check the whole record before applying any of it, so a bad record leaves the history
+        // and map as they were.
+        let nodes = record
+            .nodes
+            .iter()
+            .map(|(id, bytes)| Ok((*id, EditNode::decode(bytes, &self.indexer)?)))
+            .collect::<bincode::Result<Vec<_>>>()?;
+        let current_exists = nodes.iter().any(|(id, _)| *id == record.current)
+            || self.nodes.contains_key(&record.current);
+        if !current_exists || record.removed_nodes.contains(&record.current) {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "Journaled current edit {} is missing from the history",
+                record.current
+            ))));
+        }
+
+        let chunks = record
+            .chunks
+            .into_iter()
+            .map(|(minimum, voxels)| {
+                let key = ChunkKey::new(0, PointN(minimum));
+                let extent = self.indexer.extent_for_chunk_with_min(key.minimum);
+                Ok((key, decode_chunk(voxels, extent)?))
+            })
+            .collect::<bincode::Result<Vec<_>>>()?;
+
+        for (id, node) in nodes.into_iter() {
+            self.nodes.insert(id, node);
+            self.changes.unsaved.nodes.insert(id);
+        }
+        for id in record.removed_nodes.iter() {
+            self.nodes.remove(id);
+            self.changes.unsaved.nodes.remove(id);
+            self.changes.unsaved.removed_nodes.insert(*id);
+        }
+        for (key, chunk) in chunks.into_iter() {
+            map.write_chunk(key, chunk);
+            self.changes.unsaved.chunks.insert(key);
+        }
+
+        self.current = record.current;
+        self.next_id = self.next_id.max(self.nodes.keys().last().unwrap() + 1);
+        self.current_edit = PendingEdit::default();
+
+        Ok(())
+    }
+
+    /// Marks the chunks and history nodes of a save that failed as unsaved again, so that the next
+    /// save retries them.
+    pub fn restore_unsaved(
+        &mut self,
+        chunks: impl Iterator<Item = ChunkKey3>,
+        written_nodes: impl Iterator<Item = EditId>,
+        removed_nodes: impl Iterator<Item = EditId>,
     ) {
-        for chunk_min in src_map.indexer.chunk_mins_for_extent(&extent) {
-            let chunk_key = ChunkKey::new(0, chunk_min);
-            self.current_edit
-                .voxels
-                .get_mut_chunk_or_insert_with(chunk_key, || {
-                    src_map
-                        .storage()
-                        // This chunk will eventually get cached after being written by the editor.
-                        .copy_without_caching(chunk_key)
-                        .map(|c| c.into_decompressed())
-                        .unwrap_or_else(|| {
-                            ambient_sdf_array(src_map.indexer.extent_for_chunk_with_min(chunk_min))
-                        })
-                });
-        }
-    }
-}
-
-fn reversible_restore_snapshot(
-    do_queue: &mut VecDeque<Edit>,
-    undo_queue: &mut VecDeque<Edit>,
-    editor: &mut VoxelEditor,
-) {
-    if let Some(edit) = do_queue.pop_back() {
-        let indexer = edit.voxels.indexer;
-        let storage = edit.voxels.take_storage();
-
-        let mut redo_snap_chunks = empty_sdf_chunk_hash_map(indexer.chunk_shape());
-        for (chunk_key, chunk) in storage.into_iter() {
-            editor.write_chunk_and_touch_neighbors(chunk_key.minimum, chunk);
-            let old_chunk = editor
-                .map
-                .voxels
-                .storage()
-                .copy_without_caching(chunk_key)
-                .map(|c| c.into_decompressed())
-                .unwrap_or_else(|| {
-                    ambient_sdf_array(indexer.extent_for_chunk_with_min(chunk_key.minimum))
-                });
-            redo_snap_chunks.write_chunk(chunk_key, old_chunk);
-        }
-        undo_queue.push_back(Edit {
-            voxels: redo_snap_chunks,
-        });
-    }
-}
-
-/// The set of modified chunks in the state after the edit.
-struct Edit {
-    voxels: SdfChunkHashMap,
-}
-
-impl Edit {
-    fn new(chunk_shape: Point3i) -> Self {
+        let unsaved = &mut self.changes.unsaved;
+        unsaved.chunks.extend(chunks);
+        for id in written_nodes {
+            // The node might have been pruned since the save started.
+            if self.nodes.contains_key(&id) {
+                unsaved.nodes.insert(id);
+            }
+        }
+        unsaved.removed_nodes.extend(removed_nodes);
+    }
+
+    /// Replaces the whole timeline with nodes that were previously written to the `EditHistoryDb`.
+    /// The map must be in the state of node `current`.
+    pub fn load_history(
+        &mut self,
+        current: EditId,
+        encoded_nodes: impl Iterator<Item = (EditId, Vec<u8>)>,
+    ) -> bincode::Result<()> {
+        let mut nodes = BTreeMap::new();
+        for (id, bytes) in encoded_nodes {
+            nodes.insert(id, EditNode::decode(&bytes, &self.indexer)?);
+        }
+        if !nodes.contains_key(&current) {
+            // Nothing to load, or the history doesn't match the map.
+            return Ok(());
+        }
+
+        self.next_id = nodes.keys().last().unwrap() + 1;
+        self.nodes = nodes;
+        self.current = current;
+        self.current_edit = PendingEdit::default();
+        self.changes = ChangeTracker::default();
+
+        Ok(())
+    }
+
+    /// `id` followed by all of its ancestors, up to and including the root.
+    fn ancestors(&self, id: EditId) -> Vec<EditId> {
+        let mut ancestors = vec![id];
+        let mut next = self.node(id).parent;
+        while let Some(parent) = next {
+            ancestors.push(parent);
+            next = self.node(parent).parent;
+        }
+        ancestors
+    }
+
+    fn node(&self, id: EditId) -> &EditNode {
+        &self.nodes[&id]
+    }
+
+    fn node_mut(&mut self, id: EditId) -> &mut EditNode {
+        self.nodes.get_mut(&id).unwrap()
+    }
+}
+
+impl EditNode {
+    fn root() -> Self {
         Self {
-            voxels: empty_sdf_chunk_hash_map(chunk_shape),
+            parent: None,
+            children: Vec::new(),
+            redo_child: None,
+            edit: Edit::new(EditMetadata::new("Opened map".to_string(), 0, 0)),
         }
     }
+
+    fn encode(&self) -> Vec<u8> {
+        let encoded = EncodedEditNode {
+            parent: self.parent,
+            children: self.children.clone(),
+            redo_child: self.redo_child,
+            edit: self.edit.encode(),
+        };
+
+        bincode::serialize(&encoded).unwrap()
+    }
+
+    fn decode(bytes: &[u8], indexer: &ChunkIndexer3) -> bincode::Result<Self> {
+        let encoded: EncodedEditNode = bincode::deserialize(bytes)?;
+
+        Ok(Self {
+            parent: encoded.parent,
+            children: encoded.children,
+            redo_child: encoded.redo_child,
+            edit: Edit::decode(encoded.edit, indexer)?,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct EncodedEditNode {
+    parent: Option<EditId>,
+    children: Vec<EditId>,
+    redo_child: Option<EditId>,
+    edit: EncodedEdit,
+}
+
+/// Everything that changed between two records of the edit journal.
+#[derive(Deserialize, Serialize)]
+struct JournalRecord {
+    current: EditId,
+    nodes: Vec<(EditId, Vec<u8>)>,
+    removed_nodes: Vec<EditId>,
+    /// The full contents of every changed chunk, as `(minimum, voxels)`.
+    chunks: Vec<([i32; 3], Vec<(u8, i8)>)>,
+}
+
+/// The map operations required to take and restore snapshots of chunks.
+pub trait SnapshotMap {
+    /// Copies the chunk at `key`, or returns an ambient chunk if it doesn't exist.
+    fn copy_chunk(&self, key: ChunkKey3) -> SdfArray;
+
+    fn write_chunk(&mut self, key: ChunkKey3, chunk: SdfArray);
+}
+
+/// A map that only holds some of the chunks, like the ones streamed in around the camera.
+pub trait PartialChunkMap {
+    fn indexer(&self) -> &ChunkIndexer3;
+
+    /// Copies the chunk at `key`, or returns `None` if it isn't in the map.
+    fn copy_loaded_chunk(&self, key: ChunkKey3) -> Option<SdfArray>;
+
+    fn insert_chunk(&mut self, key: ChunkKey3, chunk: SdfArray);
+}
+
+impl<'a> PartialChunkMap for VoxelEditor<'a> {
+    fn indexer(&self) -> &ChunkIndexer3 {
+        &self.map.voxels.indexer
+    }
+
+    fn copy_loaded_chunk(&self, key: ChunkKey3) -> Option<SdfArray> {
+        self.map
+            .voxels
+            .storage()
+            // This chunk will eventually get cached after being written by the editor.
+            .copy_without_caching(key)
+            .map(|c| c.into_decompressed())
+    }
+
+    fn insert_chunk(&mut self, key: ChunkKey3, chunk: SdfArray) {
+        self.write_chunk_and_touch_neighbors(key.minimum, chunk);
+    }
+}
+
+/// A `PartialChunkMap` backed by the `VoxelDb` it was loaded from. Chunks that aren't in the map
+/// are read from the database, so that edits, undo and redo never start from an ambient chunk and
+/// overwrite the saved one.
+pub struct StreamedMap<'a, M> {
+    map: &'a mut M,
+    voxel_db: &'a VoxelDb,
+}
+
+impl<'a, M: PartialChunkMap> StreamedMap<'a, M> {
+    pub fn new(map: &'a mut M, voxel_db: &'a VoxelDb) -> Self {
+        Self { map, voxel_db }
+    }
+
+    /// Reads every chunk that intersects `extent` and isn't in the map yet from the database, so
+    /// the voxels around an edit aren't lost.
+    pub fn load_chunks_in(&mut self, extent: Extent3i) {
+        let keys: Vec<ChunkKey3> = self
+            .map
+            .indexer()
+            .chunk_mins_for_extent(&extent)
+            .map(|chunk_min| ChunkKey::new(0, chunk_min))
+            .collect();
+        for key in keys.into_iter() {
+            if self.map.copy_loaded_chunk(key).is_none() {
+                if let Some(chunk) = self.read_saved_chunk(key) {
+                    self.map.insert_chunk(key, chunk);
+                }
+            }
+        }
+    }
+
+    fn read_saved_chunk(&self, key: ChunkKey3) -> Option<SdfArray> {
+        let extent = self.map.indexer().extent_for_chunk_with_min(key.minimum);
+        // Chunks are cubes with a power of two edge, so each one is the only chunk in the orthant
+        // it covers.
+        let orthant = Orthant::new_unchecked(key.minimum, extent.shape.x().trailing_zeros() as u8);
+        match self.voxel_db.chunks().read_chunks_in_orthant(0, orthant) {
+            Ok(chunks) => chunks
+                .into_iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, chunk)| chunk.decompress()),
+            Err(e) => {
+                log::error!("Failed to read chunk {:?}: {:?}", key, e);
+                None
+            }
+        }
+    }
+}
+
+impl<'a, M: PartialChunkMap> SnapshotMap for StreamedMap<'a, M> {
+    fn copy_chunk(&self, key: ChunkKey3) -> SdfArray {
+        self.map
+            .copy_loaded_chunk(key)
+            .or_else(|| self.read_saved_chunk(key))
+            .unwrap_or_else(|| {
+                ambient_sdf_array(self.map.indexer().extent_for_chunk_with_min(key.minimum))
+            })
+    }
+
+    fn write_chunk(&mut self, key: ChunkKey3, chunk: SdfArray) {
+        self.map.insert_chunk(key, chunk);
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use feldspar::prelude::{empty_sdf_chunk_hash_map, SdfChunkHashMap, VoxelType};
+    use proptest::prelude::*;
+    use std::collections::HashMap;
+
+    const CHUNK_SHAPE: Point3i = PointN([4; 3]);
+
+    impl SnapshotMap for SdfChunkHashMap {
+        fn copy_chunk(&self, key: ChunkKey3) -> SdfArray {
+            self.storage().get(&key).cloned().unwrap_or_else(|| {
+                ambient_sdf_array(self.indexer.extent_for_chunk_with_min(key.minimum))
+            })
+        }
+
+        fn write_chunk(&mut self, key: ChunkKey3, chunk: SdfArray) {
+            SdfChunkHashMap::write_chunk(self, key, chunk);
+        }
+    }
+
+    impl PartialChunkMap for SdfChunkHashMap {
+        fn indexer(&self) -> &ChunkIndexer3 {
+            &self.indexer
+        }
+
+        fn copy_loaded_chunk(&self, key: ChunkKey3) -> Option<SdfArray> {
+            self.storage().get(&key).cloned()
+        }
+
+        fn insert_chunk(&mut self, key: ChunkKey3, chunk: SdfArray) {
+            SdfChunkHashMap::write_chunk(self, key, chunk);
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        /// Write each `(extent, voxel_type, dist)` in order, then finish the edit.
+        Edit(Vec<(Extent3i, u8, i8)>),
+        Undo,
+        Redo,
+        /// Jump to the tip of a branch, chosen by index modulo the number of branches.
+        SwitchBranch(usize),
+    }
+
+    fn domain() -> Extent3i {
+        Extent3i::from_min_and_shape(PointN([-8; 3]), PointN([16; 3]))
+    }
+
+    fn arb_extent() -> impl Strategy<Value = Extent3i> {
+        (
+            prop::array::uniform3(-8..6i32),
+            prop::array::uniform3(1..6i32),
+        )
+            .prop_map(|(min, shape)| {
+                Extent3i::from_min_and_shape(PointN(min), PointN(shape)).intersection(&domain())
+            })
+    }
+
+    fn arb_op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            prop::collection::vec((arb_extent(), 0..4u8, any::<i8>()), 1..4).prop_map(Op::Edit),
+            Just(Op::Undo),
+            Just(Op::Redo),
+            any::<usize>().prop_map(Op::SwitchBranch),
+        ]
+    }
+
+    /// Applies the edit directly to the map's chunks, bypassing the timeline.
+    fn write_extent(map: &mut SdfChunkHashMap, extent: Extent3i, voxel_type: u8, dist: i8) {
+        for chunk_min in map.indexer.chunk_mins_for_extent(&extent) {
+            let key = ChunkKey::new(0, chunk_min);
+            let mut chunk = map.copy_chunk(key);
+            let write_extent = extent.intersection(&chunk.extent());
+            chunk.for_each_mut(&write_extent, |_p, (t, d): (&mut VoxelType, &mut Sd8)| {
+                *t = VoxelType(voxel_type);
+                *d = Sd8(dist);
+            });
+            SnapshotMap::write_chunk(map, key, chunk);
+        }
+    }
+
+    /// Every chunk covering the domain, in a deterministic order, with missing chunks treated as
+    /// ambient.
+    fn snapshot(map: &SdfChunkHashMap) -> Vec<SdfArray> {
+        map.indexer
+            .chunk_mins_for_extent(&domain())
+            .map(|chunk_min| map.copy_chunk(ChunkKey::new(0, chunk_min)))
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn undo_and_redo_restore_exact_map_states(ops in prop::collection::vec(arb_op(), 1..30)) {
+            let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+            let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+
+            // A model of the tree: the map state we expect at each node, and how to move between
+            // them.
+            let mut states = HashMap::new();
+            states.insert(ROOT_EDIT_ID, snapshot(&map));
+            let mut parents = HashMap::new();
+            let mut redo_children = HashMap::new();
+            let mut current = ROOT_EDIT_ID;
+
+            for op in ops.into_iter() {
+                match op {
+                    Op::Edit(writes) => {
+                        for (extent, voxel_type, dist) in writes.into_iter() {
+                            timeline.add_extent_to_current_edit(extent, &map);
+                            write_extent(&mut map, extent, voxel_type, dist);
+                        }
+                        timeline.store_current_edit("test edit", &map);
+                        let id = timeline.current();
+                        prop_assert!(!states.contains_key(&id));
+                        states.insert(id, snapshot(&map));
+                        parents.insert(id, current);
+                        redo_children.insert(current, id);
+                        current = id;
+                    }
+                    Op::Undo => {
+                        timeline.undo(&mut map);
+                        if let Some(&parent) = parents.get(&current) {
+                            redo_children.insert(parent, current);
+                            current = parent;
+                        }
+                    }
+                    Op::Redo => {
+                        timeline.redo(&mut map);
+                        if let Some(&child) = redo_children.get(&current) {
+                            current = child;
+                        }
+                    }
+                    Op::SwitchBranch(i) => {
+                        let tips = timeline.branch_tips();
+                        let tip = tips[i % tips.len()];
+                        timeline.jump_to(tip, &mut map);
+                        current = tip;
+                        let mut child = tip;
+                        while let Some(&parent) = parents.get(&child) {
+                            redo_children.insert(parent, child);
+                            child = parent;
+                        }
+                    }
+                }
+                prop_assert_eq!(timeline.current(), current);
+                prop_assert_eq!(&snapshot(&map), &states[&current]);
+            }
+        }
+    }
+
+    #[test]
+    fn undo_redo_undo_round_trips() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let before = snapshot(&map);
+
+        let extent = Extent3i::from_min_and_shape(PointN([-2; 3]), PointN([5; 3]));
+        timeline.add_extent_to_current_edit(extent, &map);
+        write_extent(&mut map, extent, 2, -10);
+        timeline.store_current_edit("test edit", &map);
+        let after = snapshot(&map);
+
+        timeline.undo(&mut map);
+        assert_eq!(snapshot(&map), before);
+        timeline.redo(&mut map);
+        assert_eq!(snapshot(&map), after);
+        timeline.undo(&mut map);
+        assert_eq!(snapshot(&map), before);
+    }
+
+    #[test]
+    fn editing_after_undo_keeps_the_undone_branch() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+
+        let extent_a = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([3; 3]));
+        timeline.add_extent_to_current_edit(extent_a, &map);
+        write_extent(&mut map, extent_a, 1, -5);
+        timeline.store_current_edit("test edit", &map);
+        let branch_a = timeline.current();
+        let state_a = snapshot(&map);
+
+        timeline.undo(&mut map);
+
+        let extent_b = Extent3i::from_min_and_shape(PointN([-3; 3]), PointN([4; 3]));
+        timeline.add_extent_to_current_edit(extent_b, &map);
+        write_extent(&mut map, extent_b, 3, -7);
+        timeline.store_current_edit("test edit", &map);
+        let branch_b = timeline.current();
+        let state_b = snapshot(&map);
+
+        assert_eq!(timeline.branch_tips(), vec![branch_a, branch_b]);
+
+        timeline.jump_to(branch_a, &mut map);
+        assert_eq!(snapshot(&map), state_a);
+        timeline.jump_to(branch_b, &mut map);
+        assert_eq!(snapshot(&map), state_b);
+    }
+
+    #[test]
+    fn history_can_be_undone_after_reloading() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let mut states = vec![snapshot(&map)];
+
+        for (i, min) in [[-4; 3], [1; 3]].iter().enumerate() {
+            let extent = Extent3i::from_min_and_shape(PointN(*min), PointN([4; 3]));
+            timeline.add_extent_to_current_edit(extent, &map);
+            write_extent(&mut map, extent, i as u8 + 1, -3);
+            timeline.store_current_edit("test edit", &map);
+            states.push(snapshot(&map));
+        }
+        timeline.undo(&mut map);
+
+        let changes = timeline.take_history_changes();
+        assert!(changes.removed.is_empty());
+        let mut reloaded = EditTimeline::new(CHUNK_SHAPE);
+        reloaded
+            .load_history(changes.current, changes.written.into_iter())
+            .unwrap();
+
+        reloaded.redo(&mut map);
+        assert_eq!(snapshot(&map), states[2]);
+        reloaded.undo(&mut map);
+        reloaded.undo(&mut map);
+        assert_eq!(snapshot(&map), states[0]);
+    }
+
+    #[test]
+    fn a_fresh_timeline_has_nothing_to_save() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        assert!(!timeline.has_unsaved_changes());
+
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([2; 3]));
+        write_extent_in_edit(&mut timeline, &mut map, extent, 1, -1);
+        timeline.store_current_edit("test edit", &map);
+        assert!(timeline.has_unsaved_changes());
+        let written: Vec<EditId> = timeline
+            .take_history_changes()
+            .written
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(written, vec![ROOT_EDIT_ID, 1]);
+    }
+
+    #[test]
+    fn prune_keeps_only_the_most_recent_edits() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let mut states = vec![snapshot(&map)];
+
+        for i in 0..5 {
+            let extent = Extent3i::from_min_and_shape(PointN([i - 4; 3]), PointN([3; 3]));
+            timeline.add_extent_to_current_edit(extent, &map);
+            write_extent(&mut map, extent, 1, -(i as i8));
+            timeline.store_current_edit("test edit", &map);
+            states.push(snapshot(&map));
+        }
+        let _ = timeline.take_history_changes();
+
+        timeline.prune(3);
+        let changes = timeline.take_history_changes();
+        assert_eq!(changes.removed, vec![0, 1, 2]);
+
+        // Only two edits can be undone, since the oldest remaining node is now the root.
+        for _ in 0..5 {
+            timeline.undo(&mut map);
+        }
+        assert_eq!(snapshot(&map), states[3]);
+        timeline.jump_to(5, &mut map);
+        assert_eq!(snapshot(&map), states[5]);
+    }
+
+    #[test]
+    fn jumping_to_an_edit_under_another_root_does_nothing() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        for i in 0..3 {
+            let extent = Extent3i::from_min_and_shape(PointN([i; 3]), PointN([2; 3]));
+            write_extent_in_edit(&mut timeline, &mut map, extent, 1, -1);
+            timeline.store_current_edit("test edit", &map);
+        }
+        let before_prune = timeline.take_history_changes();
+
+        // Node 2 becomes a root, but the old nodes 0 and 1 are loaded along with it.
+        timeline.prune(2);
+        let after_prune = timeline.take_history_changes();
+        let mut nodes: BTreeMap<EditId, Vec<u8>> = before_prune.written.into_iter().collect();
+        nodes.extend(after_prune.written.into_iter());
+        let mut reloaded = EditTimeline::new(CHUNK_SHAPE);
+        reloaded.load_history(1, nodes.into_iter()).unwrap();
+
+        let state = snapshot(&map);
+        reloaded.jump_to(3, &mut map);
+        assert_eq!(reloaded.current(), 1);
+        assert_eq!(snapshot(&map), state);
+    }
+
+    #[test]
+    fn history_lists_branches_with_metadata() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+
+        // Touches 8 chunks.
+        let extent = Extent3i::from_min_and_shape(PointN([-2; 3]), PointN([4; 3]));
+        timeline.add_extent_to_current_edit(extent, &map);
+        write_extent(&mut map, extent, 1, -1);
+        timeline.store_current_edit("first", &map);
+        timeline.label_current("checkpoint");
+        timeline.undo(&mut map);
+
+        // Touches 1 chunk.
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([2; 3]));
+        timeline.add_extent_to_current_edit(extent, &map);
+        write_extent(&mut map, extent, 2, -1);
+        timeline.store_current_edit("second", &map);
+
+        let history = timeline.history();
+        let summary: Vec<_> = history
+            .iter()
+            .map(|e| {
+                (
+                    e.id,
+                    e.depth,
+                    e.metadata.description.as_str(),
+                    e.metadata.label.as_deref(),
+                    e.metadata.num_chunks,
+                    e.is_current,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0, 0, "Opened map", None, 0, false),
+                (1, 1, "first", Some("checkpoint"), 8, false),
+                (2, 1, "second", None, 1, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn small_edits_only_store_changed_voxels() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let before = snapshot(&map);
+
+        // A single voxel, plus an extent that gets rewritten with its current values.
+        let single = Extent3i::from_min_and_shape(PointN([1; 3]), PointN([1; 3]));
+        let unchanged = Extent3i::from_min_and_shape(PointN([-4; 3]), PointN([2; 3]));
+        timeline.add_extent_to_current_edit(single, &map);
+        timeline.add_extent_to_current_edit(unchanged, &map);
+        write_extent(&mut map, single, 1, -1);
+        timeline.store_current_edit("single voxel", &map);
+
+        let metadata = &timeline.node(timeline.current()).edit.metadata;
+        assert_eq!(metadata.num_chunks, 1);
+        assert_eq!(metadata.num_voxels, 1);
+
+        // A whole chunk.
+        let chunk = Extent3i::from_min_and_shape(PointN([0; 3]), CHUNK_SHAPE);
+        timeline.add_extent_to_current_edit(chunk, &map);
+        write_extent(&mut map, chunk, 2, -2);
+        timeline.store_current_edit("whole chunk", &map);
+
+        let metadata = &timeline.node(timeline.current()).edit.metadata;
+        assert_eq!(metadata.num_chunks, 1);
+        assert_eq!(metadata.num_voxels, 64);
+
+        let after = snapshot(&map);
+        timeline.undo(&mut map);
+        timeline.undo(&mut map);
+        assert_eq!(snapshot(&map), before);
+        timeline.redo(&mut map);
+        timeline.redo(&mut map);
+        assert_eq!(snapshot(&map), after);
+    }
+
+    /// Counts how many chunks are copied out of the map.
+    struct CountingMap {
+        map: SdfChunkHashMap,
+        copies: std::cell::Cell<usize>,
+    }
+
+    impl SnapshotMap for CountingMap {
+        fn copy_chunk(&self, key: ChunkKey3) -> SdfArray {
+            self.copies.set(self.copies.get() + 1);
+            self.map.copy_chunk(key)
+        }
+
+        fn write_chunk(&mut self, key: ChunkKey3, chunk: SdfArray) {
+            SnapshotMap::write_chunk(&mut self.map, key, chunk);
+        }
+    }
+
+    #[test]
+    fn each_chunk_is_copied_once_per_edit() {
+        let mut map = CountingMap {
+            map: empty_sdf_chunk_hash_map(CHUNK_SHAPE),
+            copies: Default::default(),
+        };
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let before = snapshot(&map.map);
+
+        // A tool adding the same extent every frame.
+        let small = Extent3i::from_min_and_shape(PointN([1; 3]), PointN([2; 3]));
+        for i in 0..5 {
+            timeline.add_extent_to_current_edit(small, &map);
+            write_extent(&mut map.map, small, 1, -(i + 1));
+        }
+        assert_eq!(map.copies.get(), 1);
+
+        // Growing to the whole chunk copies it once more, and then never again.
+        let chunk = Extent3i::from_min_and_shape(PointN([0; 3]), CHUNK_SHAPE);
+        for _ in 0..5 {
+            timeline.add_extent_to_current_edit(chunk, &map);
+            write_extent(&mut map.map, chunk, 2, -2);
+        }
+        assert_eq!(map.copies.get(), 2);
+
+        timeline.store_current_edit("drag", &map);
+        let metadata = &timeline.node(timeline.current()).edit.metadata;
+        assert_eq!(metadata.num_voxels, 64);
+        timeline.undo(&mut map);
+        assert_eq!(snapshot(&map.map), before);
+    }
+
+    #[test]
+    fn transaction_produces_a_single_undo_entry() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let before = snapshot(&map);
+
+        timeline.begin_transaction(&map);
+        for i in 0..3 {
+            let extent = Extent3i::from_min_and_shape(PointN([i * 2 - 4; 3]), PointN([2; 3]));
+            timeline.add_extent_to_current_edit(extent, &map);
+            write_extent(&mut map, extent, 1, -1);
+            // Tools finishing their edits inside the transaction don't create entries.
+            timeline.store_current_edit("tool edit", &map);
+
+            // Nor do nested transactions.
+            timeline.begin_transaction(&map);
+            write_extent_in_edit(&mut timeline, &mut map, extent, 2, -2);
+            timeline.commit_transaction("nested", &map);
+        }
+        timeline.commit_transaction("batch", &map);
+        let after = snapshot(&map);
+
+        assert_eq!(timeline.current(), 1);
+        assert_eq!(timeline.history().len(), 2);
+        timeline.undo(&mut map);
+        assert_eq!(snapshot(&map), before);
+        timeline.redo(&mut map);
+        assert_eq!(snapshot(&map), after);
+    }
+
+    #[test]
+    fn abort_restores_the_map_from_the_start_of_the_transaction() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+
+        // This edit is unfinished when the transaction begins, so it gets its own entry.
+        let first = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([3; 3]));
+        write_extent_in_edit(&mut timeline, &mut map, first, 1, -1);
+        let before_transaction = snapshot(&map);
+
+        timeline.begin_transaction(&map);
+        let second = Extent3i::from_min_and_shape(PointN([-2; 3]), PointN([4; 3]));
+        write_extent_in_edit(&mut timeline, &mut map, second, 2, -2);
+        timeline.abort_transaction(&mut map);
+
+        assert!(!timeline.in_transaction());
+        assert_eq!(snapshot(&map), before_transaction);
+        assert_eq!(timeline.current(), 1);
+        timeline.undo(&mut map);
+        assert_eq!(timeline.current(), ROOT_EDIT_ID);
+    }
+
+    #[test]
+    fn aborting_a_nested_transaction_only_reverts_its_own_edits() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let before = snapshot(&map);
+
+        timeline.begin_transaction(&map);
+        let outer = Extent3i::from_min_and_shape(PointN([-2; 3]), PointN([4; 3]));
+        write_extent_in_edit(&mut timeline, &mut map, outer, 1, -1);
+        let before_inner = snapshot(&map);
+
+        // Overlaps the outer edit, and covers a whole chunk.
+        timeline.begin_transaction(&map);
+        let inner = Extent3i::from_min_and_shape(PointN([0; 3]), CHUNK_SHAPE);
+        write_extent_in_edit(&mut timeline, &mut map, inner, 2, -2);
+        timeline.abort_transaction(&mut map);
+
+        assert!(timeline.in_transaction());
+        assert_eq!(snapshot(&map), before_inner);
+
+        // Edits after the abort still belong to the outer transaction.
+        timeline.begin_transaction(&map);
+        write_extent_in_edit(&mut timeline, &mut map, inner, 3, -3);
+        timeline.commit_transaction("nested", &map);
+        let after = snapshot(&map);
+        timeline.commit_transaction("outer", &map);
+
+        assert!(!timeline.in_transaction());
+        assert_eq!(timeline.current(), 1);
+        timeline.undo(&mut map);
+        assert_eq!(snapshot(&map), before);
+        timeline.redo(&mut map);
+        assert_eq!(snapshot(&map), after);
+    }
+
+    #[test]
+    fn edits_and_undos_mark_their_chunks_dirty() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+
+        // Crosses the boundary between two chunks along X.
+        let extent = Extent3i::from_min_and_shape(PointN([3, 0, 0]), PointN([2, 1, 1]));
+        write_extent_in_edit(&mut timeline, &mut map, extent, 1, -1);
+        timeline.store_current_edit("test edit", &map);
+        let expected: HashSet<ChunkKey3> = [[0, 0, 0], [4, 0, 0]]
+            .iter()
+            .map(|min| ChunkKey::new(0, PointN(*min)))
+            .collect();
+        assert_eq!(timeline.take_dirty_chunks(), expected);
+        assert!(timeline.take_dirty_chunks().is_empty());
+
+        timeline.undo(&mut map);
+        assert_eq!(timeline.take_dirty_chunks(), expected);
+        timeline.redo(&mut map);
+        assert_eq!(timeline.take_dirty_chunks(), expected);
+    }
+
+    #[test]
+    fn replaying_the_journal_recovers_edits_and_history() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let empty = snapshot(&map);
+
+        let mut records = Vec::new();
+        for (i, min) in [[-4; 3], [1; 3]].iter().enumerate() {
+            let extent = Extent3i::from_min_and_shape(PointN(*min), PointN([4; 3]));
+            write_extent_in_edit(&mut timeline, &mut map, extent, i as u8 + 1, -3);
+            // Unfinished edits aren't journaled.
+            assert!(timeline.take_journal_record(&map).is_none());
+            timeline.store_current_edit("test edit", &map);
+            records.extend(timeline.take_journal_record(&map));
+        }
+        assert!(timeline.take_journal_record(&map).is_none());
+
+        let mut recovered_map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut recovered = EditTimeline::new(CHUNK_SHAPE);
+        for record in records.iter() {
+            recovered
+                .replay_journal_record(record, &mut recovered_map)
+                .unwrap();
+        }
+        assert_eq!(recovered.current(), timeline.current());
+        assert_eq!(snapshot(&recovered_map), snapshot(&map));
+        assert!(recovered.has_unsaved_changes());
+
+        recovered.undo(&mut recovered_map);
+        recovered.undo(&mut recovered_map);
+        assert_eq!(snapshot(&recovered_map), empty);
+    }
+
+    #[test]
+    fn a_journal_record_without_its_current_edit_changes_nothing() {
+        let mut map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut timeline = EditTimeline::new(CHUNK_SHAPE);
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([4; 3]));
+        write_extent_in_edit(&mut timeline, &mut map, extent, 1, -3);
+        timeline.store_current_edit("test edit", &map);
+        let mut record: JournalRecord =
+            bincode::deserialize(&timeline.take_journal_record(&map).unwrap()).unwrap();
+        record.current = 42;
+
+        let mut recovered_map = empty_sdf_chunk_hash_map(CHUNK_SHAPE);
+        let mut recovered = EditTimeline::new(CHUNK_SHAPE);
+        let empty = snapshot(&recovered_map);
+        assert!(recovered
+            .replay_journal_record(&bincode::serialize(&record).unwrap(), &mut recovered_map)
+            .is_err());
+        assert_eq!(recovered.current(), ROOT_EDIT_ID);
+        assert_eq!(recovered.nodes.len(), 1);
+        assert_eq!(snapshot(&recovered_map), empty);
+        assert!(!recovered.has_unsaved_changes());
+    }
+
+    #[test]
+    fn decoding_a_chunk_with_the_wrong_number_of_voxels_fails() {
+        let extent = Extent3i::from_min_and_shape(Point3i::ZERO, CHUNK_SHAPE);
+        let voxels = encode_chunk(&ambient_sdf_array(extent));
+        assert!(decode_chunk(voxels.clone(), extent).is_ok());
+
+        let mut too_few = voxels.clone();
+        too_few.pop();
+        assert!(decode_chunk(too_few, extent).is_err());
+        let mut too_many = voxels;
+        too_many.push((1, -1));
+        assert!(decode_chunk(too_many, extent).is_err());
+    }
+
+    fn write_extent_in_edit(
+        timeline: &mut EditTimeline,
+        map: &mut SdfChunkHashMap,
+        extent: Extent3i,
+        voxel_type: u8,
+        dist: i8,
+    ) {
+        timeline.add_extent_to_current_edit(extent, map);
+        write_extent(map, extent, voxel_type, dist);
+    }
 }
@@ -0,0 +1,379 @@
+use super::{EditMetadata, SnapshotMap};
+
+use feldspar::{
+    bb::prelude::*,
+    prelude::{ambient_sdf_array, SdfArray, VoxelType},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub type SdfVoxel = (VoxelType, Sd8);
+
+/// If more than this fraction of a chunk's voxels changed, it's cheaper to store the whole chunk
+/// than a list of changes.
+const FULL_CHUNK_CHANGE_FRACTION: f32 = 0.5;
+
+/// The voxels that have been touched since the last edit was finalized, with their values from
+/// before the edit.
+#[derive(Default)]
+pub struct PendingEdit {
+    old_chunks: HashMap<ChunkKey3, OldChunk>,
+}
+
+enum OldChunk {
+    /// Only the voxels that have been touched so far.
+    Partial(HashMap<Point3i, SdfVoxel>),
+    /// The whole chunk, once every voxel in it has been touched.
+    Full(SdfArray),
+}
+
+impl PendingEdit {
+    /// Remembers the current values of any voxels in `extent` that haven't been touched yet. A
+    /// chunk is only copied from `map` when it has voxels that weren't touched before, so tools
+    /// can call this every frame.
+    pub fn add_extent(
+        &mut self,
+        extent: Extent3i,
+        indexer: &ChunkIndexer3,
+        map: &impl SnapshotMap,
+    ) {
+        for chunk_min in indexer.chunk_mins_for_extent(&extent) {
+            let chunk_key = ChunkKey::new(0, chunk_min);
+            let chunk_extent = indexer.extent_for_chunk_with_min(chunk_min);
+            let old_chunk = self
+                .old_chunks
+                .entry(chunk_key)
+                .or_insert_with(|| OldChunk::Partial(HashMap::new()));
+            let recorded = match old_chunk {
+                OldChunk::Full(_) => continue,
+                OldChunk::Partial(recorded) => recorded,
+            };
+
+            let new_points: Vec<Point3i> = extent
+                .intersection(&chunk_extent)
+                .iter_points()
+                .filter(|p| !recorded.contains_key(p))
+                .collect();
+            if new_points.is_empty() {
+                continue;
+            }
+
+            let mut chunk = map.copy_chunk(chunk_key);
+            if recorded.len() + new_points.len() == chunk_extent.num_points() {
+                // The voxels that were already touched have older values than the map.
+                for (&p, &old) in recorded.iter() {
+                    set_voxel(&mut chunk, p, old);
+                }
+                *old_chunk = OldChunk::Full(chunk);
+            } else {
+                for p in new_points.into_iter() {
+                    recorded.insert(p, get_voxel(&chunk, p));
+                }
+            }
+        }
+    }
+
+    /// Adds the voxels remembered by `inner`, which began after `self`. Where both remember a
+    /// voxel, the value from `self` is older, so it wins.
+    pub fn merge(&mut self, inner: PendingEdit) {
+        for (chunk_key, inner_chunk) in inner.old_chunks.into_iter() {
+            let old_chunk = match self.old_chunks.remove(&chunk_key) {
+                None => inner_chunk,
+                Some(OldChunk::Full(chunk)) => OldChunk::Full(chunk),
+                Some(OldChunk::Partial(mut recorded)) => match inner_chunk {
+                    OldChunk::Partial(inner_recorded) => {
+                        for (p, old) in inner_recorded.into_iter() {
+                            recorded.entry(p).or_insert(old);
+                        }
+                        OldChunk::Partial(recorded)
+                    }
+                    OldChunk::Full(mut chunk) => {
+                        for (p, old) in recorded.into_iter() {
+                            set_voxel(&mut chunk, p, old);
+                        }
+                        OldChunk::Full(chunk)
+                    }
+                },
+            };
+            self.old_chunks.insert(chunk_key, old_chunk);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.old_chunks.is_empty()
+    }
+
+    /// Writes the remembered values back into `map`, undoing everything since the edit began.
+    pub fn revert(self, map: &mut impl SnapshotMap) {
+        for (chunk_key, old_chunk) in self.old_chunks.into_iter() {
+            let chunk = match old_chunk {
+                OldChunk::Partial(old_voxels) => {
+                    let mut chunk = map.copy_chunk(chunk_key);
+                    for (point, old) in old_voxels.into_iter() {
+                        set_voxel(&mut chunk, point, old);
+                    }
+                    chunk
+                }
+                OldChunk::Full(old_chunk) => old_chunk,
+            };
+            map.write_chunk(chunk_key, chunk);
+        }
+    }
+
+    /// Compares the remembered voxels with their current values in `map`, keeping only the voxels
+    /// that actually changed.
+    pub fn finalize(self, map: &impl SnapshotMap, description: String) -> Edit {
+        let mut chunks = Vec::new();
+        let mut num_changed_voxels = 0;
+        for (chunk_key, old_chunk) in self.old_chunks.into_iter() {
+            let new_chunk = map.copy_chunk(chunk_key);
+            let changed = |(point, old): (Point3i, SdfVoxel)| {
+                let new = get_voxel(&new_chunk, point);
+                (old != new).then(|| VoxelChange { point, old, new })
+            };
+            let changes: Vec<VoxelChange> = match &old_chunk {
+                OldChunk::Partial(old_voxels) => old_voxels
+                    .iter()
+                    .filter_map(|(&point, &old)| changed((point, old)))
+                    .collect(),
+                OldChunk::Full(old) => old
+                    .extent()
+                    .iter_points()
+                    .filter_map(|point| changed((point, get_voxel(old, point))))
+                    .collect(),
+            };
+            if changes.is_empty() {
+                continue;
+            }
+            num_changed_voxels += changes.len();
+
+            let chunk_volume = new_chunk.extent().num_points();
+            let delta = if changes.len() as f32 > FULL_CHUNK_CHANGE_FRACTION * chunk_volume as f32 {
+                let old = match old_chunk {
+                    OldChunk::Full(old) => old,
+                    OldChunk::Partial(_) => {
+                        let mut old = new_chunk.clone();
+                        for change in changes.iter() {
+                            set_voxel(&mut old, change.point, change.old);
+                        }
+                        old
+                    }
+                };
+                ChunkDelta::Full {
+                    old,
+                    new: new_chunk,
+                }
+            } else {
+                ChunkDelta::Sparse(changes)
+            };
+            chunks.push((chunk_key, delta));
+        }
+
+        let metadata = EditMetadata::new(description, chunks.len(), num_changed_voxels);
+
+        Edit { chunks, metadata }
+    }
+}
+
+/// The changes made to the map by a single edit, and a description of that edit.
+pub struct Edit {
+    chunks: Vec<(ChunkKey3, ChunkDelta)>,
+    pub metadata: EditMetadata,
+}
+
+enum ChunkDelta {
+    /// Only the voxels that changed.
+    Sparse(Vec<VoxelChange>),
+    /// The whole chunk, for edits that changed most of it.
+    Full { old: SdfArray, new: SdfArray },
+}
+
+struct VoxelChange {
+    point: Point3i,
+    old: SdfVoxel,
+    new: SdfVoxel,
+}
+
+impl Edit {
+    pub fn new(metadata: EditMetadata) -> Self {
+        Self {
+            chunks: Vec::new(),
+            metadata,
+        }
+    }
+
+    /// Forgets the changes, but keeps the metadata.
+    pub fn clear_changes(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// The chunks written by `undo` and `redo`.
+    pub fn chunk_keys(&self) -> impl Iterator<Item = ChunkKey3> + '_ {
+        self.chunks.iter().map(|(key, _)| *key)
+    }
+
+    /// Writes the values from before the edit into `map`.
+    pub fn undo(&self, map: &mut impl SnapshotMap) {
+        self.apply(map, false);
+    }
+
+    /// Writes the values from after the edit into `map`.
+    pub fn redo(&self, map: &mut impl SnapshotMap) {
+        self.apply(map, true);
+    }
+
+    fn apply(&self, map: &mut impl SnapshotMap, forward: bool) {
+        for (chunk_key, delta) in self.chunks.iter() {
+            match delta {
+                ChunkDelta::Sparse(changes) => {
+                    let mut chunk = map.copy_chunk(*chunk_key);
+                    for change in changes.iter() {
+                        let value = if forward { change.new } else { change.old };
+                        set_voxel(&mut chunk, change.point, value);
+                    }
+                    map.write_chunk(*chunk_key, chunk);
+                }
+                ChunkDelta::Full { old, new } => {
+                    let chunk = if forward { new } else { old };
+                    map.write_chunk(*chunk_key, chunk.clone());
+                }
+            }
+        }
+    }
+
+    pub fn encode(&self) -> EncodedEdit {
+        EncodedEdit {
+            metadata: self.metadata.clone(),
+            chunks: self
+                .chunks
+                .iter()
+                .map(|(key, delta)| {
+                    let minimum = key.minimum.0;
+                    match delta {
+                        ChunkDelta::Sparse(changes) => EncodedChunkDelta::Sparse {
+                            minimum,
+                            changes: changes
+                                .iter()
+                                .map(|c| (c.point.0, encode_voxel(c.old), encode_voxel(c.new)))
+                                .collect(),
+                        },
+                        ChunkDelta::Full { old, new } => EncodedChunkDelta::Full {
+                            minimum,
+                            old: encode_chunk(old),
+                            new: encode_chunk(new),
+                        },
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn decode(encoded: EncodedEdit, indexer: &ChunkIndexer3) -> bincode::Result<Self> {
+        let chunks = encoded
+            .chunks
+            .into_iter()
+            .map(|delta| match delta {
+                EncodedChunkDelta::Sparse { minimum, changes } => Ok((
+                    ChunkKey::new(0, PointN(minimum)),
+                    ChunkDelta::Sparse(
+                        changes
+                            .into_iter()
+                            .map(|(point, old, new)| VoxelChange {
+                                point: PointN(point),
+                                old: decode_voxel(old),
+                                new: decode_voxel(new),
+                            })
+                            .collect(),
+                    ),
+                )),
+                EncodedChunkDelta::Full { minimum, old, new } => {
+                    let extent = indexer.extent_for_chunk_with_min(PointN(minimum));
+                    Ok((
+                        ChunkKey::new(0, PointN(minimum)),
+                        ChunkDelta::Full {
+                            old: decode_chunk(old, extent)?,
+                            new: decode_chunk(new, extent)?,
+                        },
+                    ))
+                }
+            })
+            .collect::<bincode::Result<_>>()?;
+
+        Ok(Self {
+            chunks,
+            metadata: encoded.metadata,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EncodedEdit {
+    metadata: EditMetadata,
+    chunks: Vec<EncodedChunkDelta>,
+}
+
+#[derive(Deserialize, Serialize)]
+enum EncodedChunkDelta {
+    Sparse {
+        minimum: [i32; 3],
+        /// `(point, old, new)` for every changed voxel.
+        changes: Vec<([i32; 3], (u8, i8), (u8, i8))>,
+    },
+    Full {
+        minimum: [i32; 3],
+        /// Every voxel in the chunk's extent, in iteration order.
+        old: Vec<(u8, i8)>,
+        new: Vec<(u8, i8)>,
+    },
+}
+
+fn get_voxel(chunk: &SdfArray, p: Point3i) -> SdfVoxel {
+    chunk.get(p)
+}
+
+fn set_voxel(chunk: &mut SdfArray, p: Point3i, (voxel_type, dist): SdfVoxel) {
+    let (t, d) = chunk.get_mut(p);
+    *t = voxel_type;
+    *d = dist;
+}
+
+fn encode_voxel((voxel_type, dist): SdfVoxel) -> (u8, i8) {
+    (voxel_type.0, dist.0)
+}
+
+fn decode_voxel((voxel_type, dist): (u8, i8)) -> SdfVoxel {
+    (VoxelType(voxel_type), Sd8(dist))
+}
+
+pub fn encode_chunk(chunk: &SdfArray) -> Vec<(u8, i8)> {
+    let mut voxels = Vec::new();
+    chunk.for_each(chunk.extent(), |_p: Point3i, v: SdfVoxel| {
+        voxels.push(encode_voxel(v));
+    });
+
+    voxels
+}
+
+/// Fails if there isn't exactly one voxel for every point of `extent`.
+pub fn decode_chunk(voxels: Vec<(u8, i8)>, extent: Extent3i) -> bincode::Result<SdfArray> {
+    if voxels.len() != extent.num_points() {
+        return Err(Box::new(bincode::ErrorKind::Custom(format!(
+            "a chunk has {} voxels instead of {}",
+            voxels.len(),
+            extent.num_points()
+        ))));
+    }
+
+    let mut chunk = ambient_sdf_array(extent);
+    let mut voxels = voxels.into_iter().map(decode_voxel);
+    chunk.for_each_mut(
+        &extent,
+        |_p: Point3i, (t, d): (&mut VoxelType, &mut Sd8)| {
+            let (voxel_type, dist) = voxels.next().unwrap();
+            *t = voxel_type;
+            *d = dist;
+        },
+    );
+
+    Ok(chunk)
+}
@@ -0,0 +1,210 @@
+use super::{
+    edit_timeline::{EditTimeline, HistoryEntry, StreamedMap},
+    CurrentTool,
+};
+
+use crate::{Action, ActionInput, Config, KeyBindings};
+
+use feldspar::prelude::{VoxelDb, VoxelEditor};
+
+use bevy::{ecs::prelude::*, window::ReceivedCharacter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Lists the whole edit history in the log, and lets the user jump to any entry or label the
+/// current state.
+#[derive(Clone, Default)]
+pub struct HistoryBrowserState {
+    /// Index into `EditTimeline::history`.
+    selected: usize,
+    /// The label being typed, if the user is labeling the current state.
+    label_input: Option<String>,
+}
+
+impl HistoryBrowserState {
+    pub fn is_typing(&self) -> bool {
+        self.label_input.is_some()
+    }
+}
+
+pub enum HistoryBrowserEvents {
+    MoveSelection(i32),
+    JumpToSelected,
+    StartLabel,
+    TypeLabel(char),
+    DeleteLabelChar,
+    FinishLabel,
+    CancelLabel,
+}
+
+pub fn history_browser_default_input_map(
+    mut events: EventWriter<HistoryBrowserEvents>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut actions: EventReader<Action>,
+    current_tool: Res<CurrentTool>,
+    input: ActionInput,
+) {
+    // Always drain the characters, so the key that starts labeling isn't typed into the label.
+    let typed: Vec<char> = characters.iter().map(|c| c.char).collect();
+    let actions: Vec<Action> = actions.iter().copied().collect();
+
+    let state = if let CurrentTool::HistoryBrowser(state) = &*current_tool {
+        state
+    } else {
+        return;
+    };
+
+    if state.is_typing() {
+        if actions.contains(&Action::Confirm) {
+            events.send(HistoryBrowserEvents::FinishLabel);
+        } else if actions.contains(&Action::Cancel) {
+            events.send(HistoryBrowserEvents::CancelLabel);
+        } else if input.just_pressed(Action::DeleteChar) {
+            events.send(HistoryBrowserEvents::DeleteLabelChar);
+        } else {
+            for c in typed.into_iter().filter(|c| !c.is_control()) {
+                events.send(HistoryBrowserEvents::TypeLabel(c));
+            }
+        }
+        return;
+    }
+
+    for action in actions {
+        match action {
+            Action::SelectPrevious => events.send(HistoryBrowserEvents::MoveSelection(-1)),
+            Action::SelectNext => events.send(HistoryBrowserEvents::MoveSelection(1)),
+            Action::Confirm => events.send(HistoryBrowserEvents::JumpToSelected),
+            Action::Label => events.send(HistoryBrowserEvents::StartLabel),
+            _ => {}
+        }
+    }
+}
+
+pub fn history_browser_system(
+    mut current_tool: ResMut<CurrentTool>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut editor: VoxelEditor,
+    voxel_db: Res<VoxelDb>,
+    mut events: EventReader<HistoryBrowserEvents>,
+    config: Res<Config>,
+) {
+    let state = if let CurrentTool::HistoryBrowser(state) = &mut *current_tool {
+        state
+    } else {
+        return;
+    };
+
+    let mut history_changed = false;
+    for event in events.iter() {
+        match event {
+            HistoryBrowserEvents::MoveSelection(delta) => {
+                let num_entries = edit_timeline.history().len() as i32;
+                state.selected =
+                    (state.selected as i32 + delta).max(0).min(num_entries - 1) as usize;
+                history_changed = true;
+            }
+            HistoryBrowserEvents::JumpToSelected => {
+                if let Some(entry) = edit_timeline.history().get(state.selected) {
+                    log::info!("Jumping to edit {}", entry.id);
+                    edit_timeline.jump_to(entry.id, &mut StreamedMap::new(&mut editor, &voxel_db));
+                    history_changed = true;
+                }
+            }
+            HistoryBrowserEvents::StartLabel => {
+                log::info!(
+                    "Type a label for the current state, then press {}",
+                    config.bindings.chord_names(Action::Confirm)
+                );
+                state.label_input = Some(String::new());
+            }
+            HistoryBrowserEvents::TypeLabel(c) => {
+                if let Some(label) = &mut state.label_input {
+                    label.push(*c);
+                    log::info!("Label: {}", label);
+                }
+            }
+            HistoryBrowserEvents::DeleteLabelChar => {
+                if let Some(label) = &mut state.label_input {
+                    label.pop();
+                    log::info!("Label: {}", label);
+                }
+            }
+            HistoryBrowserEvents::FinishLabel => {
+                if let Some(label) = state.label_input.take() {
+                    if !label.is_empty() {
+                        edit_timeline.label_current(label);
+                        history_changed = true;
+                    }
+                }
+            }
+            HistoryBrowserEvents::CancelLabel => {
+                state.label_input = None;
+            }
+        }
+    }
+
+    if history_changed {
+        log_history(&edit_timeline.history(), state.selected, &config.bindings);
+    }
+}
+
+/// Prints the history and returns the browser tool, with the current state selected.
+pub fn open_history_browser(edit_timeline: &EditTimeline, bindings: &KeyBindings) -> CurrentTool {
+    let history = edit_timeline.history();
+    let selected = history.iter().position(|e| e.is_current).unwrap_or(0);
+    log_history(&history, selected, bindings);
+
+    CurrentTool::HistoryBrowser(HistoryBrowserState {
+        selected,
+        label_input: None,
+    })
+}
+
+fn log_history(history: &[HistoryEntry], selected: usize, bindings: &KeyBindings) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    log::info!(
+        "Edit history ({})",
+        bindings.help(&[
+            (&[Action::SelectPrevious, Action::SelectNext], "select"),
+            (&[Action::Confirm], "jump"),
+            (&[Action::Label], "label current state"),
+        ])
+    );
+    for (i, entry) in history.iter().enumerate() {
+        let selected_marker = if i == selected { '>' } else { ' ' };
+        let current_marker = if entry.is_current { '*' } else { ' ' };
+        let label = entry
+            .metadata
+            .label
+            .as_ref()
+            .map(|l| format!(" [{}]", l))
+            .unwrap_or_default();
+        log::info!(
+            "{}{} {}{}{}: {} ({} voxels in {} chunks, {})",
+            selected_marker,
+            current_marker,
+            "  ".repeat(entry.depth),
+            entry.id,
+            label,
+            entry.metadata.description,
+            entry.metadata.num_voxels,
+            entry.metadata.num_chunks,
+            format_age(now.saturating_sub(entry.metadata.timestamp)),
+        );
+    }
+}
+
+fn format_age(seconds: u64) -> String {
+    if seconds < 60 {
+        format!("{}s ago", seconds)
+    } else if seconds < 60 * 60 {
+        format!("{}m ago", seconds / 60)
+    } else if seconds < 24 * 60 * 60 {
+        format!("{}h ago", seconds / (60 * 60))
+    } else {
+        format!("{}d ago", seconds / (24 * 60 * 60))
+    }
+}
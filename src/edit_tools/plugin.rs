@@ -5,12 +5,18 @@ use super::{
         drag_face_default_input_map, drag_face_tool_system, DragFaceEvents, DragFaceState,
     },
     edit_timeline::EditTimeline,
+    history_browser::{
+        history_browser_default_input_map, history_browser_system, HistoryBrowserEvents,
+    },
     selection::{SelectionEvents, SelectionPlugin},
     terraformer::{
         terraformer_default_input_map, terraformer_system, Terraformer, TerraformerEvents,
     },
     tool_switcher::tool_switcher_system,
     undo::undo_system,
+    version_browser::{
+        version_browser_default_input_map, version_browser_system, VersionBrowserEvents,
+    },
     CurrentTool,
 };
 
@@ -38,6 +44,8 @@ impl Plugin for EditToolsPlugin {
             .add_event::<TerraformerEvents>()
             .add_event::<DragFaceEvents>()
             .add_event::<SelectionEvents>()
+            .add_event::<HistoryBrowserEvents>()
+            .add_event::<VersionBrowserEvents>()
             .add_system_set(
                 SystemSet::on_update(EditorState::Editing)
                     .with_system(undo_system.system())
@@ -45,7 +53,11 @@ impl Plugin for EditToolsPlugin {
                     .with_system(terraformer_system.system())
                     .with_system(terraformer_default_input_map.system())
                     .with_system(drag_face_tool_system.system())
-                    .with_system(drag_face_default_input_map.system()),
+                    .with_system(drag_face_default_input_map.system())
+                    .with_system(history_browser_system.system())
+                    .with_system(history_browser_default_input_map.system())
+                    .with_system(version_browser_system.system())
+                    .with_system(version_browser_default_input_map.system()),
             );
     }
 }
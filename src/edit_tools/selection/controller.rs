@@ -1,8 +1,10 @@
-use crate::picking::{VoxelCursor, VoxelFace};
+use crate::{
+    picking::{VoxelCursor, VoxelFace},
+    Action, ActionInput,
+};
 
 use bevy::{
     ecs::prelude::*,
-    input::prelude::*,
     prelude::{EventReader, EventWriter},
 };
 use feldspar::bb::core::{prelude::*, SignedAxis3};
@@ -39,10 +41,15 @@ pub fn selection_default_input_map(
     mut events: EventWriter<SelectionEvents>,
     mut selection_state: ResMut<SelectionState>,
     voxel_cursor: VoxelCursor,
+    input: ActionInput,
 ) {
+    let clicked_face = input
+        .mouse_buttons(Action::SelectCorner)
+        .into_iter()
+        .find_map(|button| voxel_cursor.voxel_just_clicked(button));
     match &mut *selection_state {
         SelectionState::SelectingFirstCorner => {
-            if let Some(first_corner) = voxel_cursor.voxel_just_clicked(MouseButton::Left) {
+            if let Some(first_corner) = clicked_face {
                 events.send(SelectionEvents::SelectFirstCorner(first_corner));
             }
         }
@@ -52,7 +59,7 @@ pub fn selection_default_input_map(
                     if hover_face != *previous_hover {
                         events.send(SelectionEvents::HoverMove(hover_face));
                     }
-                    if voxel_cursor.voxel_just_clicked(MouseButton::Left).is_some() {
+                    if clicked_face.is_some() {
                         events.send(SelectionEvents::SelectSecondCorner(hover_face));
                     }
                 }
@@ -0,0 +1,124 @@
+use super::{
+    history_browser::open_history_browser, selection::SelectionState, terraformer::Terraformer,
+    version_browser::open_version_browser, CurrentTool, DragFaceState, EditTimeline,
+};
+
+use crate::{EditorDb, EditorResult, KeyBindings};
+
+use feldspar::{
+    bb::core::{prelude::*, SignedAxis3},
+    prelude::VoxelType,
+};
+use serde::{Deserialize, Serialize};
+
+/// The state of the edit tools that's kept between sessions.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToolSession {
+    pub tool: SavedTool,
+    pub edit_radius: u32,
+    pub voxel_type: u8,
+    pub selection: Option<SavedSelection>,
+}
+
+impl Default for ToolSession {
+    fn default() -> Self {
+        let terraformer = Terraformer::default();
+
+        Self {
+            tool: SavedTool::DragFace,
+            edit_radius: terraformer.edit_radius,
+            voxel_type: terraformer.voxel_type.0,
+            selection: None,
+        }
+    }
+}
+
+/// Which tool was active. Anything in progress, like a drag or a name being typed, is dropped.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum SavedTool {
+    DragFace,
+    Terraform,
+    HistoryBrowser,
+    VersionBrowser,
+}
+
+/// A finished selection of a quad of voxels.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct SavedSelection {
+    pub min: [i32; 3],
+    pub shape: [i32; 3],
+    pub normal: [i32; 3],
+}
+
+impl ToolSession {
+    pub fn capture(
+        current_tool: &CurrentTool,
+        terraformer: &Terraformer,
+        selection: &SelectionState,
+    ) -> Self {
+        let tool = match current_tool {
+            CurrentTool::Terraform => SavedTool::Terraform,
+            CurrentTool::HistoryBrowser(_) => SavedTool::HistoryBrowser,
+            CurrentTool::VersionBrowser(_) => SavedTool::VersionBrowser,
+            _ => SavedTool::DragFace,
+        };
+        let selection = match selection {
+            SelectionState::SelectionReady {
+                quad_extent,
+                normal,
+            } => {
+                let mut normal_vector = Point3i::ZERO;
+                *normal_vector.axis_component_mut(normal.axis) = normal.sign;
+
+                Some(SavedSelection {
+                    min: quad_extent.minimum.0,
+                    shape: quad_extent.shape.0,
+                    normal: normal_vector.0,
+                })
+            }
+            _ => None,
+        };
+
+        Self {
+            tool,
+            edit_radius: terraformer.edit_radius,
+            voxel_type: terraformer.voxel_type.0,
+            selection,
+        }
+    }
+
+    pub fn restore(
+        &self,
+        current_tool: &mut CurrentTool,
+        terraformer: &mut Terraformer,
+        selection: &mut SelectionState,
+        editor_db: &EditorDb,
+        edit_timeline: &EditTimeline,
+        bindings: &KeyBindings,
+    ) -> EditorResult<()> {
+        terraformer.edit_radius = self.edit_radius.max(1);
+        terraformer.voxel_type = VoxelType(self.voxel_type);
+
+        if let Some(saved) = self.selection {
+            if let Some(normal) = SignedAxis3::from_vector(PointN(saved.normal)) {
+                *selection = SelectionState::SelectionReady {
+                    quad_extent: Extent3i::from_min_and_shape(
+                        PointN(saved.min),
+                        PointN(saved.shape),
+                    ),
+                    normal,
+                };
+            }
+        }
+
+        *current_tool = match self.tool {
+            SavedTool::DragFace => CurrentTool::DragFace(DragFaceState::SelectionReady),
+            SavedTool::Terraform => CurrentTool::Terraform,
+            SavedTool::HistoryBrowser => open_history_browser(edit_timeline, bindings),
+            SavedTool::VersionBrowser => open_version_browser(editor_db, bindings)?,
+        };
+
+        Ok(())
+    }
+}
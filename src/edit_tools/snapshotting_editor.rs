@@ -1,7 +1,7 @@
-use super::edit_timeline::EditTimeline;
+use super::edit_timeline::{EditTimeline, StreamedMap};
 
 use feldspar::bb::prelude::*;
-use feldspar::prelude::{VoxelEditor, VoxelType};
+use feldspar::prelude::{VoxelDb, VoxelEditor, VoxelType};
 
 use bevy::ecs::{prelude::*, system::SystemParam};
 
@@ -9,6 +9,7 @@ use bevy::ecs::{prelude::*, system::SystemParam};
 pub struct SnapshottingVoxelEditor<'a> {
     editor: VoxelEditor<'a>,
     timeline: ResMut<'a, EditTimeline>,
+    voxel_db: Res<'a, VoxelDb>,
 }
 
 impl<'a> SnapshottingVoxelEditor<'a> {
@@ -17,13 +18,36 @@ impl<'a> SnapshottingVoxelEditor<'a> {
         extent: Extent3i,
         edit_func: impl FnMut(Point3i, (&mut VoxelType, &mut Sd8)),
     ) {
-        self.timeline
-            .add_extent_to_current_edit(extent, &self.editor.map.voxels);
+        // The edit might reach into chunks that haven't been streamed in yet.
+        let mut map = StreamedMap::new(&mut self.editor, &self.voxel_db);
+        map.load_chunks_in(extent);
+        self.timeline.add_extent_to_current_edit(extent, &map);
         self.editor
             .edit_extent_and_touch_neighbors(extent, edit_func);
     }
 
-    pub fn finish_edit(&mut self) {
-        self.timeline.store_current_edit();
+    /// Stores everything edited since the last call as a single undoable edit, described to the
+    /// user by `description`. Inside of a transaction, the edit becomes part of the transaction.
+    pub fn finish_edit(&mut self, description: impl Into<String>) {
+        let map = StreamedMap::new(&mut self.editor, &self.voxel_db);
+        self.timeline.store_current_edit(description, &map);
+    }
+
+    /// Groups all edits until the matching `commit_transaction` or `abort_transaction` into a single
+    /// undoable edit, even across multiple tools and calls to `finish_edit`.
+    pub fn begin_transaction(&mut self) {
+        let map = StreamedMap::new(&mut self.editor, &self.voxel_db);
+        self.timeline.begin_transaction(&map);
+    }
+
+    pub fn commit_transaction(&mut self, description: impl Into<String>) {
+        let map = StreamedMap::new(&mut self.editor, &self.voxel_db);
+        self.timeline.commit_transaction(description, &map);
+    }
+
+    /// Reverts the map to the state it was in when the transaction began.
+    pub fn abort_transaction(&mut self) {
+        let mut map = StreamedMap::new(&mut self.editor, &self.voxel_db);
+        self.timeline.abort_transaction(&mut map);
     }
 }
@@ -1,16 +1,18 @@
 use super::{CurrentTool, SnapshottingVoxelEditor};
 
-use crate::{CursorRay, VoxelCursor};
+use crate::{Action, ActionInput, CursorRay, VoxelCursor};
 
 use feldspar::bb::{core::prelude::*, storage::prelude::Sd8};
 use feldspar::prelude::VoxelType;
 
-use bevy::{ecs::prelude::*, input::prelude::*, prelude::*};
+use bevy::{ecs::prelude::*, prelude::*};
 
 pub struct Terraformer {
-    edit_radius: u32,
-    voxel_type: VoxelType,
+    pub(super) edit_radius: u32,
+    pub(super) voxel_type: VoxelType,
     dist_from_camera: Option<f32>,
+    /// The operation performed since the last finished edit, used to describe the edit.
+    pending_operation: Option<TerraformOperation>,
 }
 
 impl Default for Terraformer {
@@ -19,6 +21,21 @@ impl Default for Terraformer {
             edit_radius: 10,
             voxel_type: VoxelType(1),
             dist_from_camera: None,
+            pending_operation: None,
+        }
+    }
+}
+
+impl Terraformer {
+    fn describe(&self, operation: TerraformOperation) -> String {
+        match operation {
+            TerraformOperation::MakeSolid => format!(
+                "Terraform: add voxel type {} with radius {}",
+                self.voxel_type.0, self.edit_radius
+            ),
+            TerraformOperation::RemoveSolid => {
+                format!("Terraform: remove with radius {}", self.edit_radius)
+            }
         }
     }
 }
@@ -33,33 +50,30 @@ pub enum TerraformerEvents {
 
 pub fn terraformer_default_input_map(
     mut events: EventWriter<TerraformerEvents>,
-    keyboard: Res<Input<KeyCode>>,
+    mut actions: EventReader<Action>,
+    input: ActionInput,
 ) {
-    // Adjust the edit radius.
-    if keyboard.just_pressed(KeyCode::Up) {
-        events.send(TerraformerEvents::ChangeEditRadius(1))
-    } else if keyboard.just_pressed(KeyCode::Down) {
-        events.send(TerraformerEvents::ChangeEditRadius(-1))
-    }
-
-    // Adjust the voxel type to create.
-    if keyboard.just_pressed(KeyCode::Key1) {
-        events.send(TerraformerEvents::ChangeVoxelType(1));
-    } else if keyboard.just_pressed(KeyCode::Key2) {
-        events.send(TerraformerEvents::ChangeVoxelType(2));
-    } else if keyboard.just_pressed(KeyCode::Key3) {
-        events.send(TerraformerEvents::ChangeVoxelType(3));
-    } else if keyboard.just_pressed(KeyCode::Key4) {
-        events.send(TerraformerEvents::ChangeVoxelType(4));
+    for action in actions.iter() {
+        match action {
+            // Adjust the edit radius.
+            Action::GrowBrush => events.send(TerraformerEvents::ChangeEditRadius(1)),
+            Action::ShrinkBrush => events.send(TerraformerEvents::ChangeEditRadius(-1)),
+            // Adjust the voxel type to create.
+            Action::VoxelType1 => events.send(TerraformerEvents::ChangeVoxelType(1)),
+            Action::VoxelType2 => events.send(TerraformerEvents::ChangeVoxelType(2)),
+            Action::VoxelType3 => events.send(TerraformerEvents::ChangeVoxelType(3)),
+            Action::VoxelType4 => events.send(TerraformerEvents::ChangeVoxelType(4)),
+            _ => {}
+        }
     }
 
-    if keyboard.pressed(KeyCode::Z) {
+    if input.pressed(Action::TerraformAdd) {
         events.send(TerraformerEvents::MakeSolid);
-    } else if keyboard.pressed(KeyCode::X) {
+    } else if input.pressed(Action::TerraformRemove) {
         events.send(TerraformerEvents::RemoveSolid);
     }
 
-    if keyboard.just_released(KeyCode::Z) || keyboard.just_released(KeyCode::X) {
+    if input.just_released(Action::TerraformAdd) || input.just_released(Action::TerraformRemove) {
         events.send(TerraformerEvents::FinishEdit);
     }
 }
@@ -93,6 +107,7 @@ pub fn terraformer_system(
         match event {
             TerraformerEvents::MakeSolid => {
                 lock_edit_dist_from_camera = true;
+                terraformer.pending_operation = Some(TerraformOperation::MakeSolid);
                 edit_sphere(
                     TerraformOperation::MakeSolid,
                     edit_center,
@@ -103,6 +118,7 @@ pub fn terraformer_system(
             }
             TerraformerEvents::RemoveSolid => {
                 lock_edit_dist_from_camera = true;
+                terraformer.pending_operation = Some(TerraformOperation::RemoveSolid);
                 edit_sphere(
                     TerraformOperation::RemoveSolid,
                     edit_center,
@@ -112,7 +128,9 @@ pub fn terraformer_system(
                 );
             }
             TerraformerEvents::FinishEdit => {
-                voxel_editor.finish_edit();
+                if let Some(operation) = terraformer.pending_operation.take() {
+                    voxel_editor.finish_edit(terraformer.describe(operation));
+                }
             }
             TerraformerEvents::ChangeEditRadius(delta) => {
                 terraformer.edit_radius =
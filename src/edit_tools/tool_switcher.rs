@@ -1,13 +1,77 @@
-use super::{CurrentTool, DragFaceState};
+use super::{
+    drag_face::abort_drag, history_browser::open_history_browser,
+    version_browser::open_version_browser, CurrentTool, DragFaceState, EditTimeline,
+    SelectionState, StreamedMap,
+};
 
-use bevy::{ecs::prelude::*, input::prelude::*};
+use crate::{Action, Config, EditorDb, EditorError};
 
-pub fn tool_switcher_system(keyboard: Res<Input<KeyCode>>, mut current_tool: ResMut<CurrentTool>) {
-    if keyboard.just_pressed(KeyCode::D) {
-        println!("Switching to DragFace tool");
-        *current_tool = CurrentTool::DragFace(DragFaceState::SelectionReady);
-    } else if keyboard.just_pressed(KeyCode::T) {
-        println!("Switching to Terraformer tool");
-        *current_tool = CurrentTool::Terraform;
+use feldspar::prelude::{VoxelDb, VoxelEditor};
+
+use bevy::ecs::prelude::*;
+use smooth_bevy_cameras::controllers::unreal::UnrealCameraController;
+
+pub fn tool_switcher_system(
+    mut actions: EventReader<Action>,
+    config: Res<Config>,
+    mut edit_timeline: ResMut<EditTimeline>,
+    mut editor: VoxelEditor,
+    voxel_db: Res<VoxelDb>,
+    editor_db: Res<EditorDb>,
+    mut current_tool: ResMut<CurrentTool>,
+    mut selection_state: ResMut<SelectionState>,
+    mut mouse_camera_controllers: Query<&mut UnrealCameraController>,
+    mut errors: EventWriter<EditorError>,
+) {
+    let switch_to = actions.iter().copied().find(|action| {
+        matches!(
+            action,
+            Action::SwitchToDragFace
+                | Action::SwitchToTerraform
+                | Action::OpenHistoryBrowser
+                | Action::OpenVersionBrowser
+        )
+    });
+    let switch_to = if let Some(action) = switch_to {
+        action
+    } else {
+        return;
+    };
+
+    // A drag in progress would otherwise keep its transaction open forever.
+    if let CurrentTool::DragFace(state) = &*current_tool {
+        if let Some(selection) = abort_drag(
+            state,
+            &mut edit_timeline,
+            &mut StreamedMap::new(&mut editor, &voxel_db),
+        ) {
+            log::info!("Switching tools cancelled the face drag");
+            *selection_state = selection;
+            if let Some(mut controller) = mouse_camera_controllers.iter_mut().next() {
+                controller.enabled = true;
+            }
+        }
+    }
+
+    match switch_to {
+        Action::SwitchToDragFace => {
+            println!("Switching to DragFace tool");
+            *current_tool = CurrentTool::DragFace(DragFaceState::SelectionReady);
+        }
+        Action::SwitchToTerraform => {
+            println!("Switching to Terraformer tool");
+            *current_tool = CurrentTool::Terraform;
+        }
+        Action::OpenHistoryBrowser => {
+            log::info!("Switching to history browser");
+            *current_tool = open_history_browser(&edit_timeline, &config.bindings);
+        }
+        _ => {
+            log::info!("Switching to version browser");
+            match open_version_browser(&editor_db, &config.bindings) {
+                Ok(tool) => *current_tool = tool,
+                Err(e) => errors.send(e),
+            }
+        }
     }
 }
@@ -1,18 +1,48 @@
-use super::edit_timeline::EditTimeline;
+use super::edit_timeline::{EditTimeline, SnapshotMap, StreamedMap};
 
-use feldspar::prelude::VoxelEditor;
+use crate::{Action, Config};
 
-use bevy::{ecs::prelude::*, input::prelude::*};
+use feldspar::prelude::{VoxelDb, VoxelEditor};
+
+use bevy::ecs::prelude::*;
 
 pub fn undo_system(
     mut edit_timeline: ResMut<EditTimeline>,
     mut editor: VoxelEditor,
-    keys: Res<Input<KeyCode>>,
+    voxel_db: Res<VoxelDb>,
+    mut actions: EventReader<Action>,
+    config: Res<Config>,
 ) {
-    if keys.just_pressed(KeyCode::U) {
-        edit_timeline.undo(&mut editor);
+    let mut map = StreamedMap::new(&mut editor, &voxel_db);
+    for action in actions.iter() {
+        match action {
+            Action::Undo => edit_timeline.undo(&mut map),
+            Action::Redo => edit_timeline.redo(&mut map),
+            Action::NextUndoBranch => switch_to_next_branch(&mut edit_timeline, &mut map),
+            Action::PruneHistory => {
+                // The pruned history is removed from the database on the next save.
+                log::info!(
+                    "Pruning undo history to {} edits",
+                    config.undo_history_limit
+                );
+                edit_timeline.prune(config.undo_history_limit);
+            }
+            _ => {}
+        }
     }
-    if keys.just_pressed(KeyCode::R) {
-        edit_timeline.redo(&mut editor);
+}
+
+/// Cycles through the branches of the undo tree, jumping to the tip of the branch after the one we're
+/// currently on.
+fn switch_to_next_branch(edit_timeline: &mut EditTimeline, map: &mut impl SnapshotMap) {
+    let tips = edit_timeline.branch_tips();
+    log::info!("Undo branches: {:?}", tips);
+
+    let current_tip = edit_timeline.current_branch_tip();
+    let current_index = tips.iter().position(|t| *t == current_tip).unwrap_or(0);
+    let next_tip = tips[(current_index + 1) % tips.len()];
+    if next_tip != current_tip {
+        log::info!("Switching to undo branch {}", next_tip);
+        edit_timeline.jump_to(next_tip, map);
     }
 }
@@ -0,0 +1,411 @@
+use super::CurrentTool;
+
+use crate::{
+    list_versions, picking::VoxelCursorRayImpact, Action, ActionInput, Config, DiffCommand,
+    EditorDb, EditorError, EditorResult, KeyBindings, MergeResolutions, MergeSide, PendingMerge,
+    VersionCommand, VersionInfo,
+};
+
+use feldspar::bb::prelude::*;
+
+use bevy::{ecs::prelude::*, window::ReceivedCharacter};
+
+/// Lists the world versions in the log, and lets the user commit, branch and check them out.
+#[derive(Clone, Default)]
+pub struct VersionBrowserState {
+    /// Index into the list of versions, in order of creation.
+    selected: usize,
+    /// The text being typed, if the user is naming a commit or branch.
+    input: Option<VersionInput>,
+    /// The older side of the next diff, or the base of the next merge. The current version is
+    /// used as the diff base if none was marked, and the common ancestor as the merge base.
+    marked: Option<u64>,
+    /// Opposite corners of the region that conflicts are resolved in, picked with the cursor.
+    region_corners: [Option<Point3i>; 2],
+}
+
+#[derive(Clone)]
+struct VersionInput {
+    kind: VersionInputKind,
+    text: String,
+}
+
+#[derive(Clone, Copy)]
+enum VersionInputKind {
+    CommitMessage,
+    BranchName,
+}
+
+impl VersionBrowserState {
+    pub fn is_typing(&self) -> bool {
+        self.input.is_some()
+    }
+}
+
+pub enum VersionBrowserEvents {
+    MoveSelection(i32),
+    CheckoutSelected,
+    StartCommit,
+    StartBranch,
+    MarkSelected,
+    DiffSelected,
+    ClearDiff,
+    MergeSelected,
+    MarkRegionCorner(usize),
+    Resolve { side: MergeSide, all: bool },
+    Type(char),
+    DeleteChar,
+    FinishInput,
+    CancelInput,
+}
+
+pub fn version_browser_default_input_map(
+    mut events: EventWriter<VersionBrowserEvents>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut actions: EventReader<Action>,
+    current_tool: Res<CurrentTool>,
+    input: ActionInput,
+) {
+    // Always drain the characters, so the key that starts typing isn't typed into the input.
+    let typed: Vec<char> = characters.iter().map(|c| c.char).collect();
+    let actions: Vec<Action> = actions.iter().copied().collect();
+
+    let state = if let CurrentTool::VersionBrowser(state) = &*current_tool {
+        state
+    } else {
+        return;
+    };
+
+    if state.is_typing() {
+        if actions.contains(&Action::Confirm) {
+            events.send(VersionBrowserEvents::FinishInput);
+        } else if actions.contains(&Action::Cancel) {
+            events.send(VersionBrowserEvents::CancelInput);
+        } else if input.just_pressed(Action::DeleteChar) {
+            events.send(VersionBrowserEvents::DeleteChar);
+        } else {
+            for c in typed.into_iter().filter(|c| !c.is_control()) {
+                events.send(VersionBrowserEvents::Type(c));
+            }
+        }
+        return;
+    }
+
+    for action in actions {
+        let event = match action {
+            Action::SelectPrevious => VersionBrowserEvents::MoveSelection(-1),
+            Action::SelectNext => VersionBrowserEvents::MoveSelection(1),
+            Action::Confirm => VersionBrowserEvents::CheckoutSelected,
+            Action::Commit => VersionBrowserEvents::StartCommit,
+            Action::Branch => VersionBrowserEvents::StartBranch,
+            Action::MarkVersion => VersionBrowserEvents::MarkSelected,
+            Action::DiffVersion => VersionBrowserEvents::DiffSelected,
+            Action::Cancel => VersionBrowserEvents::ClearDiff,
+            Action::MergeVersion => VersionBrowserEvents::MergeSelected,
+            Action::MarkRegionStart => VersionBrowserEvents::MarkRegionCorner(0),
+            Action::MarkRegionEnd => VersionBrowserEvents::MarkRegionCorner(1),
+            Action::ResolveOurs => VersionBrowserEvents::Resolve {
+                side: MergeSide::Ours,
+                all: false,
+            },
+            Action::ResolveTheirs => VersionBrowserEvents::Resolve {
+                side: MergeSide::Theirs,
+                all: false,
+            },
+            Action::ResolveAllOurs => VersionBrowserEvents::Resolve {
+                side: MergeSide::Ours,
+                all: true,
+            },
+            Action::ResolveAllTheirs => VersionBrowserEvents::Resolve {
+                side: MergeSide::Theirs,
+                all: true,
+            },
+            _ => continue,
+        };
+        events.send(event);
+    }
+}
+
+pub fn version_browser_system(
+    mut commands: Commands,
+    mut current_tool: ResMut<CurrentTool>,
+    editor_db: Res<EditorDb>,
+    mut pending_merge: Option<ResMut<PendingMerge>>,
+    cursor: Res<VoxelCursorRayImpact>,
+    mut events: EventReader<VersionBrowserEvents>,
+    mut version_commands: EventWriter<VersionCommand>,
+    mut diff_commands: EventWriter<DiffCommand>,
+    mut errors: EventWriter<EditorError>,
+    config: Res<Config>,
+) {
+    let state = if let CurrentTool::VersionBrowser(state) = &mut *current_tool {
+        state
+    } else {
+        return;
+    };
+
+    let events: Vec<_> = events.iter().collect();
+    if events.is_empty() {
+        return;
+    }
+    let (versions, current) = match read_versions(&editor_db) {
+        Ok(versions) => versions,
+        Err(e) => {
+            errors.send(e);
+            return;
+        }
+    };
+    let mut versions_changed = false;
+    for event in events.into_iter() {
+        match event {
+            VersionBrowserEvents::MoveSelection(delta) => {
+                state.selected = (state.selected as i32 + delta)
+                    .max(0)
+                    .min(versions.len() as i32 - 1) as usize;
+                versions_changed = true;
+            }
+            VersionBrowserEvents::CheckoutSelected => {
+                if let Some((version, _)) = versions.get(state.selected) {
+                    version_commands.send(VersionCommand::Checkout(*version));
+                }
+            }
+            VersionBrowserEvents::StartCommit => {
+                log::info!(
+                    "Type a message for the commit, then press {}",
+                    config.bindings.chord_names(Action::Confirm)
+                );
+                state.input = Some(VersionInput {
+                    kind: VersionInputKind::CommitMessage,
+                    text: String::new(),
+                });
+            }
+            VersionBrowserEvents::StartBranch => {
+                log::info!(
+                    "Type a name for the branch, then press {}",
+                    config.bindings.chord_names(Action::Confirm)
+                );
+                state.input = Some(VersionInput {
+                    kind: VersionInputKind::BranchName,
+                    text: String::new(),
+                });
+            }
+            VersionBrowserEvents::MarkSelected => {
+                if let Some((version, _)) = versions.get(state.selected) {
+                    log::info!(
+                        "Marked version {} as the base of the next diff or merge",
+                        version
+                    );
+                    state.marked = Some(*version);
+                }
+            }
+            VersionBrowserEvents::DiffSelected => {
+                let base = state.marked.take().or(current);
+                if let (Some(from), Some((to, _))) = (base, versions.get(state.selected)) {
+                    diff_commands.send(DiffCommand::Show { from, to: *to });
+                }
+            }
+            VersionBrowserEvents::ClearDiff => {
+                diff_commands.send(DiffCommand::Clear);
+                if pending_merge.is_some() {
+                    log::info!("Cancelled the merge");
+                    commands.remove_resource::<PendingMerge>();
+                }
+                state.region_corners = [None; 2];
+            }
+            VersionBrowserEvents::MergeSelected => {
+                // Retry the pending merge with the resolutions chosen so far, or start a new one.
+                let command = if let Some(merge) = &pending_merge {
+                    VersionCommand::Merge {
+                        theirs: merge.theirs,
+                        base: Some(merge.base),
+                        resolutions: merge.resolutions.clone(),
+                    }
+                } else if let Some((theirs, _)) = versions.get(state.selected) {
+                    VersionCommand::Merge {
+                        theirs: *theirs,
+                        base: state.marked.take(),
+                        resolutions: MergeResolutions::default(),
+                    }
+                } else {
+                    continue;
+                };
+                version_commands.send(command);
+            }
+            VersionBrowserEvents::MarkRegionCorner(i) => {
+                if let Some(face) = cursor.get_voxel_face() {
+                    log::info!("Region corner {} at {:?}", i + 1, face.point.0);
+                    state.region_corners[*i] = Some(face.point);
+                }
+            }
+            VersionBrowserEvents::Resolve { side, all } => {
+                let merge = if let Some(merge) = &mut pending_merge {
+                    merge
+                } else {
+                    continue;
+                };
+                resolve_conflicts(merge, state, &cursor, *side, *all, &config.bindings);
+            }
+            VersionBrowserEvents::Type(c) => {
+                if let Some(input) = &mut state.input {
+                    input.text.push(*c);
+                    log::info!("> {}", input.text);
+                }
+            }
+            VersionBrowserEvents::DeleteChar => {
+                if let Some(input) = &mut state.input {
+                    input.text.pop();
+                    log::info!("> {}", input.text);
+                }
+            }
+            VersionBrowserEvents::FinishInput => {
+                let input = if let Some(input) = state.input.take() {
+                    input
+                } else {
+                    continue;
+                };
+                if input.text.is_empty() {
+                    continue;
+                }
+                match input.kind {
+                    VersionInputKind::CommitMessage => {
+                        version_commands.send(VersionCommand::Commit {
+                            message: input.text,
+                        });
+                    }
+                    VersionInputKind::BranchName => {
+                        if let Some((version, _)) = versions.get(state.selected) {
+                            version_commands.send(VersionCommand::Branch {
+                                name: input.text,
+                                from: *version,
+                            });
+                        }
+                    }
+                }
+            }
+            VersionBrowserEvents::CancelInput => {
+                state.input = None;
+            }
+        }
+    }
+
+    if versions_changed {
+        log_versions(&versions, current, state.selected, &config.bindings);
+    }
+}
+
+/// Prints the versions and returns the browser tool, with the current version selected.
+pub fn open_version_browser(
+    editor_db: &EditorDb,
+    bindings: &KeyBindings,
+) -> EditorResult<CurrentTool> {
+    let (versions, current) = read_versions(editor_db)?;
+    let selected = versions
+        .iter()
+        .position(|(v, _)| Some(*v) == current)
+        .unwrap_or(0);
+    log_versions(&versions, current, selected, bindings);
+
+    Ok(CurrentTool::VersionBrowser(VersionBrowserState {
+        selected,
+        input: None,
+        marked: None,
+        region_corners: [None; 2],
+    }))
+}
+
+/// Chooses a side for every conflict, for the region between the marked corners, or for the
+/// conflicting chunk under the cursor, in that order of preference.
+fn resolve_conflicts(
+    merge: &mut PendingMerge,
+    state: &mut VersionBrowserState,
+    cursor: &VoxelCursorRayImpact,
+    side: MergeSide,
+    all: bool,
+    bindings: &KeyBindings,
+) {
+    if all {
+        for conflict in merge.conflicts.iter() {
+            merge.resolutions.chunks.insert(conflict.key, side);
+        }
+        log::info!("Resolved all conflicts with {:?}", side);
+    } else if let [Some(a), Some(b)] = state.region_corners {
+        let min = PointN([a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())]);
+        let max = PointN([a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())]);
+        let region = Extent3i::from_min_and_max(min, max);
+        merge.resolutions.regions.push((region, side));
+        state.region_corners = [None; 2];
+        log::info!(
+            "Resolved conflicts from {:?} to {:?} with {:?}",
+            region.minimum.0,
+            region.max().0,
+            side
+        );
+    } else if let Some(conflict) = cursor.get_voxel_face().and_then(|face| {
+        merge
+            .conflicts
+            .iter()
+            .find(|c| c.extent.contains(face.point))
+    }) {
+        merge.resolutions.chunks.insert(conflict.key, side);
+        log::info!(
+            "Resolved chunk at {:?} with {:?}",
+            conflict.key.minimum.0,
+            side
+        );
+    } else {
+        log::warn!(
+            "Point the cursor at a conflicting chunk, or mark a region with {} and {}",
+            bindings.chord_names(Action::MarkRegionStart),
+            bindings.chord_names(Action::MarkRegionEnd)
+        );
+        return;
+    }
+    log::info!(
+        "Press {} to finish the merge",
+        bindings.chord_names(Action::MergeVersion)
+    );
+}
+
+/// Every version, and the current one.
+fn read_versions(editor_db: &EditorDb) -> EditorResult<(Vec<(u64, VersionInfo)>, Option<u64>)> {
+    Ok((list_versions(editor_db)?, editor_db.current_version()?))
+}
+
+fn log_versions(
+    versions: &[(u64, VersionInfo)],
+    current: Option<u64>,
+    selected: usize,
+    bindings: &KeyBindings,
+) {
+    log::info!(
+        "World versions ({})",
+        bindings.help(&[
+            (&[Action::SelectPrevious, Action::SelectNext], "select"),
+            (&[Action::Confirm], "check out"),
+            (&[Action::Commit], "commit current"),
+            (&[Action::Branch], "branch from selected"),
+            (&[Action::MarkVersion], "mark diff/merge base"),
+            (&[Action::DiffVersion], "diff against selected"),
+            (&[Action::MergeVersion], "merge selected into current"),
+            (&[Action::Cancel], "clear diff and cancel merge"),
+        ])
+    );
+    for (i, (version, info)) in versions.iter().enumerate() {
+        let selected_marker = if i == selected { '>' } else { ' ' };
+        let current_marker = if Some(*version) == current { '*' } else { ' ' };
+        let parent = match (info.parent, info.merged) {
+            (Some(p), Some(m)) => format!(" (after {}, merged {})", p, m),
+            (Some(p), None) => format!(" (after {})", p),
+            _ => String::new(),
+        };
+        log::info!(
+            "{}{} {} [{}]{}: {}",
+            selected_marker,
+            current_marker,
+            version,
+            info.branch,
+            parent,
+            info.message.as_deref().unwrap_or("<uncommitted>"),
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use crate::Config;
+
+use feldspar::bb::storage::database::sled;
+
+use bevy::{ecs::prelude::*, window::Windows};
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong in the editor without it being a bug.
+///
+/// Systems that can recover from an error send it as an event instead of panicking, and
+/// `report_editor_errors` shows it to the user.
+#[derive(Debug)]
+pub enum EditorError {
+    /// The config file couldn't be opened, usually because it doesn't exist.
+    ReadConfig { path: String, error: io::Error },
+    /// The config file couldn't be parsed.
+    Config { path: String, error: ron::Error },
+    /// The world database couldn't be opened, e.g. because another editor has it locked.
+    OpenDatabase { path: String, error: sled::Error },
+    /// A read or write failed, e.g. because the disk is full.
+    Database(sled::Error),
+    /// The chunk database or its snapshot forest failed.
+    ChunkDatabase(String),
+    /// A record couldn't be encoded or decoded.
+    Encoding(bincode::Error),
+    /// Something in the database doesn't have the shape we wrote it with.
+    Corrupt(String),
+    /// A background save failed. The edits stay in memory and are saved again next time.
+    SaveFailed(String),
+    /// A world archive couldn't be exported or imported, e.g. because it's from a newer editor.
+    Archive(String),
+    /// The template for a new world couldn't be used, e.g. because its heightmap is missing.
+    WorldTemplate(String),
+}
+
+pub type EditorResult<T> = Result<T, EditorError>;
+
+impl EditorError {
+    /// For errors from the chunk database, which don't share an error type.
+    pub fn chunk_db(error: impl fmt::Debug) -> Self {
+        EditorError::ChunkDatabase(format!("{:?}", error))
+    }
+}
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditorError::ReadConfig { path, error } if error.kind() == io::ErrorKind::NotFound => {
+                let dir = std::env::current_dir()
+                    .map(|d| d.display().to_string())
+                    .unwrap_or_else(|_| "?".to_string());
+                write!(
+                    f,
+                    "There's no config file at {} (relative to {}), pass one with --config",
+                    path, dir
+                )
+            }
+            EditorError::ReadConfig { path, error } => {
+                write!(f, "Failed to open config file {}: {}", path, error)
+            }
+            EditorError::Config { path, error } => {
+                write!(f, "Failed to parse config file {}: {}", path, error)
+            }
+            EditorError::OpenDatabase { path, error } => {
+                write!(f, "Failed to open world database {}: {}", path, error)
+            }
+            EditorError::Database(e) => write!(f, "Database error: {}", e),
+            EditorError::ChunkDatabase(e) => write!(f, "Chunk database error: {}", e),
+            EditorError::Encoding(e) => write!(f, "Failed to encode or decode a record: {}", e),
+            EditorError::Corrupt(what) => write!(f, "The database is corrupt: {}", what),
+            EditorError::SaveFailed(e) => {
+                write!(f, "Save failed, the edits will be saved next time: {}", e)
+            }
+            EditorError::Archive(e) => write!(f, "World archive error: {}", e),
+            EditorError::WorldTemplate(e) => write!(f, "Failed to create the new world: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EditorError {}
+
+impl From<sled::Error> for EditorError {
+    fn from(e: sled::Error) -> Self {
+        EditorError::Database(e)
+    }
+}
+
+impl From<bincode::Error> for EditorError {
+    fn from(e: bincode::Error) -> Self {
+        EditorError::Encoding(e)
+    }
+}
+
+/// Logs every error, and shows the most recent one in the window title.
+pub fn report_editor_errors(
+    mut errors: EventReader<EditorError>,
+    mut windows: ResMut<Windows>,
+    config: Res<Config>,
+) {
+    let mut last_error = None;
+    for error in errors.iter() {
+        log::error!("{}", error);
+        last_error = Some(error);
+    }
+
+    if let (Some(error), Some(window)) = (last_error, windows.get_primary_mut()) {
+        window.set_title(format!("{} - {}", config.window.title, error));
+    }
+}
+
+/// The default for `WindowConfig::title`.
+pub const WINDOW_TITLE: &str = "Feldspar Editor";
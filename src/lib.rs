@@ -1,20 +1,46 @@
+mod actions;
+mod bindings;
 mod camera;
+mod cli;
 mod config;
 mod cursor_tracker;
 mod database;
+mod diff_view;
 mod edit_tools;
+mod error;
 mod geometry;
 mod immediate_mode;
 mod picking;
 mod plugin;
 
-use camera::{create_camera_entity, CameraPlugin, CursorRay};
+use actions::{ActionInput, ActionsPlugin};
+use camera::{create_camera_entity, CameraPlugin, CursorRay, CursorRayCameraTag};
 use cursor_tracker::{CursorPosition, CursorPositionPlugin};
-use database::{open_voxel_database, save_map_to_db};
-use edit_tools::EditToolsPlugin;
+use database::{
+    autosave_map, close_editor_session, create_new_world, list_versions, open_voxel_database,
+    replay_edit_journal, report_save_progress, restore_editor_session, save_editor_session,
+    save_map_to_db, stream_chunks, version_command_system, version_diff_system, write_edit_journal,
+    ChunkChange, DiffCommand, EditorDb, MergeResolutions, MergeSide, PendingMerge, VersionCommand,
+    VersionDiff,
+};
+use diff_view::DiffViewPlugin;
+use edit_tools::{
+    decode_chunk, encode_chunk, CurrentTool, EditTimeline, EditToolsPlugin, HistoryChanges,
+    SelectionState, StreamedMap, Terraformer, ToolSession,
+};
+use error::{report_editor_errors, WINDOW_TITLE};
 use immediate_mode::{ImmediateModePlugin, ImmediateModeTag};
 use picking::{VoxelCursor, VoxelCursorRayImpact, VoxelPickingPlugin};
 use plugin::EditorState;
 
+pub use actions::{Action, ActionInfo, ActionQueue, ActionScope, CommandPalette};
+pub use bindings::{Chord, KeyBindings, Modifiers, Trigger};
+pub use cli::{CameraType, EditorArgs, EDITOR_USAGE};
 pub use config::*;
+pub use database::{
+    benchmark_storage, collect_garbage, compact_world_db, export_world_archive,
+    import_world_archive, ArchiveCompression, ArchiveHeader, GcReport, IntegrityReport, LodStats,
+    RetentionPolicy, StorageBenchmark, VersionInfo, WorldDb,
+};
+pub use error::{EditorError, EditorResult};
 pub use plugin::EditorPlugin;
@@ -1,14 +1,14 @@
 use crate::{
-    create_camera_entity, open_voxel_database, save_map_to_db, BevyConfig, CameraConfig,
-    CameraPlugin, Config, CursorPositionPlugin, EditToolsPlugin, ImmediateModePlugin,
-    VoxelPickingPlugin,
+    autosave_map, close_editor_session, create_camera_entity, create_new_world,
+    open_voxel_database, reload_config, replay_edit_journal, report_editor_errors,
+    report_save_progress, restore_editor_session, save_editor_session, save_map_to_db,
+    stream_chunks, version_command_system, version_diff_system, write_edit_journal, ActionsPlugin,
+    BevyConfig, CameraConfig, CameraPlugin, Config, ConfigWatcher, CursorPositionPlugin,
+    DiffCommand, DiffViewPlugin, EditToolsPlugin, EditorArgs, EditorError, ImmediateModePlugin,
+    LightConfig, VersionCommand, VoxelPickingPlugin,
 };
 
-use feldspar::{
-    bb::core::prelude::*,
-    bb::storage::prelude::*,
-    prelude::{VoxelEditor, VoxelRenderAssets, VoxelType, VoxelWorldPlugin},
-};
+use feldspar::prelude::{VoxelRenderAssets, VoxelWorldPlugin};
 
 use bevy::{
     app::{prelude::*, PluginGroupBuilder},
@@ -56,11 +56,21 @@ impl PluginGroup for BevyPlugins {
 
 pub struct EditorPlugin {
     config: Config,
+    watched_config: Option<EditorArgs>,
 }
 
 impl EditorPlugin {
     pub fn new(config: Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            watched_config: None,
+        }
+    }
+
+    /// Reloads `args.config_path` while the editor runs, with `args` applied on top.
+    pub fn watch_config(mut self, args: EditorArgs) -> Self {
+        self.watched_config = Some(args);
+        self
     }
 }
 
@@ -69,13 +79,15 @@ impl Plugin for EditorPlugin {
         app
             // Bevy stuff.
             .insert_resource(WindowDescriptor {
-                width: 1600.0,
-                height: 900.0,
-                title: "Feldspar Editor".to_string(),
+                width: self.config.window.width,
+                height: self.config.window.height,
+                title: self.config.window.title.clone(),
                 ..Default::default()
             })
-            .insert_resource(ClearColor(Color::rgb(0.2, 0.2, 0.4)))
-            .insert_resource(Msaa { samples: 4 })
+            .insert_resource(self.config.bevy.clear_color())
+            .insert_resource(Msaa {
+                samples: self.config.bevy.msaa_samples,
+            })
             .insert_resource(WgpuOptions {
                 features: WgpuFeatures {
                     // The Wireframe plugin requires NonFillPolygonMode feature
@@ -98,6 +110,13 @@ impl Plugin for EditorPlugin {
             .add_plugin(CameraPlugin)
             .add_plugin(VoxelPickingPlugin)
             .add_plugin(EditToolsPlugin::new(self.config.feldspar.map.chunk_shape()))
+            .add_plugin(DiffViewPlugin)
+            .add_plugin(ActionsPlugin)
+            .add_event::<VersionCommand>()
+            .add_event::<DiffCommand>()
+            .add_event::<EditorError>()
+            .add_system(report_editor_errors.system())
+            .add_system(reload_config.system())
             .add_state(EditorState::Loading)
             // Load assets.
             .add_system_set(
@@ -117,8 +136,28 @@ impl Plugin for EditorPlugin {
             // Save the map to our database
             // TODO: this should happen in veldspar proper as edits are made
             .add_system_set(
-                SystemSet::on_update(EditorState::Editing).with_system(save_map_to_db.system()),
-            );
+                SystemSet::on_update(EditorState::Editing)
+                    .with_system(save_map_to_db.system())
+                    .with_system(autosave_map.system())
+                    .with_system(report_save_progress.system())
+                    .with_system(replay_edit_journal.system())
+                    .with_system(write_edit_journal.system())
+                    .with_system(create_new_world.system())
+                    .with_system(restore_editor_session.system())
+                    .with_system(version_command_system.system().label("change_version"))
+                    .with_system(version_diff_system.system())
+                    // Streams from the version that was just checked out.
+                    .with_system(stream_chunks.system().after("change_version")),
+            )
+            .add_system_to_stage(CoreStage::Last, save_editor_session.system())
+            .add_system_to_stage(CoreStage::Last, close_editor_session.system());
+
+        if let Some(args) = &self.watched_config {
+            app.insert_resource(ConfigWatcher::new(
+                args.clone(),
+                self.config.bevy.wireframes,
+            ));
+        }
     }
 }
 
@@ -152,33 +191,20 @@ fn wait_for_assets_loaded(
     }
 }
 
-fn initialize_editor(mut commands: Commands, mut voxel_editor: VoxelEditor, config: Res<Config>) {
-    // TODO: remove this once we can create voxels out of thin air
-    log::info!("Initializing voxels");
-    let write_extent = Extent3i::from_min_and_shape(PointN([0, 0, 0]), PointN([64, 2, 64]));
-    voxel_editor.edit_extent_and_touch_neighbors(write_extent, |_p, (voxel_type, dist)| {
-        *voxel_type = VoxelType(2);
-        *dist = Sd8::from(-10.0);
-    });
-
-    create_lights(&mut commands);
+fn initialize_editor(mut commands: Commands, config: Res<Config>) {
+    create_lights(&mut commands, &config.lights);
     initialize_camera(&mut commands, config.camera);
 }
 
-fn create_lights(commands: &mut Commands) {
-    for p in [
-        Vec3::new(-100.0, 100.0, -100.0),
-        Vec3::new(-100.0, 100.0, 100.0),
-        Vec3::new(100.0, 100.0, -100.0),
-        Vec3::new(100.0, 100.0, 100.0),
-    ]
-    .iter()
-    {
+fn create_lights(commands: &mut Commands, lights: &[LightConfig]) {
+    for light in lights.iter() {
+        let [r, g, b] = light.color;
         commands.spawn_bundle(LightBundle {
-            transform: Transform::from_translation(*p),
+            transform: Transform::from_translation(Vec3::from(light.position)),
             light: Light {
-                intensity: 40000.0,
-                range: 800.0,
+                color: Color::rgb(r, g, b),
+                intensity: light.intensity,
+                range: light.range,
                 ..Default::default()
             },
             ..Default::default()